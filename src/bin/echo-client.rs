@@ -1,9 +1,49 @@
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use std::path::Path;
+
+use my_redis_project::tls;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 
+/// 例如：`cargo run --bin echo-client -- --addr 127.0.0.1:6142 --tls-ca ca.pem --tls-name localhost`
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let socket = TcpStream::connect("127.0.0.1:6142").await?;
+    let mut addr = "127.0.0.1:6142".to_string();
+    let mut ca = None;
+    let mut name = "localhost".to_string();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("missing value for `{}`", arg)))?;
+        match arg.as_str() {
+            "--addr" => addr = value,
+            "--tls-ca" => ca = Some(value),
+            "--tls-name" => name = value,
+            _ => {
+                let msg = format!("unknown argument `{}`", arg);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        }
+    }
+
+    let socket = TcpStream::connect(&addr).await?;
+    match ca {
+        // 服务端开了 TLS 时用 `--tls-ca` 指定签发它证书的 CA，`--tls-name` 要和证书里的名字一致
+        Some(ca) => {
+            let connector = tls::connector(Path::new(&ca))?;
+            let socket = connector.connect(tls::server_name(&name)?, socket).await?;
+            echo(socket).await
+        }
+        None => echo(socket).await,
+    }
+}
+
+/// 发两行数据，打印收到的回显，明文 TCP 和 TLS 上的都一样
+async fn echo<S>(socket: S) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
     let (mut rd, mut wr) = io::split(socket);
 
     // 创建异步任务，在后台写入数据
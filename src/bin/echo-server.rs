@@ -1,34 +1,270 @@
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use my_redis_project::tls;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpListener;
+use tokio::time;
+
+/// 怎么回显
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// 读到多少字节就原样写回多少
+    Raw,
+    /// 按 `\r\n` 分行，凑齐一整行才写回，半行留到下次
+    Line,
+}
+
+/// 启动参数
+///
+/// 例如：`cargo run --bin echo-server -- --mode line --max-line 256 --idle-timeout 30`
+/// 或者走 TLS：`cargo run --bin echo-server -- --tls-cert server.pem --tls-key server.key`
+#[derive(Debug, Clone)]
+struct Config {
+    addr: String,
+    mode: Mode,
+    /// 行模式下一行最多多长（不含 `\r\n`），超出时断开连接，免得对端一直不发换行把内存撑爆
+    max_line: usize,
+    /// 这么久没收到数据、或者写出的数据这么久都没被对端读走就断开，`None` 表示不限制
+    idle_timeout: Option<Duration>,
+    /// PEM 格式的证书链和私钥，都给出时先完成 TLS 握手再回显
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+}
+
+/// 连接为什么关闭
+#[derive(Debug)]
+enum Close {
+    /// 对端关闭了连接
+    Eof,
+    Idle,
+    LineTooLong,
+    Error(io::Error),
+}
+
+/// 每个连接读写了多少字节
+#[derive(Debug, Default)]
+struct Counters {
+    read: u64,
+    written: u64,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mode, String> {
+        match s {
+            "raw" => Ok(Mode::Raw),
+            "line" => Ok(Mode::Line),
+            _ => Err(format!("unknown mode `{}`, expected raw or line", s)),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            addr: "127.0.0.1:6142".to_string(),
+            mode: Mode::Raw,
+            max_line: 8 * 1024,
+            idle_timeout: Some(Duration::from_secs(60)),
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+impl Config {
+    fn from_args() -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for `{}`", arg));
+
+            match arg.as_str() {
+                "--addr" => config.addr = value()?,
+                "--mode" => config.mode = value()?.parse()?,
+                "--max-line" => {
+                    config.max_line = parse_number(&arg, &value()?)?;
+                    if config.max_line == 0 {
+                        return Err("`--max-line` must be greater than zero".to_string());
+                    }
+                }
+                // 秒，0 表示不限制
+                "--idle-timeout" => {
+                    let secs = parse_number(&arg, &value()?)?;
+                    config.idle_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                "--tls-cert" => config.tls_cert = Some(value()?.into()),
+                "--tls-key" => config.tls_key = Some(value()?.into()),
+                _ => return Err(format!("unknown argument `{}`", arg)),
+            }
+        }
+
+        if config.tls_cert.is_some() != config.tls_key.is_some() {
+            return Err("`--tls-cert` and `--tls-key` must be given together".to_string());
+        }
+        Ok(config)
+    }
+}
+
+fn parse_number<T: FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}` for `{}`", value, arg))
+}
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:6142").await?;
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(cert, key)?),
+        _ => None,
+    };
+
+    let listener = TcpListener::bind(&config.addr).await?;
+    let tls_note = if tls.is_some() { ", TLS" } else { "" };
+    println!("Listening on {} ({:?} mode{})", listener.local_addr()?, config.mode, tls_note);
 
     loop {
-        let (mut socket, _) = listener.accept().await?;
+        let (socket, peer) = listener.accept().await?;
+        let config = config.clone();
+        let tls = tls.clone();
 
         tokio::spawn(async move {
-            let mut buf = vec![0; 1024];
-
-            loop {
-                match socket.read(&mut buf).await {
-                    // 返回值 `Ok(0)` 说明对端已经关闭
-                    Ok(0) => return,
-                    Ok(n) => {
-                        // Copy the data back to socket
-                        // 将数据拷贝回 socket 中
-                        if socket.write_all(&buf[..n]).await.is_err() {
-                            // 非预期错误，由于我们这里无需再做什么，因此直接停止处理
-                            return;
-                        }
-                    }
-                    Err(_) => {
-                      // 非预期错误，由于我们无需再做什么，因此直接停止处理
-                        return;
+            let mut counters = Counters::default();
+            let close = match tls {
+                // 握手也受空闲超时的限制，只连不握手的客户端不会一直占着连接
+                Some(acceptor) => match with_idle_timeout(&config, acceptor.accept(socket)).await {
+                    Ok(Ok(socket)) => serve(socket, &config, &mut counters).await,
+                    Ok(Err(e)) => Close::Error(e),
+                    Err(close) => close,
+                },
+                None => serve(socket, &config, &mut counters).await,
+            };
+            log_close(peer, &close, &counters);
+        });
+    }
+}
+
+/// 超过 `--idle-timeout` 还没完成就返回 `Close::Idle`
+async fn with_idle_timeout<F: Future>(config: &Config, future: F) -> Result<F::Output, Close> {
+    match config.idle_timeout {
+        Some(idle) => time::timeout(idle, future).await.map_err(|_| Close::Idle),
+        None => Ok(future.await),
+    }
+}
+
+/// 处理一个连接直到它该关闭，返回关闭的原因，明文 TCP 和 TLS 上的都一样
+async fn serve<S>(mut socket: S, config: &Config, counters: &mut Counters) -> Close
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; 1024];
+    // 行模式下还没凑齐一行的数据
+    let mut pending = Vec::new();
+
+    loop {
+        let res = match with_idle_timeout(config, socket.read(&mut buf)).await {
+            Ok(res) => res,
+            Err(close) => return close,
+        };
+
+        let n = match res {
+            // 返回值 `Ok(0)` 说明对端已经关闭，我们这边也关掉，TLS 上会发出 close_notify
+            Ok(0) => {
+                let _ = with_idle_timeout(config, socket.shutdown()).await;
+                return Close::Eof;
+            }
+            Ok(n) => n,
+            // 非预期错误，由于我们无需再做什么，因此直接停止处理
+            Err(e) => return Close::Error(e),
+        };
+        counters.read += n as u64;
+
+        let res = match config.mode {
+            Mode::Raw => write(&mut socket, &buf[..n], config, counters).await,
+            Mode::Line => {
+                pending.extend_from_slice(&buf[..n]);
+                match complete_lines(&pending, config.max_line) {
+                    Some(end) => {
+                        let res = write(&mut socket, &pending[..end], config, counters).await;
+                        pending.drain(..end);
+                        res
                     }
+                    None => return Close::LineTooLong,
                 }
             }
-        });
+        };
+        if let Err(close) = res {
+            return close;
+        }
+    }
+}
+
+/// `buf` 开头的完整行一共多长（含 `\r\n`），有一行超过 `max_line` 时返回 `None`
+fn complete_lines(buf: &[u8], max_line: usize) -> Option<usize> {
+    let mut end = 0;
+    while let Some(i) = buf[end..].windows(2).position(|w| w == b"\r\n") {
+        if i > max_line {
+            return None;
+        }
+        end += i + 2;
+    }
+
+    // 还没收到 `\r\n` 的半行也不能超长，末尾的 `\r` 可能是换行的一半，不算在内
+    let tail = &buf[end..];
+    let len = tail.len() - usize::from(tail.ends_with(b"\r"));
+    (len <= max_line).then_some(end)
+}
+
+/// 把数据拷贝回 socket 中
+///
+/// 对端一直不读的话发送缓冲区会满，`write_all` 就一直等下去，所以写也要受空闲超时的限制
+async fn write<S: AsyncWrite + Unpin>(
+    socket: &mut S,
+    data: &[u8],
+    config: &Config,
+    counters: &mut Counters,
+) -> Result<(), Close> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    // TLS 会把数据先攒在会话里，要 flush 才一定发出去；明文 TCP 上 flush 什么都不做
+    let write = async {
+        socket.write_all(data).await?;
+        socket.flush().await
+    };
+    with_idle_timeout(config, write).await?.map_err(Close::Error)?;
+    counters.written += data.len() as u64;
+    Ok(())
+}
+
+fn log_close(peer: SocketAddr, close: &Close, counters: &Counters) {
+    println!(
+        "{} closed ({}): read {} bytes, wrote {} bytes",
+        peer, close, counters.read, counters.written
+    );
+}
+
+impl fmt::Display for Close {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Close::Eof => "eof".fmt(f),
+            Close::Idle => "idle timeout".fmt(f),
+            Close::LineTooLong => "line too long".fmt(f),
+            Close::Error(e) => write!(f, "error: {}", e),
+        }
     }
-}
\ No newline at end of file
+}
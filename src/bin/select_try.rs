@@ -1,36 +1,11 @@
-use std::{pin::Pin, task::{Context, Poll}};
+use my_redis_project::select::select_all;
 use tokio::sync::oneshot;
-use std::future::Future;  // 需要添加这个导入
 
 async fn some_operation() -> &'static str {
     // 在这里执行一些操作...
     "operation result"  // 返回静态字符串字面量，不再创建String
 }
 
-struct MySelect {
-    rx1: oneshot::Receiver<&'static str>,  // 恢复原来的类型
-    rx2: oneshot::Receiver<&'static str>,
-}
-
-impl Future for MySelect {
-    type Output = ();
-
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
-        if let Poll::Ready(val) = Pin::new(&mut self.rx1).poll(cx) {
-            println!("rx1 completed first with {:?}", val);
-            return Poll::Ready(());
-        }
-
-        if let Poll::Ready(val) = Pin::new(&mut self.rx2).poll(cx) {
-            println!("rx2 completed first with {:?}", val);
-            return Poll::Ready(());
-        }
-
-        Poll::Pending
-    }
-}
-
-
 #[tokio::main]
 async fn main() {
     let (mut tx1, rx1) = oneshot::channel();
@@ -66,10 +41,10 @@ async fn main() {
     //     }
     // }
 
-    MySelect {
-        rx1,
-        rx2,
-    }.await;
+    // 原来手写的 MySelect 只能等两个 Receiver，现在交给 select_all，
+    // 返回先完成的下标和结果，另一个 Receiver 随之被丢弃
+    let (i, val) = select_all([rx1, rx2]).await;
+    println!("rx{} completed first with {:?}", i + 1, val);
 
     // 任何一个 select 分支结束后，都会继续执行接下来的代码
 }
@@ -0,0 +1,590 @@
+//! AUTH 和 ACL 用户
+//!
+//! 和 Redis 一样，每个用户有一组规则：是否启用、密码、允许执行的命令、允许访问的 key。
+//! 规则的写法也和 Redis 相同，例如 `ACL SETUSER alice on >secret ~cache:* +@read`，
+//! 服务端启动时可以用 `--user "alice on >secret ~cache:* +@read"` 预先配置。
+//!
+//! 密码只保存 SHA-256，`ACL LIST` 里显示的也是哈希值。
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+use crate::cmd::Command;
+
+/// 命令名和它所属的类别，子命令写成 `acl|list` 的形式，和 Redis 7 一致
+const COMMANDS: &[(&str, &[&str])] = &[
+    ("get", &["read", "string"]),
+    ("set", &["write", "string"]),
+    ("publish", &["pubsub"]),
+    ("subscribe", &["pubsub"]),
+    ("unsubscribe", &["pubsub"]),
+    ("ping", &["connection"]),
+    ("hello", &["connection"]),
+    ("auth", &["connection"]),
+    ("hset", &["write", "hash"]),
+    ("hget", &["read", "hash"]),
+    ("hgetall", &["read", "hash"]),
+    ("hdel", &["write", "hash"]),
+    ("xadd", &["write", "stream"]),
+    ("xrange", &["read", "stream"]),
+    ("xrevrange", &["read", "stream"]),
+    ("xlen", &["read", "stream"]),
+    ("xtrim", &["write", "stream"]),
+    ("xread", &["read", "stream"]),
+    ("xgroup|create", &["write", "stream"]),
+    ("xgroup|destroy", &["write", "stream"]),
+    ("xreadgroup", &["write", "stream"]),
+    ("xack", &["write", "stream"]),
+    ("xpending", &["read", "stream"]),
+    ("xclaim", &["write", "stream"]),
+    ("acl|whoami", &["connection"]),
+    ("acl|list", &["admin"]),
+    ("acl|setuser", &["admin"]),
+    ("select", &["connection"]),
+    ("move", &["write", "keyspace"]),
+    ("swapdb", &["write", "keyspace", "dangerous"]),
+    ("flushdb", &["write", "keyspace", "dangerous"]),
+    ("info", &["dangerous"]),
+    ("client|id", &["connection"]),
+    ("client|setname", &["connection"]),
+    ("client|getname", &["connection"]),
+    ("client|list", &["admin", "connection", "dangerous"]),
+    ("client|kill", &["admin", "connection", "dangerous"]),
+    ("monitor", &["admin", "dangerous"]),
+    ("slowlog|get", &["admin", "dangerous"]),
+    ("slowlog|len", &["admin", "dangerous"]),
+    ("slowlog|reset", &["admin", "dangerous"]),
+];
+
+/// 所有连接共享的用户表
+///
+/// 和 `Db` 一样内部是 `Arc`，clone 之后指向同一份数据。连接只记住自己登录的用户名，
+/// 每条命令都重新查一次权限，所以 `ACL SETUSER` 改了规则之后对已经登录的连接也立即生效。
+#[derive(Clone)]
+pub struct Acl {
+    users: Arc<Mutex<BTreeMap<String, User>>>,
+}
+
+#[derive(Clone, Default)]
+struct User {
+    enabled: bool,
+    /// 任何密码都能登录
+    nopass: bool,
+    /// 密码的 SHA-256，十六进制
+    passwords: BTreeSet<String>,
+    commands: BTreeSet<&'static str>,
+    /// 允许访问的 key 的 glob 模式
+    keys: Vec<String>,
+}
+
+impl Acl {
+    /// 只有一个 `default` 用户，不需要密码、可以执行所有命令，也就是不开启认证
+    pub fn new() -> Acl {
+        let acl = Acl {
+            users: Arc::new(Mutex::new(BTreeMap::new())),
+        };
+        let rules = ["on", "nopass", "~*", "+@all"].map(String::from);
+        acl.set_user("default", &rules).unwrap();
+        acl
+    }
+
+    /// ACL SETUSER：用户不存在时新建（初始为禁用、没有任何权限），然后按顺序应用 `rules`
+    ///
+    /// 有一条规则不合法时整个命令都不生效。
+    pub fn set_user(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut users = self.users.lock().unwrap();
+        let mut user = users.get(name).cloned().unwrap_or_default();
+        for rule in rules {
+            user.apply(rule)
+                .map_err(|e| format!("ERR Error in ACL SETUSER modifier '{}': {}", rule, e))?;
+        }
+        users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// 新连接自动登录的用户：`default` 启用且不需要密码时就是它，否则需要先 AUTH
+    pub fn default_user(&self) -> Option<String> {
+        let users = self.users.lock().unwrap();
+        let user = users.get("default")?;
+        (user.enabled && user.nopass).then(|| "default".to_string())
+    }
+
+    /// AUTH，成功时返回登录的用户名；不指定用户名时登录 `default`
+    pub fn authenticate(&self, username: Option<&str>, password: &str) -> Result<String, String> {
+        let users = self.users.lock().unwrap();
+        let name = username.unwrap_or("default");
+        let user = users.get(name);
+
+        if username.is_none() && user.is_some_and(|u| u.nopass) {
+            return Err(
+                "ERR AUTH <password> called without any password configured for the default user. \
+                        Are you sure your configuration is correct?"
+                    .to_string(),
+            );
+        }
+        match user {
+            Some(user)
+                if user.enabled && (user.nopass || user.passwords.contains(&hash(password))) =>
+            {
+                Ok(name.to_string())
+            }
+            _ => Err("WRONGPASS invalid username-password pair or user is disabled.".to_string()),
+        }
+    }
+
+    /// 检查 `user` 能不能执行 `command`，没登录时只允许 AUTH 和带 AUTH 选项的 HELLO
+    pub fn check(&self, user: Option<&str>, command: &Command) -> Result<(), String> {
+        if let Command::Auth { .. } | Command::Hello { auth: Some(_), .. } = command {
+            return Ok(());
+        }
+        let Some(name) = user else {
+            return Err("NOAUTH Authentication required.".to_string());
+        };
+
+        let users = self.users.lock().unwrap();
+        let Some(user) = users.get(name) else {
+            return Err("NOAUTH Authentication required.".to_string());
+        };
+        if !user.commands.contains(command.name()) {
+            return Err(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                name,
+                command.name()
+            ));
+        }
+        let allowed = |key: &str| {
+            user.keys
+                .iter()
+                .any(|pattern| glob_match(pattern.as_bytes(), key.as_bytes()))
+        };
+        if !command.keys().into_iter().all(allowed) {
+            return Err("NOPERM No permissions to access a key".to_string());
+        }
+        Ok(())
+    }
+
+    /// ACL LIST：每个用户一行，格式和 `ACL SETUSER` 的规则相同
+    pub fn list(&self) -> Vec<String> {
+        let users = self.users.lock().unwrap();
+        users
+            .iter()
+            .map(|(name, user)| user.describe(name))
+            .collect()
+    }
+}
+
+impl User {
+    fn apply(&mut self, rule: &str) -> Result<(), &'static str> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".to_string()],
+            "resetkeys" => self.keys.clear(),
+            "allcommands" => self.allow("@all", true)?,
+            "nocommands" => self.allow("@all", false)?,
+            "reset" => *self = User::default(),
+            _ => match rule.split_at(rule.chars().next().map_or(0, char::len_utf8)) {
+                (">", password) => {
+                    self.nopass = false;
+                    self.passwords.insert(hash(password));
+                }
+                ("<", password) => {
+                    self.passwords.remove(&hash(password));
+                }
+                ("#", digest) if is_sha256(digest) => {
+                    self.nopass = false;
+                    self.passwords.insert(digest.to_lowercase());
+                }
+                ("!", digest) if is_sha256(digest) => {
+                    self.passwords.remove(&digest.to_lowercase());
+                }
+                ("#" | "!", _) => {
+                    return Err(
+                        "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters",
+                    );
+                }
+                ("~", pattern) => self.keys.push(pattern.to_string()),
+                ("+", name) => self.allow(name, true)?,
+                ("-", name) => self.allow(name, false)?,
+                _ => return Err("Syntax error"),
+            },
+        }
+        Ok(())
+    }
+
+    /// `+@read`、`-get`、`+acl|list` 之类：`name` 是 `@类别`、命令或者子命令，命令包括它的所有子命令
+    fn allow(&mut self, name: &str, allow: bool) -> Result<(), &'static str> {
+        let name = name.to_lowercase();
+        let matched: Vec<&'static str> = match name.strip_prefix('@') {
+            Some("all") => COMMANDS.iter().map(|(command, _)| *command).collect(),
+            Some(category) => COMMANDS
+                .iter()
+                .filter(|(_, categories)| categories.contains(&category))
+                .map(|(command, _)| *command)
+                .collect(),
+            None => COMMANDS
+                .iter()
+                .map(|(command, _)| *command)
+                .filter(|command| {
+                    *command == name
+                        || command
+                            .strip_prefix(name.as_str())
+                            .is_some_and(|s| s.starts_with('|'))
+                })
+                .collect(),
+        };
+        if matched.is_empty() {
+            return Err(if name.starts_with('@') {
+                "Unknown command category"
+            } else {
+                "Unknown command"
+            });
+        }
+
+        for command in matched {
+            if allow {
+                self.commands.insert(command);
+            } else {
+                self.commands.remove(command);
+            }
+        }
+        Ok(())
+    }
+
+    /// 把用户描述成一组规则，例如 `user default on nopass ~* +@all`
+    fn describe(&self, name: &str) -> String {
+        let mut out = format!("user {} {}", name, if self.enabled { "on" } else { "off" });
+        if self.nopass {
+            out.push_str(" nopass");
+        }
+        for password in &self.passwords {
+            write!(out, " #{}", password).unwrap();
+        }
+        if self.keys.is_empty() {
+            out.push_str(" resetkeys");
+        }
+        for pattern in &self.keys {
+            write!(out, " ~{}", pattern).unwrap();
+        }
+
+        if self.commands.len() == COMMANDS.len() {
+            out.push_str(" +@all");
+        } else {
+            out.push_str(" -@all");
+            for command in &self.commands {
+                write!(out, " +{}", command).unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// Redis 风格的 glob 匹配，支持 `*`、`?`、`[abc]`、`[^a-z]` 和 `\` 转义
+///
+/// 只记住最近一个 `*` 的位置和它当时对应的 key 偏移，失配时让这个 `*` 多吞一个字节再试。
+/// 更早的 `*` 不需要再回溯，所以有多少个 `*` 都是 O(模式长度 × key 长度)。
+fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // `*` 之后的模式位置，以及这个 `*` 目前吞到的 key 位置
+    let mut star: Option<(usize, usize)> = None;
+    while k < key.len() {
+        let next = match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p + 1, k));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => {
+                let (matched, rest) = class_match(&pattern[p + 1..], key[k]);
+                matched.then_some(pattern.len() - rest.len())
+            }
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == key[k]).then_some(p + 2),
+            Some(&c) => (c == key[k]).then_some(p + 1),
+            None => None,
+        };
+        match (next, star) {
+            (Some(next), _) => {
+                p = next;
+                k += 1;
+            }
+            (None, Some((after, taken))) => {
+                p = after;
+                k = taken + 1;
+                star = Some((after, k));
+            }
+            (None, None) => return false,
+        }
+    }
+    // key 用完了，剩下的模式只能全是 `*`
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// 匹配 `[...]`，`pattern` 从 `[` 之后开始，返回 `c` 是否在类里和 `]` 之后的剩余模式
+///
+/// 和 Redis 一样：`^` 开头表示取反，`a-z` 是范围（两头写反了也行），`\` 转义下一个字符，
+/// 没有 `]` 时一直到模式结尾都算在类里。
+fn class_match(pattern: &[u8], c: u8) -> (bool, &[u8]) {
+    let (negate, mut pattern) = match pattern {
+        [b'^', rest @ ..] => (true, rest),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+    loop {
+        match pattern {
+            [] => break,
+            [b']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            [b'\\', x, rest @ ..] => {
+                matched |= *x == c;
+                pattern = rest;
+            }
+            [lo, b'-', hi, rest @ ..] if *hi != b']' => {
+                matched |= (*lo.min(hi)..=*lo.max(hi)).contains(&c);
+                pattern = rest;
+            }
+            [x, rest @ ..] => {
+                matched |= *x == c;
+                pattern = rest;
+            }
+        }
+    }
+    (matched != negate, pattern)
+}
+
+fn is_sha256(digest: &str) -> bool {
+    digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// 密码的 SHA-256，十六进制小写
+fn hash(password: &str) -> String {
+    Sha256::digest(password.as_bytes())
+        .iter()
+        .fold(String::new(), |mut out, b| {
+            write!(out, "{:02x}", b).unwrap();
+            out
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &str) -> Vec<String> {
+        rules.split_whitespace().map(String::from).collect()
+    }
+
+    fn get(key: &str) -> Command {
+        Command::Get {
+            key: key.to_string(),
+        }
+    }
+
+    fn set(key: &str) -> Command {
+        Command::Set {
+            key: key.to_string(),
+            value: "v".into(),
+            expire: None,
+        }
+    }
+
+    fn glob(pattern: &str, key: &str) -> bool {
+        glob_match(pattern.as_bytes(), key.as_bytes())
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob("*", ""));
+        assert!(glob("cache:*", "cache:user:1"));
+        assert!(!glob("cache:*", "session:1"));
+        assert!(glob("h?llo", "hello"));
+        assert!(!glob("h?llo", "hllo"));
+        assert!(glob("*a*b*c", "xxaxxbxxc"));
+        assert!(!glob("*a*b*c", "xxaxxcxxb"));
+        assert!(glob("a**b", "ab"));
+        assert!(!glob("abc", "abcd"));
+    }
+
+    #[test]
+    fn glob_classes() {
+        assert!(glob("h[ae]llo", "hallo"));
+        assert!(!glob("h[ae]llo", "hillo"));
+        assert!(glob("[a-c]x", "bx"));
+        assert!(!glob("[^a-c]x", "bx"));
+        assert!(glob("[^a-c]x", "dx"));
+        // 范围两头写反了也一样
+        assert!(glob("[c-a]x", "bx"));
+        assert!(!glob("[c-a]x", "dx"));
+        // `-` 紧挨着 `]` 时只是普通字符
+        assert!(glob("[a-]", "-"));
+    }
+
+    #[test]
+    fn glob_escapes() {
+        assert!(glob(r"a\*b", "a*b"));
+        assert!(!glob(r"a\*b", "axb"));
+        assert!(glob(r"\?", "?"));
+        assert!(!glob(r"\?", "x"));
+        assert!(glob(r"\[x]", "[x]"));
+        assert!(glob(r"[\]]", "]"));
+        assert!(glob(r"[\^a]", "^"));
+        // 末尾单独的 `\` 按字面匹配
+        assert!(glob(r"a\", r"a\"));
+    }
+
+    #[test]
+    fn glob_unterminated_class() {
+        // 没有 `]` 时一直到模式结尾都算在类里
+        assert!(glob("x[abc", "xb"));
+        assert!(!glob("x[abc", "xd"));
+        assert!(!glob("x[abc", "xbc"));
+        assert!(!glob("x[", "x"));
+    }
+
+    #[test]
+    fn glob_many_stars() {
+        // 递归的写法在这里要回溯指数次
+        let pattern = "a*".repeat(30) + "b";
+        let key = "a".repeat(100);
+        assert!(!glob(&pattern, &key));
+        assert!(glob(&pattern, &(key + "b")));
+    }
+
+    #[test]
+    fn parse_rules() {
+        let acl = Acl::new();
+        acl.set_user("alice", &rules("on >secret ~cache:* +@read -hget"))
+            .unwrap();
+        acl.set_user("bob", &rules("off")).unwrap();
+        let list = acl.list();
+        assert_eq!(
+            list[0],
+            format!(
+                "user alice on #{} ~cache:* -@all +get +hgetall +xlen +xpending +xrange +xread +xrevrange",
+                hash("secret")
+            )
+        );
+        assert_eq!(list[1], "user bob off resetkeys -@all");
+        assert_eq!(list[2], "user default on nopass ~* +@all");
+
+        // 子命令跟着父命令一起加减
+        acl.set_user("carol", &rules("+acl -acl|setuser")).unwrap();
+        assert!(acl.list()[2].ends_with(" -@all +acl|list +acl|whoami"));
+
+        // 有一条规则不合法时整个命令都不生效
+        let err = acl
+            .set_user("alice", &rules("off +nosuchcommand"))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "ERR Error in ACL SETUSER modifier '+nosuchcommand': Unknown command"
+        );
+        assert!(acl.list()[0].starts_with("user alice on "));
+        let err = acl
+            .set_user("alice", &rules("+@nosuchcategory"))
+            .unwrap_err();
+        assert!(err.ends_with("Unknown command category"));
+        let err = acl.set_user("alice", &rules("secret")).unwrap_err();
+        assert!(err.ends_with("Syntax error"));
+        let err = acl.set_user("alice", &rules("#abc")).unwrap_err();
+        assert!(err.contains("exactly 64 characters"));
+    }
+
+    #[test]
+    fn sha256_passwords() {
+        assert_eq!(
+            hash("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let acl = Acl::new();
+        acl.set_user(
+            "alice",
+            &rules(&format!("on #{}", hash("abc").to_uppercase())),
+        )
+        .unwrap();
+        assert_eq!(acl.authenticate(Some("alice"), "abc").unwrap(), "alice");
+        assert!(
+            acl.authenticate(Some("alice"), "abd")
+                .unwrap_err()
+                .starts_with("WRONGPASS")
+        );
+
+        acl.set_user("alice", &rules(">second <abc")).unwrap();
+        assert!(acl.authenticate(Some("alice"), "abc").is_err());
+        assert!(acl.authenticate(Some("alice"), "second").is_ok());
+
+        // 禁用的用户密码对了也不能登录
+        acl.set_user("alice", &rules("off")).unwrap();
+        assert!(
+            acl.authenticate(Some("alice"), "second")
+                .unwrap_err()
+                .starts_with("WRONGPASS")
+        );
+        assert!(acl.authenticate(Some("nobody"), "second").is_err());
+
+        // default 不需要密码时，不带用户名的 AUTH 是配置错误
+        assert!(
+            acl.authenticate(None, "x")
+                .unwrap_err()
+                .starts_with("ERR AUTH <password>")
+        );
+        acl.set_user("default", &rules("resetpass >pw")).unwrap();
+        assert_eq!(acl.authenticate(None, "pw").unwrap(), "default");
+        assert_eq!(acl.default_user(), None);
+    }
+
+    #[test]
+    fn check_permissions() {
+        let acl = Acl::new();
+        acl.set_user("alice", &rules("on >pw ~cache:* +@read"))
+            .unwrap();
+
+        assert!(acl.check(Some("alice"), &get("cache:1")).is_ok());
+        assert_eq!(
+            acl.check(Some("alice"), &set("cache:1")).unwrap_err(),
+            "NOPERM User alice has no permissions to run the 'set' command"
+        );
+        assert_eq!(
+            acl.check(Some("alice"), &get("session:1")).unwrap_err(),
+            "NOPERM No permissions to access a key"
+        );
+
+        // 改了规则之后立即生效
+        acl.set_user("alice", &rules("+set resetkeys")).unwrap();
+        assert!(
+            acl.check(Some("alice"), &set("cache:1"))
+                .unwrap_err()
+                .starts_with("NOPERM No permissions")
+        );
+
+        // 没登录时只能 AUTH
+        let auth = Command::Auth {
+            username: None,
+            password: "pw".to_string(),
+        };
+        assert!(acl.check(None, &auth).is_ok());
+        assert_eq!(
+            acl.check(None, &get("k")).unwrap_err(),
+            "NOAUTH Authentication required."
+        );
+        assert!(
+            acl.check(Some("nobody"), &get("k"))
+                .unwrap_err()
+                .starts_with("NOAUTH")
+        );
+    }
+}
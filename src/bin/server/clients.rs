@@ -0,0 +1,352 @@
+//! 连接登记表
+//!
+//! 每条连接在建立时登记、断开时注销，记录地址、名字、最近执行的命令等信息，
+//! CLIENT LIST / KILL、INFO 的 clients 部分、MONITOR 和 SLOWLOG 都从这里取数据。
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use my_redis_project::frame::Frame;
+use my_redis_project::mailbox::{Mailbox, Policy};
+use tokio::sync::Notify;
+
+use crate::slowlog::SlowLog;
+
+/// MONITOR 连接接收命令的队列，每条是一行格式化好的命令
+pub type Monitor = Arc<Mailbox<String>>;
+
+/// 所有连接共享的登记表，clone 之后指向同一份数据
+#[derive(Clone)]
+pub struct Clients {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // 正在 MONITOR 的连接数，没有时执行命令不必格式化
+    monitors: AtomicUsize,
+    slowlog: SlowLog,
+    started: Instant,
+}
+
+struct State {
+    // 按 ID 排序，CLIENT LIST 按连接的先后顺序输出
+    clients: BTreeMap<u64, Record>,
+    next_id: u64,
+    total_connections: u64,
+    total_commands: u64,
+}
+
+struct Record {
+    addr: String,
+    name: Option<String>,
+    created: Instant,
+    last_interaction: Instant,
+    // 正在执行或者最近执行的命令
+    cmd: &'static str,
+    db: usize,
+    user: Option<String>,
+    kill: Arc<Notify>,
+    monitor: Option<Monitor>,
+}
+
+/// 一条连接在登记表中的记录，drop 时自动注销
+pub struct Client {
+    clients: Clients,
+    id: u64,
+    addr: String,
+    kill: Arc<Notify>,
+}
+
+/// CLIENT KILL 的过滤条件，都满足的连接才会被关闭
+#[derive(Debug)]
+pub struct KillFilter {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    pub user: Option<String>,
+    /// 不关闭执行 CLIENT KILL 的连接自己
+    pub skipme: bool,
+}
+
+/// INFO 用到的统计数据
+#[derive(Debug)]
+pub struct Stats {
+    pub uptime_in_seconds: u64,
+    pub connected_clients: usize,
+    pub total_connections_received: u64,
+    pub total_commands_processed: u64,
+}
+
+// MONITOR 跟不上时丢掉最旧的，不能拖慢执行命令的连接
+const MONITOR_BUFFER: usize = 1024;
+
+impl Clients {
+    pub fn new(slowlog: SlowLog) -> Clients {
+        Clients {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    clients: BTreeMap::new(),
+                    next_id: 1,
+                    total_connections: 0,
+                    total_commands: 0,
+                }),
+                monitors: AtomicUsize::new(0),
+                slowlog,
+                started: Instant::now(),
+            }),
+        }
+    }
+
+    /// 登记一条新连接，分配一个递增的 ID
+    pub fn register(&self, addr: String) -> Client {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.total_connections += 1;
+
+        let now = Instant::now();
+        let kill = Arc::new(Notify::new());
+        state.clients.insert(
+            id,
+            Record {
+                addr: addr.clone(),
+                name: None,
+                created: now,
+                last_interaction: now,
+                cmd: "NULL",
+                db: 0,
+                user: None,
+                kill: kill.clone(),
+                monitor: None,
+            },
+        );
+
+        Client {
+            clients: self.clone(),
+            id,
+            addr,
+            kill,
+        }
+    }
+
+    /// CLIENT LIST：每条连接一行 `id=1 addr=127.0.0.1:50000 name= ...`
+    pub fn list(&self) -> String {
+        let state = self.shared.state.lock().unwrap();
+        let now = Instant::now();
+        let mut out = String::new();
+        for (id, record) in &state.clients {
+            writeln!(
+                out,
+                "id={} addr={} name={} age={} idle={} flags={} db={} cmd={} user={}",
+                id,
+                record.addr,
+                record.name.as_deref().unwrap_or(""),
+                now.duration_since(record.created).as_secs(),
+                now.duration_since(record.last_interaction).as_secs(),
+                if record.monitor.is_some() { "O" } else { "N" },
+                record.db,
+                record.cmd,
+                record.user.as_deref().unwrap_or(""),
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// CLIENT KILL：关闭满足条件的连接，返回关闭的数量，`me` 是执行命令的连接
+    pub fn kill(&self, filter: &KillFilter, me: u64) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        let mut killed = 0;
+        for (id, record) in &state.clients {
+            let matches = filter.id.is_none_or(|want| want == *id)
+                && filter.addr.as_ref().is_none_or(|addr| *addr == record.addr)
+                && filter.user.as_ref().is_none_or(|user| Some(user) == record.user.as_ref())
+                && !(filter.skipme && *id == me);
+            if matches {
+                // `notify_one` 会留下一个许可，连接即使还没开始等也不会错过
+                record.kill.notify_one();
+                killed += 1;
+            }
+        }
+        killed
+    }
+
+    pub fn slowlog(&self) -> &SlowLog {
+        &self.shared.slowlog
+    }
+
+    pub fn has_monitors(&self) -> bool {
+        self.shared.monitors.load(Ordering::Relaxed) > 0
+    }
+
+    /// 把一条命令发给所有正在 MONITOR 的连接
+    pub async fn feed(&self, db: usize, addr: &str, args: &Frame) {
+        let monitors: Vec<Monitor> = {
+            let state = self.shared.state.lock().unwrap();
+            state.clients.values().filter_map(|record| record.monitor.clone()).collect()
+        };
+        if monitors.is_empty() {
+            return;
+        }
+
+        let line = monitor_line(db, addr, args);
+        for monitor in monitors {
+            // `DropOldest` 策略下不会等待，关闭了的也不用管，注销时会移除
+            let _ = monitor.send(line.clone()).await;
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let state = self.shared.state.lock().unwrap();
+        Stats {
+            uptime_in_seconds: self.shared.started.elapsed().as_secs(),
+            connected_clients: state.clients.len(),
+            total_connections_received: state.total_connections,
+            total_commands_processed: state.total_commands,
+        }
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut Record)) {
+        if let Some(record) = self.shared.state.lock().unwrap().clients.get_mut(&id) {
+            f(record);
+        }
+    }
+}
+
+impl Client {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn clients(&self) -> &Clients {
+        &self.clients
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// 开始执行一条命令
+    pub fn begin(&self, cmd: &'static str) {
+        let mut state = self.clients.shared.state.lock().unwrap();
+        state.total_commands += 1;
+        if let Some(record) = state.clients.get_mut(&self.id) {
+            record.cmd = cmd;
+            record.last_interaction = Instant::now();
+        }
+    }
+
+    /// 命令执行完了，`args` 是客户端发来的原始帧，执行得太慢时记入 SLOWLOG
+    pub fn finish(&self, args: &Frame, elapsed: Duration) {
+        let slowlog = &self.clients.shared.slowlog;
+        if slowlog.is_slow(elapsed) {
+            slowlog.record(elapsed, args, &self.addr, self.name());
+        }
+    }
+
+    pub fn set_db(&self, db: usize) {
+        self.clients.update(self.id, |record| record.db = db);
+    }
+
+    pub fn set_user(&self, user: Option<String>) {
+        self.clients.update(self.id, |record| record.user = user);
+    }
+
+    /// CLIENT SETNAME，空字符串表示清除名字
+    pub fn set_name(&self, name: String) -> Result<(), String> {
+        check_name(&name)?;
+        let name = (!name.is_empty()).then_some(name);
+        self.clients.update(self.id, |record| record.name = name);
+        Ok(())
+    }
+
+    pub fn name(&self) -> Option<String> {
+        let state = self.clients.shared.state.lock().unwrap();
+        state.clients[&self.id].name.clone()
+    }
+
+    /// 进入 MONITOR 模式，返回接收命令的队列
+    pub fn monitor(&self) -> Monitor {
+        let monitor = Arc::new(Mailbox::new(Policy::DropOldest, MONITOR_BUFFER));
+        let mut state = self.clients.shared.state.lock().unwrap();
+        let record = state.clients.get_mut(&self.id).unwrap();
+        if record.monitor.is_none() {
+            self.clients.shared.monitors.fetch_add(1, Ordering::Relaxed);
+        }
+        record.monitor = Some(monitor.clone());
+        monitor
+    }
+
+    /// 被 CLIENT KILL 关闭时返回
+    pub async fn killed(&self) {
+        self.kill.notified().await
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let mut state = self.clients.shared.state.lock().unwrap();
+        if let Some(record) = state.clients.remove(&self.id)
+            && let Some(monitor) = record.monitor
+        {
+            monitor.close();
+            self.clients.shared.monitors.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for KillFilter {
+    fn default() -> KillFilter {
+        KillFilter {
+            id: None,
+            addr: None,
+            user: None,
+            skipme: true,
+        }
+    }
+}
+
+/// 连接的名字只能由可打印、非空白的 ASCII 字符组成
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.chars().any(|c| !c.is_ascii_graphic()) {
+        return Err("ERR Client names cannot contain spaces, newlines or special characters.".to_string());
+    }
+    Ok(())
+}
+
+/// MONITOR 输出的一行：`1339518083.107412 [0 127.0.0.1:60866] "set" "key" "value"`
+fn monitor_line(db: usize, addr: &str, args: &Frame) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut line = format!("{}.{:06} [{} {}]", now.as_secs(), now.subsec_micros(), db, addr);
+    if let Frame::Array(args) = args {
+        for arg in args {
+            line.push(' ');
+            match arg {
+                Frame::Bulk(data) => quote(&mut line, data),
+                Frame::Simple(s) => quote(&mut line, s.as_bytes()),
+                frame => write!(line, "{:?}", frame).unwrap(),
+            }
+        }
+    }
+    line
+}
+
+/// 加上引号，不可打印的字节转义成 `\xHH`，和 Redis 的 `sdscatrepr` 一样
+fn quote(out: &mut String, data: &[u8]) {
+    out.push('"');
+    for &b in data {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            b if b.is_ascii_graphic() || b == b' ' => out.push(b as char),
+            b => write!(out, "\\x{:02x}", b).unwrap(),
+        }
+    }
+    out.push('"');
+}
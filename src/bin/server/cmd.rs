@@ -0,0 +1,769 @@
+use std::ops::Bound;
+use std::time::Duration;
+
+use bytes::Bytes;
+use my_redis_project::frame::Frame;
+
+use crate::clients::KillFilter;
+use crate::stream::{IdSpec, PendingQuery, StreamId, parse_range_bound};
+
+/// 服务端支持的命令
+///
+/// `mini_redis::Command` 只认识固定的几个命令，而且 `Publish`/`Subscribe` 的字段都是私有的，
+/// 服务端拿不到频道名，所以这里自己从 `Frame` 解析。
+#[derive(Debug)]
+pub enum Command {
+    Get { key: String },
+    /// `expire` 来自 EX / PX 选项，`None` 表示不过期
+    Set {
+        key: String,
+        value: Bytes,
+        expire: Option<Duration>,
+    },
+    Publish { channel: String, message: Bytes },
+    Subscribe { channels: Vec<String> },
+    /// `channels` 为空表示取消全部订阅
+    Unsubscribe { channels: Vec<String> },
+    Ping { message: Option<Bytes> },
+    /// 协商协议版本，`protover` 为 `None` 时保持当前版本；
+    /// 可以同时用 `AUTH <用户名> <密码>` 登录、用 `SETNAME` 设置连接的名字
+    Hello {
+        protover: Option<i64>,
+        auth: Option<(String, String)>,
+        setname: Option<String>,
+    },
+    HSet {
+        key: String,
+        fields: Vec<(Bytes, Bytes)>,
+    },
+    HGet { key: String, field: Bytes },
+    HGetAll { key: String },
+    HDel { key: String, fields: Vec<Bytes> },
+    XAdd {
+        key: String,
+        id: IdSpec,
+        maxlen: Option<usize>,
+        fields: Vec<(Bytes, Bytes)>,
+    },
+    /// XRANGE 和 XREVRANGE，`rev` 为 true 时从新到旧返回
+    XRange {
+        key: String,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+        rev: bool,
+    },
+    XLen { key: String },
+    XTrim { key: String, maxlen: usize },
+    XRead {
+        count: Option<usize>,
+        block: Option<Duration>,
+        streams: Vec<(String, ReadFrom)>,
+    },
+    XGroupCreate {
+        key: String,
+        group: String,
+        start: ReadFrom,
+        mkstream: bool,
+    },
+    XGroupDestroy { key: String, group: String },
+    XReadGroup {
+        group: String,
+        consumer: String,
+        count: Option<usize>,
+        block: Option<Duration>,
+        noack: bool,
+        streams: Vec<(String, GroupReadFrom)>,
+    },
+    XAck {
+        key: String,
+        group: String,
+        ids: Vec<StreamId>,
+    },
+    /// `query` 为 `None` 时是简单形式，只返回汇总信息
+    XPending {
+        key: String,
+        group: String,
+        query: Option<PendingQuery>,
+    },
+    XClaim {
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: Duration,
+        ids: Vec<StreamId>,
+        justid: bool,
+    },
+    /// `username` 为 `None` 时登录 `default` 用户
+    Auth {
+        username: Option<String>,
+        password: String,
+    },
+    AclWhoami,
+    AclList,
+    AclSetUser { username: String, rules: Vec<String> },
+    Select { index: usize },
+    Move { key: String, db: usize },
+    SwapDb { a: usize, b: usize },
+    FlushDb,
+    /// `section` 为 `None` 时返回所有部分
+    Info { section: Option<String> },
+    ClientId,
+    /// 空字符串表示清除名字
+    ClientSetName { name: String },
+    ClientGetName,
+    ClientList,
+    /// `legacy` 是只带一个地址参数的旧写法，回复 OK 或者错误而不是关闭的数量
+    ClientKill { filter: KillFilter, legacy: bool },
+    Monitor,
+    /// `count` 为 `None` 时返回全部
+    SlowLogGet { count: Option<usize> },
+    SlowLogLen,
+    SlowLogReset,
+}
+
+/// XREAD 和 XGROUP CREATE 的起始位置
+#[derive(Debug, Clone, Copy)]
+pub enum ReadFrom {
+    /// 指定的 ID 之后
+    After(StreamId),
+    /// `$`：执行命令时 stream 中最后一条之后，也就是只要新消息
+    Last,
+}
+
+/// XREADGROUP 的起始位置
+#[derive(Debug, Clone, Copy)]
+pub enum GroupReadFrom {
+    /// `>`：组内还没投递过的新消息
+    New,
+    /// 具体 ID：当前消费者 PEL 中该 ID 之后的消息
+    Pending(StreamId),
+}
+
+impl Command {
+    /// 把客户端发来的一帧解析成命令，失败时返回可以直接回给客户端的错误信息
+    pub fn from_frame(frame: Frame) -> Result<Command, String> {
+        let mut parse = Parse::new(frame)?;
+        let name = parse.next_string()?.to_lowercase();
+
+        let command = match name.as_str() {
+            "get" => Command::Get {
+                key: parse.next_string()?,
+            },
+            "set" => {
+                let key = parse.next_string()?;
+                let value = parse.next_bytes()?;
+                let expire = if parse.next_keyword("ex") {
+                    Some(Duration::from_secs(parse.next_number()?))
+                } else if parse.next_keyword("px") {
+                    Some(Duration::from_millis(parse.next_number()?))
+                } else {
+                    None
+                };
+                if expire == Some(Duration::ZERO) {
+                    return Err("ERR invalid expire time in 'set' command".to_string());
+                }
+                Command::Set { key, value, expire }
+            }
+            "publish" => Command::Publish {
+                channel: parse.next_string()?,
+                message: parse.next_bytes()?,
+            },
+            "subscribe" => {
+                let channels = parse.remaining_strings()?;
+                if channels.is_empty() {
+                    return Err(wrong_arity(&name));
+                }
+                Command::Subscribe { channels }
+            }
+            "unsubscribe" => Command::Unsubscribe {
+                channels: parse.remaining_strings()?,
+            },
+            "ping" => Command::Ping {
+                message: parse.next_optional_bytes()?,
+            },
+            "hello" => {
+                let protover = match parse.next_optional_string()? {
+                    Some(v) => Some(
+                        v.parse()
+                            .map_err(|_| "ERR Protocol version is not an integer or out of range".to_string())?,
+                    ),
+                    None => None,
+                };
+                let mut auth = None;
+                let mut setname = None;
+                while let Some(option) = parse.next_optional_string()? {
+                    match option.to_lowercase().as_str() {
+                        "auth" => auth = Some((parse.next_string()?, parse.next_string()?)),
+                        "setname" => setname = Some(parse.next_string()?),
+                        _ => return Err(format!("ERR Syntax error in HELLO option '{}'", option)),
+                    }
+                }
+                Command::Hello {
+                    protover,
+                    auth,
+                    setname,
+                }
+            }
+            "hset" => {
+                let key = parse.next_string()?;
+                let fields = parse.remaining_pairs()?;
+                if fields.is_empty() {
+                    return Err(wrong_arity(&name));
+                }
+                Command::HSet { key, fields }
+            }
+            "hget" => Command::HGet {
+                key: parse.next_string()?,
+                field: parse.next_bytes()?,
+            },
+            "hgetall" => Command::HGetAll {
+                key: parse.next_string()?,
+            },
+            "hdel" => {
+                let key = parse.next_string()?;
+                let mut fields = Vec::new();
+                while !parse.is_empty() {
+                    fields.push(parse.next_bytes()?);
+                }
+                if fields.is_empty() {
+                    return Err(wrong_arity(&name));
+                }
+                Command::HDel { key, fields }
+            }
+            "xadd" => {
+                let key = parse.next_string()?;
+                let maxlen = if parse.next_keyword("maxlen") {
+                    // 近似裁剪 `~` 在这里和精确裁剪一样处理
+                    let _ = parse.next_keyword("~") || parse.next_keyword("=");
+                    Some(parse.next_number()?)
+                } else {
+                    None
+                };
+                let id = parse.next_string()?.parse()?;
+                let fields = parse.remaining_pairs()?;
+                if fields.is_empty() {
+                    return Err(wrong_arity(&name));
+                }
+                Command::XAdd {
+                    key,
+                    id,
+                    maxlen,
+                    fields,
+                }
+            }
+            "xrange" | "xrevrange" => {
+                let rev = name == "xrevrange";
+                let key = parse.next_string()?;
+                let (first, second) = (parse.next_string()?, parse.next_string()?);
+                // XREVRANGE 的参数顺序是先终点后起点
+                let (start, end) = if rev { (second, first) } else { (first, second) };
+                let count = if parse.next_keyword("count") {
+                    Some(parse.next_number()?)
+                } else {
+                    None
+                };
+                Command::XRange {
+                    key,
+                    start: parse_range_bound(&start, true)?,
+                    end: parse_range_bound(&end, false)?,
+                    count,
+                    rev,
+                }
+            }
+            "xlen" => Command::XLen {
+                key: parse.next_string()?,
+            },
+            "xtrim" => {
+                let key = parse.next_string()?;
+                if !parse.next_keyword("maxlen") {
+                    return Err("ERR syntax error, only MAXLEN is supported".to_string());
+                }
+                let _ = parse.next_keyword("~") || parse.next_keyword("=");
+                Command::XTrim {
+                    key,
+                    maxlen: parse.next_number()?,
+                }
+            }
+            "xread" => {
+                let (mut count, mut block) = (None, None);
+                loop {
+                    if parse.next_keyword("count") {
+                        count = Some(parse.next_number()?);
+                    } else if parse.next_keyword("block") {
+                        block = Some(Duration::from_millis(parse.next_number()?));
+                    } else if parse.next_keyword("streams") {
+                        break;
+                    } else {
+                        return Err("ERR syntax error".to_string());
+                    }
+                }
+                let streams = parse.streams(|id| {
+                    if id == "$" {
+                        Ok(ReadFrom::Last)
+                    } else {
+                        id.parse().map(ReadFrom::After)
+                    }
+                })?;
+                Command::XRead {
+                    count,
+                    block,
+                    streams,
+                }
+            }
+            "xgroup" => {
+                let sub = parse.next_string()?.to_lowercase();
+                match sub.as_str() {
+                    "create" => {
+                        let key = parse.next_string()?;
+                        let group = parse.next_string()?;
+                        let start = match parse.next_string()?.as_str() {
+                            "$" => ReadFrom::Last,
+                            id => ReadFrom::After(id.parse()?),
+                        };
+                        let mkstream = parse.next_keyword("mkstream");
+                        Command::XGroupCreate {
+                            key,
+                            group,
+                            start,
+                            mkstream,
+                        }
+                    }
+                    "destroy" => Command::XGroupDestroy {
+                        key: parse.next_string()?,
+                        group: parse.next_string()?,
+                    },
+                    _ => return Err(format!("ERR unknown subcommand '{}' for 'xgroup'", sub)),
+                }
+            }
+            "xreadgroup" => {
+                if !parse.next_keyword("group") {
+                    return Err("ERR syntax error".to_string());
+                }
+                let group = parse.next_string()?;
+                let consumer = parse.next_string()?;
+                let (mut count, mut block, mut noack) = (None, None, false);
+                loop {
+                    if parse.next_keyword("count") {
+                        count = Some(parse.next_number()?);
+                    } else if parse.next_keyword("block") {
+                        block = Some(Duration::from_millis(parse.next_number()?));
+                    } else if parse.next_keyword("noack") {
+                        noack = true;
+                    } else if parse.next_keyword("streams") {
+                        break;
+                    } else {
+                        return Err("ERR syntax error".to_string());
+                    }
+                }
+                let streams = parse.streams(|id| {
+                    if id == ">" {
+                        Ok(GroupReadFrom::New)
+                    } else {
+                        id.parse().map(GroupReadFrom::Pending)
+                    }
+                })?;
+                Command::XReadGroup {
+                    group,
+                    consumer,
+                    count,
+                    block,
+                    noack,
+                    streams,
+                }
+            }
+            "xack" => {
+                let key = parse.next_string()?;
+                let group = parse.next_string()?;
+                let ids = parse.remaining_ids()?;
+                if ids.is_empty() {
+                    return Err(wrong_arity(&name));
+                }
+                Command::XAck { key, group, ids }
+            }
+            "xpending" => {
+                let key = parse.next_string()?;
+                let group = parse.next_string()?;
+                let query = if parse.is_empty() {
+                    None
+                } else {
+                    let min_idle = if parse.next_keyword("idle") {
+                        Duration::from_millis(parse.next_number()?)
+                    } else {
+                        Duration::ZERO
+                    };
+                    Some(PendingQuery {
+                        min_idle,
+                        start: parse_range_bound(&parse.next_string()?, true)?,
+                        end: parse_range_bound(&parse.next_string()?, false)?,
+                        count: parse.next_number()?,
+                        consumer: parse.next_optional_string()?,
+                    })
+                };
+                Command::XPending { key, group, query }
+            }
+            "xclaim" => {
+                let key = parse.next_string()?;
+                let group = parse.next_string()?;
+                let consumer = parse.next_string()?;
+                let min_idle = Duration::from_millis(parse.next_number()?);
+                let mut ids = Vec::new();
+                let mut justid = false;
+                while !parse.is_empty() {
+                    if parse.next_keyword("justid") {
+                        justid = true;
+                    } else {
+                        ids.push(parse.next_string()?.parse()?);
+                    }
+                }
+                if ids.is_empty() {
+                    return Err(wrong_arity(&name));
+                }
+                Command::XClaim {
+                    key,
+                    group,
+                    consumer,
+                    min_idle,
+                    ids,
+                    justid,
+                }
+            }
+            "auth" => {
+                let first = parse.next_string()?;
+                match parse.next_optional_string()? {
+                    Some(password) => Command::Auth {
+                        username: Some(first),
+                        password,
+                    },
+                    None => Command::Auth {
+                        username: None,
+                        password: first,
+                    },
+                }
+            }
+            "acl" => {
+                let sub = parse.next_string()?.to_lowercase();
+                match sub.as_str() {
+                    "whoami" => Command::AclWhoami,
+                    "list" => Command::AclList,
+                    "setuser" => Command::AclSetUser {
+                        username: parse.next_string()?,
+                        rules: parse.remaining_strings()?,
+                    },
+                    _ => return Err(format!("ERR unknown subcommand '{}' for 'acl'", sub)),
+                }
+            }
+            "select" => Command::Select {
+                index: parse.next_number()?,
+            },
+            "move" => Command::Move {
+                key: parse.next_string()?,
+                db: parse.next_number()?,
+            },
+            "swapdb" => Command::SwapDb {
+                a: parse.next_number()?,
+                b: parse.next_number()?,
+            },
+            "flushdb" => {
+                // 这里总是同步清空，ASYNC / SYNC 只是为了兼容
+                let _ = parse.next_keyword("async") || parse.next_keyword("sync");
+                Command::FlushDb
+            }
+            "info" => Command::Info {
+                section: parse.next_optional_string()?.map(|s| s.to_lowercase()),
+            },
+            "client" => {
+                let sub = parse.next_string()?.to_lowercase();
+                match sub.as_str() {
+                    "id" => Command::ClientId,
+                    "setname" => Command::ClientSetName {
+                        name: parse.next_string()?,
+                    },
+                    "getname" => Command::ClientGetName,
+                    "list" => Command::ClientList,
+                    "kill" => parse_client_kill(&mut parse)?,
+                    _ => return Err(format!("ERR unknown subcommand '{}' for 'client'", sub)),
+                }
+            }
+            "monitor" => Command::Monitor,
+            "slowlog" => {
+                let sub = parse.next_string()?.to_lowercase();
+                match sub.as_str() {
+                    "get" => {
+                        // 默认 10 条，-1 表示全部
+                        let count = match parse.next_optional_string()? {
+                            None => Some(10),
+                            Some(count) => match count.parse::<i64>() {
+                                Ok(-1) => None,
+                                Ok(count) if count >= 0 => Some(count as usize),
+                                _ => {
+                                    return Err(
+                                        "ERR count should be greater than or equal to -1".to_string()
+                                    );
+                                }
+                            },
+                        };
+                        Command::SlowLogGet { count }
+                    }
+                    "len" => Command::SlowLogLen,
+                    "reset" => Command::SlowLogReset,
+                    _ => return Err(format!("ERR unknown subcommand '{}' for 'slowlog'", sub)),
+                }
+            }
+            _ => return Err(format!("ERR unknown command '{}'", name)),
+        };
+
+        if !parse.is_empty() {
+            return Err(wrong_arity(&name));
+        }
+
+        Ok(command)
+    }
+
+    /// 命令名，ACL 按它检查权限；子命令写成 `xgroup|create` 的形式
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Get { .. } => "get",
+            Command::Set { .. } => "set",
+            Command::Publish { .. } => "publish",
+            Command::Subscribe { .. } => "subscribe",
+            Command::Unsubscribe { .. } => "unsubscribe",
+            Command::Ping { .. } => "ping",
+            Command::Hello { .. } => "hello",
+            Command::HSet { .. } => "hset",
+            Command::HGet { .. } => "hget",
+            Command::HGetAll { .. } => "hgetall",
+            Command::HDel { .. } => "hdel",
+            Command::XAdd { .. } => "xadd",
+            Command::XRange { rev: false, .. } => "xrange",
+            Command::XRange { rev: true, .. } => "xrevrange",
+            Command::XLen { .. } => "xlen",
+            Command::XTrim { .. } => "xtrim",
+            Command::XRead { .. } => "xread",
+            Command::XGroupCreate { .. } => "xgroup|create",
+            Command::XGroupDestroy { .. } => "xgroup|destroy",
+            Command::XReadGroup { .. } => "xreadgroup",
+            Command::XAck { .. } => "xack",
+            Command::XPending { .. } => "xpending",
+            Command::XClaim { .. } => "xclaim",
+            Command::Auth { .. } => "auth",
+            Command::AclWhoami => "acl|whoami",
+            Command::AclList => "acl|list",
+            Command::AclSetUser { .. } => "acl|setuser",
+            Command::Select { .. } => "select",
+            Command::Move { .. } => "move",
+            Command::SwapDb { .. } => "swapdb",
+            Command::FlushDb => "flushdb",
+            Command::Info { .. } => "info",
+            Command::ClientId => "client|id",
+            Command::ClientSetName { .. } => "client|setname",
+            Command::ClientGetName => "client|getname",
+            Command::ClientList => "client|list",
+            Command::ClientKill { .. } => "client|kill",
+            Command::Monitor => "monitor",
+            Command::SlowLogGet { .. } => "slowlog|get",
+            Command::SlowLogLen => "slowlog|len",
+            Command::SlowLogReset => "slowlog|reset",
+        }
+    }
+
+    /// 命令要访问的 key，频道名不算
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Get { key }
+            | Command::Set { key, .. }
+            | Command::HSet { key, .. }
+            | Command::HGet { key, .. }
+            | Command::HGetAll { key }
+            | Command::HDel { key, .. }
+            | Command::XAdd { key, .. }
+            | Command::XRange { key, .. }
+            | Command::XLen { key }
+            | Command::XTrim { key, .. }
+            | Command::XGroupCreate { key, .. }
+            | Command::XGroupDestroy { key, .. }
+            | Command::XAck { key, .. }
+            | Command::XPending { key, .. }
+            | Command::XClaim { key, .. }
+            | Command::Move { key, .. } => vec![key],
+            Command::XRead { streams, .. } => streams.iter().map(|(key, _)| key.as_str()).collect(),
+            Command::XReadGroup { streams, .. } => streams.iter().map(|(key, _)| key.as_str()).collect(),
+            Command::Publish { .. }
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::Ping { .. }
+            | Command::Hello { .. }
+            | Command::Auth { .. }
+            | Command::AclWhoami
+            | Command::AclList
+            | Command::AclSetUser { .. }
+            | Command::Select { .. }
+            | Command::SwapDb { .. }
+            | Command::FlushDb
+            | Command::Info { .. }
+            | Command::ClientId
+            | Command::ClientSetName { .. }
+            | Command::ClientGetName
+            | Command::ClientList
+            | Command::ClientKill { .. }
+            | Command::Monitor
+            | Command::SlowLogGet { .. }
+            | Command::SlowLogLen
+            | Command::SlowLogReset => vec![],
+        }
+    }
+}
+
+/// `CLIENT KILL addr` 或者 `CLIENT KILL [ID id] [ADDR addr] [USER user] [SKIPME yes|no]`
+fn parse_client_kill(parse: &mut Parse) -> Result<Command, String> {
+    if parse.is_empty() {
+        return Err(wrong_arity("client|kill"));
+    }
+
+    let mut filter = KillFilter::default();
+    if parse.parts.len() == 1 {
+        filter.addr = Some(parse.next_string()?);
+        // 旧写法连自己也可以关
+        filter.skipme = false;
+        return Ok(Command::ClientKill {
+            filter,
+            legacy: true,
+        });
+    }
+
+    while !parse.is_empty() {
+        if parse.next_keyword("id") {
+            filter.id = Some(parse.next_number()?);
+        } else if parse.next_keyword("addr") {
+            filter.addr = Some(parse.next_string()?);
+        } else if parse.next_keyword("user") {
+            filter.user = Some(parse.next_string()?);
+        } else if parse.next_keyword("skipme") {
+            filter.skipme = match parse.next_string()?.to_lowercase().as_str() {
+                "yes" => true,
+                "no" => false,
+                _ => return Err("ERR syntax error".to_string()),
+            };
+        } else {
+            return Err("ERR syntax error".to_string());
+        }
+    }
+    Ok(Command::ClientKill {
+        filter,
+        legacy: false,
+    })
+}
+
+fn wrong_arity(name: &str) -> String {
+    format!("ERR wrong number of arguments for '{}' command", name)
+}
+
+/// 按顺序取出命令参数的小工具
+struct Parse {
+    parts: std::vec::IntoIter<Frame>,
+}
+
+impl Parse {
+    fn new(frame: Frame) -> Result<Parse, String> {
+        match frame {
+            Frame::Array(parts) => Ok(Parse {
+                parts: parts.into_iter(),
+            }),
+            frame => Err(format!("ERR protocol error; expected array, got {}", frame.type_name())),
+        }
+    }
+
+    fn next_bytes(&mut self) -> Result<Bytes, String> {
+        self.next_optional_bytes()?
+            .ok_or_else(|| "ERR wrong number of arguments".to_string())
+    }
+
+    fn next_optional_bytes(&mut self) -> Result<Option<Bytes>, String> {
+        match self.parts.next() {
+            Some(Frame::Bulk(data)) => Ok(Some(data)),
+            Some(Frame::Simple(s)) => Ok(Some(Bytes::from(s))),
+            Some(frame) => Err(format!("ERR protocol error; expected bulk, got {}", frame.type_name())),
+            None => Ok(None),
+        }
+    }
+
+    fn next_string(&mut self) -> Result<String, String> {
+        let data = self.next_bytes()?;
+        String::from_utf8(data.to_vec()).map_err(|_| "ERR protocol error; invalid string".to_string())
+    }
+
+    fn next_optional_string(&mut self) -> Result<Option<String>, String> {
+        if self.is_empty() {
+            Ok(None)
+        } else {
+            self.next_string().map(Some)
+        }
+    }
+
+    fn next_number<T: std::str::FromStr>(&mut self) -> Result<T, String> {
+        self.next_string()?
+            .parse()
+            .map_err(|_| "ERR value is not an integer or out of range".to_string())
+    }
+
+    /// 如果下一个参数是 `keyword`（不区分大小写）就消耗掉它并返回 true
+    fn next_keyword(&mut self, keyword: &str) -> bool {
+        let matches = match self.parts.as_slice().first() {
+            Some(Frame::Bulk(data)) => data.eq_ignore_ascii_case(keyword.as_bytes()),
+            Some(Frame::Simple(s)) => s.eq_ignore_ascii_case(keyword),
+            _ => false,
+        };
+        if matches {
+            self.parts.next();
+        }
+        matches
+    }
+
+    /// 剩余参数两两一组，例如 XADD 的 field value
+    fn remaining_pairs(&mut self) -> Result<Vec<(Bytes, Bytes)>, String> {
+        let mut pairs = Vec::new();
+        while !self.is_empty() {
+            pairs.push((self.next_bytes()?, self.next_bytes()?));
+        }
+        Ok(pairs)
+    }
+
+    fn remaining_ids(&mut self) -> Result<Vec<StreamId>, String> {
+        let mut ids = Vec::new();
+        while !self.is_empty() {
+            ids.push(self.next_string()?.parse()?);
+        }
+        Ok(ids)
+    }
+
+    /// `STREAMS key1 key2 ... id1 id2 ...`：前一半是 key，后一半是对应的 ID
+    fn streams<T>(
+        &mut self,
+        parse_id: impl Fn(&str) -> Result<T, String>,
+    ) -> Result<Vec<(String, T)>, String> {
+        let args = self.remaining_strings()?;
+        if args.is_empty() || args.len() % 2 != 0 {
+            return Err(
+                "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+                    .to_string(),
+            );
+        }
+        let (keys, ids) = args.split_at(args.len() / 2);
+        keys.iter()
+            .zip(ids)
+            .map(|(key, id)| Ok((key.clone(), parse_id(id)?)))
+            .collect()
+    }
+
+    fn remaining_strings(&mut self) -> Result<Vec<String>, String> {
+        let mut strings = Vec::new();
+        while !self.is_empty() {
+            strings.push(self.next_string()?);
+        }
+        Ok(strings)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.parts.len() == 0
+    }
+}
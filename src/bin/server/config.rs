@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use my_redis_project::codec::{DEFAULT_MAX_BULK_LEN, DEFAULT_MAX_DEPTH, DEFAULT_MAX_MULTIBULK_LEN};
+use my_redis_project::mailbox::Policy;
+
+use crate::memory::{self, EvictionPolicy};
+
+/// 服务端的启动参数
+///
+/// 例如：`cargo run --bin server -- --slow-consumer disconnect --pubsub-buffer 64 --unixsocket /tmp/redis.sock`
+/// 或者：`cargo run --bin server -- --maxmemory 100mb --maxmemory-policy allkeys-lru`
+/// 只监听 Unix socket：`cargo run --bin server -- --addr none --unixsocket /tmp/redis.sock`
+/// TCP 上走 TLS：`cargo run --bin server -- --tls-cert server.pem --tls-key server.key`
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// 监听的 TCP 地址，`None` 表示不监听 TCP，只走 Unix socket
+    pub addr: Option<String>,
+    /// PEM 格式的证书链和私钥，都给出时 TCP 连接要先完成 TLS 握手，Unix socket 不受影响
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// 额外监听的 Unix socket 路径
+    pub unixsocket: Option<PathBuf>,
+    /// Unix socket 文件的权限位，用文件权限控制谁能连上来
+    pub unixsocketperm: u32,
+    /// 有几个编号的库，客户端用 SELECT 切换
+    pub databases: usize,
+    /// 估算的内存占用上限（字节），0 表示不限制
+    pub maxmemory: usize,
+    /// 超过 `maxmemory` 时淘汰哪些 key
+    pub maxmemory_policy: EvictionPolicy,
+    /// 淘汰时每个库采样几个 key，越大越接近精确的 LRU/LFU，也越慢
+    pub maxmemory_samples: usize,
+    /// 执行时间超过多久的命令记入 SLOWLOG，`None` 表示不记录
+    pub slowlog_log_slower_than: Option<Duration>,
+    /// SLOWLOG 最多保留几条
+    pub slowlog_max_len: usize,
+    /// 订阅者消费太慢时的处理策略
+    pub slow_consumer: Policy,
+    /// 每个订阅连接最多缓冲多少条还没发出去的消息
+    pub pubsub_buffer: usize,
+    /// 客户端发来的单个 bulk string 最大长度，超出时关闭连接
+    pub proto_max_bulk_len: usize,
+    /// 客户端发来的单个数组最多多少个元素，超出时关闭连接
+    pub proto_max_multibulk_len: usize,
+    /// 客户端发来的帧最多嵌套多少层
+    pub proto_max_depth: usize,
+    /// `default` 用户的密码，设置后客户端要先 AUTH
+    pub requirepass: Option<String>,
+    /// 启动时创建的 ACL 用户：用户名和规则，例如 `--user "alice on >secret ~cache:* +@read"`
+    pub users: Vec<(String, Vec<String>)>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            addr: Some("127.0.0.1:6379".to_string()),
+            tls_cert: None,
+            tls_key: None,
+            unixsocket: None,
+            unixsocketperm: 0o700,
+            databases: 16,
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            maxmemory_samples: 5,
+            slowlog_log_slower_than: Some(Duration::from_millis(10)),
+            slowlog_max_len: 128,
+            slow_consumer: Policy::DropOldest,
+            pubsub_buffer: 1024,
+            proto_max_bulk_len: DEFAULT_MAX_BULK_LEN,
+            proto_max_multibulk_len: DEFAULT_MAX_MULTIBULK_LEN,
+            proto_max_depth: DEFAULT_MAX_DEPTH,
+            requirepass: None,
+            users: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for `{}`", arg));
+
+            match arg.as_str() {
+                // `--addr none` 不监听 TCP，同机进程只能通过 `--unixsocket` 连上来
+                "--addr" => config.addr = Some(value()?).filter(|addr| addr != "none"),
+                "--tls-cert" => config.tls_cert = Some(value()?.into()),
+                "--tls-key" => config.tls_key = Some(value()?.into()),
+                "--unixsocket" => config.unixsocket = Some(value()?.into()),
+                // 和 Redis 的 `unixsocketperm` 一样按八进制写，例如 770
+                "--unixsocketperm" => {
+                    let perm = value()?;
+                    config.unixsocketperm = u32::from_str_radix(&perm, 8)
+                        .ok()
+                        .filter(|perm| *perm <= 0o777)
+                        .ok_or(format!("invalid value `{}` for `{}`", perm, arg))?;
+                }
+                "--databases" => {
+                    config.databases = parse_number(&arg, &value()?)?;
+                    if config.databases == 0 {
+                        return Err("`--databases` must be greater than zero".to_string());
+                    }
+                }
+                "--maxmemory" => config.maxmemory = memory::parse_memory(&value()?)?,
+                "--maxmemory-policy" => config.maxmemory_policy = value()?.parse()?,
+                "--maxmemory-samples" => {
+                    config.maxmemory_samples = parse_number(&arg, &value()?)?;
+                    if config.maxmemory_samples == 0 {
+                        return Err("`--maxmemory-samples` must be greater than zero".to_string());
+                    }
+                }
+                // 微秒，和 Redis 一样负数表示关闭，0 表示记录所有命令
+                "--slowlog-log-slower-than" => {
+                    let micros: i64 = parse_number(&arg, &value()?)?;
+                    config.slowlog_log_slower_than =
+                        u64::try_from(micros).ok().map(Duration::from_micros);
+                }
+                "--slowlog-max-len" => config.slowlog_max_len = parse_number(&arg, &value()?)?,
+                "--slow-consumer" => config.slow_consumer = value()?.parse()?,
+                "--pubsub-buffer" => {
+                    config.pubsub_buffer = parse_number(&arg, &value()?)?;
+                    if config.pubsub_buffer == 0 {
+                        return Err("`--pubsub-buffer` must be greater than zero".to_string());
+                    }
+                }
+                "--proto-max-bulk-len" => {
+                    config.proto_max_bulk_len = parse_number(&arg, &value()?)?
+                }
+                "--proto-max-multibulk-len" => {
+                    config.proto_max_multibulk_len = parse_number(&arg, &value()?)?
+                }
+                "--proto-max-depth" => config.proto_max_depth = parse_number(&arg, &value()?)?,
+                "--requirepass" => config.requirepass = Some(value()?),
+                "--user" => {
+                    let spec = value()?;
+                    let mut words = spec.split_whitespace().map(String::from);
+                    let name = words.next().ok_or("`--user` requires a user name".to_string())?;
+                    config.users.push((name, words.collect()));
+                }
+                _ => return Err(format!("unknown argument `{}`", arg)),
+            }
+        }
+
+        if config.tls_cert.is_some() != config.tls_key.is_some() {
+            return Err("`--tls-cert` and `--tls-key` must be given together".to_string());
+        }
+        if config.addr.is_none() && config.unixsocket.is_none() {
+            return Err("`--addr none` requires `--unixsocket`".to_string());
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}` for `{}`", value, arg))
+}
@@ -0,0 +1,46 @@
+use futures::{SinkExt, StreamExt};
+use my_redis_project::codec::RespCodec;
+use my_redis_project::frame::{Frame, Protocol};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+
+/// 在 `TcpStream`、`UnixStream` 之类的字节流上收发 `Frame`
+///
+/// 解码、编码都交给 `RespCodec`，这里只是把 `Framed` 包装成和 `mini_redis::Connection`
+/// 一样的 `read_frame` / `write_frame` 接口。写出时按 HELLO 协商出的协议版本编码，
+/// RESP2 客户端收到的是退化后的帧。
+pub struct Connection<S> {
+    framed: Framed<S, RespCodec>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(socket: S, codec: RespCodec) -> Connection<S> {
+        Connection {
+            framed: Framed::new(socket, codec),
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.framed.codec().protocol()
+    }
+
+    /// 切换协议版本，之后写出的帧都按新版本编码
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.framed.codec_mut().set_protocol(protocol);
+    }
+
+    /// 读取一帧，对端正常关闭时返回 `None`
+    ///
+    /// 读到的数据留在 `Framed` 内部的缓冲区，因此在 `select!` 中被取消也不会丢数据。
+    pub async fn read_frame(&mut self) -> mini_redis::Result<Option<Frame>> {
+        match self.framed.next().await {
+            Some(frame) => Ok(Some(frame?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn write_frame(&mut self, frame: &Frame) -> mini_redis::Result<()> {
+        self.framed.send(frame).await?;
+        Ok(())
+    }
+}
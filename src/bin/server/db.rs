@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::future;
+use my_redis_project::mailbox::{Mailbox, Policy};
+use tokio::sync::Notify;
+
+use crate::config::Config;
+use crate::keyspace::{Hash, Keyspace, Value};
+use crate::memory::{EvictionPolicy, Rng};
+use crate::stream::{Entry, IdSpec, Stream, StreamId};
+
+/// 每个订阅连接持有一个 `Mailbox`，它订阅的所有频道的消息都投递到这里
+pub type Subscriber = Arc<Mailbox<(String, Bytes)>>;
+
+/// 所有连接共享的数据库：若干个编号的 keyspace + pub/sub 频道
+///
+/// `Db` 内部是 `Arc`，clone 之后指向同一份数据。每个 `Db` 还记着自己选中的是几号库，
+/// key 相关的操作都只作用在这个库上；pub/sub 和 Redis 一样不区分库。
+#[derive(Clone)]
+pub struct Db {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // 有 stream 写入新消息时通知阻塞中的 XREAD / XREADGROUP
+    stream_added: Notify,
+    slow_consumer: Policy,
+    pubsub_buffer: usize,
+}
+
+struct State {
+    // 下标就是库的编号
+    dbs: Vec<Keyspace>,
+    // 频道名 -> 订阅了该频道的连接
+    pub_sub: HashMap<String, Vec<Subscriber>>,
+    maxmemory: usize,
+    policy: EvictionPolicy,
+    samples: usize,
+    evicted_keys: u64,
+    rng: Rng,
+}
+
+/// INFO 用到的统计数据
+#[derive(Debug)]
+pub struct Stats {
+    pub used_memory: usize,
+    pub maxmemory: usize,
+    pub maxmemory_policy: EvictionPolicy,
+    pub evicted_keys: u64,
+    pub expired_keys: u64,
+    /// 每个非空的库：编号、key 的数量、设置了过期时间的 key 的数量
+    pub keyspace: Vec<(usize, usize, usize)>,
+}
+
+impl Db {
+    /// 按配置创建若干个空库，返回的 `Db` 选中的是 0 号库
+    pub fn new(config: &Config) -> Db {
+        Db {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    dbs: (0..config.databases).map(|_| Keyspace::default()).collect(),
+                    pub_sub: HashMap::new(),
+                    maxmemory: config.maxmemory,
+                    policy: config.maxmemory_policy,
+                    samples: config.maxmemory_samples,
+                    evicted_keys: 0,
+                    rng: Rng::new(),
+                }),
+                stream_added: Notify::new(),
+                slow_consumer: config.slow_consumer,
+                pubsub_buffer: config.pubsub_buffer,
+            }),
+            index: 0,
+        }
+    }
+
+    /// 选中的是几号库
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// SELECT：指向同一份数据、但选中 `index` 号库的 `Db`
+    pub fn select(&self, index: usize) -> Result<Db, String> {
+        self.check_index(index)?;
+        Ok(Db {
+            shared: self.shared.clone(),
+            index,
+        })
+    }
+
+    /// MOVE：把 `key` 从当前库移到 `to` 号库，目标库里已经有这个 key 或者当前库里没有时返回 false
+    pub fn move_key(&self, key: &str, to: usize) -> Result<bool, String> {
+        self.check_index(to)?;
+        if to == self.index {
+            return Err("ERR source and destination objects are the same".to_string());
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        if state.dbs[to].contains_key(key) {
+            return Ok(false);
+        }
+        let Some(entry) = state.dbs[self.index].remove(key) else {
+            return Ok(false);
+        };
+        state.dbs[to].insert(key.to_string(), entry.value, entry.expires_at);
+        drop(state);
+
+        // 移过去的可能是 stream，让阻塞在目标库上的 XREAD 重新检查一遍
+        self.shared.stream_added.notify_waiters();
+        Ok(true)
+    }
+
+    /// SWAPDB：交换两个库的数据，选中这两个库的连接马上看到的就是对方的数据
+    pub fn swap(&self, a: usize, b: usize) -> Result<(), String> {
+        self.check_index(a)?;
+        self.check_index(b)?;
+        self.shared.state.lock().unwrap().dbs.swap(a, b);
+        self.shared.stream_added.notify_waiters();
+        Ok(())
+    }
+
+    /// FLUSHDB：清空当前库
+    pub fn flush(&self) {
+        let entries = self.shared.state.lock().unwrap().dbs[self.index].clear();
+        // 在锁外释放，大库的析构不会挡住其他连接
+        drop(entries);
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), String> {
+        if index < self.shared.state.lock().unwrap().dbs.len() {
+            Ok(())
+        } else {
+            Err("ERR DB index is out of range".to_string())
+        }
+    }
+
+    /// INFO：内存、淘汰和各个库的统计
+    pub fn stats(&self) -> Stats {
+        let state = self.shared.state.lock().unwrap();
+        Stats {
+            used_memory: state.used_memory(),
+            maxmemory: state.maxmemory,
+            maxmemory_policy: state.policy,
+            evicted_keys: state.evicted_keys,
+            expired_keys: state.dbs.iter().map(Keyspace::expired_keys).sum(),
+            keyspace: state
+                .dbs
+                .iter()
+                .enumerate()
+                .filter(|(_, keyspace)| keyspace.len() > 0)
+                .map(|(i, keyspace)| (i, keyspace.len(), keyspace.volatile_len()))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Bytes>, String> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.dbs[self.index].get(key) {
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    /// SET：写入字符串，`expire` 之后过期，覆盖原来的值和过期时间
+    pub fn set(&self, key: String, value: Bytes, expire: Option<Duration>) -> Result<(), String> {
+        // 先算好过期时间再加锁，EX 给得太大时 `Instant` 会溢出
+        let expires_at = match expire {
+            Some(expire) => Some(
+                Instant::now()
+                    .checked_add(expire)
+                    .ok_or_else(|| "ERR invalid expire time in 'set' command".to_string())?,
+            ),
+            None => None,
+        };
+        let mut state = self.shared.state.lock().unwrap();
+        state.reserve()?;
+        state.dbs[self.index].insert(key, Value::String(value), expires_at);
+        Ok(())
+    }
+
+    /// 超过 maxmemory 时按策略淘汰 key，腾不出空间时返回 OOM 错误
+    ///
+    /// SET、HSET 这类会新建 key 的写命令内部已经调用过，
+    /// XREADGROUP 之类只修改已有 key、但同样会占用更多内存的命令由调用者在执行前调用。
+    pub fn reserve(&self) -> Result<(), String> {
+        self.shared.state.lock().unwrap().reserve()
+    }
+
+    /// 在锁内对 `key` 对应的 stream 执行 `f`
+    ///
+    /// key 不存在时，`create` 为 true 则新建一个空 stream，否则返回 `Ok(None)`；
+    /// key 存在但不是 stream 时返回 WRONGTYPE 错误。
+    pub fn with_stream<R>(
+        &self,
+        key: &str,
+        create: bool,
+        f: impl FnOnce(&mut Stream) -> R,
+    ) -> Result<Option<R>, String> {
+        let mut state = self.shared.state.lock().unwrap();
+        if create {
+            state.reserve()?;
+            if !state.dbs[self.index].contains_key(key) {
+                state.dbs[self.index]
+                    .insert(key.to_string(), Value::Stream(Stream::default()), None);
+            }
+        }
+        state.dbs[self.index]
+            .update(key, |value| match value {
+                Value::Stream(stream) => Ok(f(stream)),
+                _ => Err(wrong_type()),
+            })
+            .transpose()
+    }
+
+    /// 在锁内读取 `key` 对应的 hash，key 不存在时返回 `Ok(None)`
+    pub fn with_hash<R>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&HashMap<Bytes, Bytes>) -> R,
+    ) -> Result<Option<R>, String> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.dbs[self.index].get(key) {
+            Some(Value::Hash(hash)) => Ok(Some(f(&hash.fields))),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    /// HSET：写入若干字段，返回新增（而不是覆盖）的字段数量
+    pub fn hset(&self, key: &str, fields: Vec<(Bytes, Bytes)>) -> Result<usize, String> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.reserve()?;
+        if !state.dbs[self.index].contains_key(key) {
+            state.dbs[self.index].insert(key.to_string(), Value::Hash(Hash::default()), None);
+        }
+        state.dbs[self.index]
+            .update(key, |value| match value {
+                Value::Hash(hash) => Ok(fields
+                    .into_iter()
+                    .filter(|(field, value)| hash.insert(field.clone(), value.clone()))
+                    .count()),
+                _ => Err(wrong_type()),
+            })
+            .expect("hash was just created")
+    }
+
+    /// HDEL：删除若干字段，返回实际删除的数量；字段删光后 key 也一并删除
+    pub fn hdel(&self, key: &str, fields: &[Bytes]) -> Result<usize, String> {
+        let mut state = self.shared.state.lock().unwrap();
+        let removed = state.dbs[self.index].update(key, |value| match value {
+            Value::Hash(hash) => {
+                let removed = fields.iter().filter(|f| hash.remove(f)).count();
+                Ok((removed, hash.is_empty()))
+            }
+            _ => Err(wrong_type()),
+        });
+        let Some((removed, empty)) = removed.transpose()? else {
+            return Ok(0);
+        };
+        if empty {
+            state.dbs[self.index].remove(key);
+        }
+        Ok(removed)
+    }
+
+    /// XADD：追加消息，可选地按 MAXLEN 裁剪，并唤醒阻塞读取的客户端
+    pub fn xadd(
+        &self,
+        key: &str,
+        id: IdSpec,
+        fields: Vec<(Bytes, Bytes)>,
+        maxlen: Option<usize>,
+    ) -> Result<StreamId, String> {
+        let id = self
+            .with_stream(key, true, |stream| {
+                let id = stream.add(id, fields)?;
+                if let Some(maxlen) = maxlen {
+                    stream.trim(maxlen);
+                }
+                Ok::<_, String>(id)
+            })?
+            .expect("stream was just created")?;
+
+        self.shared.stream_added.notify_waiters();
+        Ok(id)
+    }
+
+    /// 对每个 stream 执行 `read`，只要有一个读到了数据就返回；都没有数据时，
+    /// 如果指定了 `block` 就等待新的 XADD 再重试，直到超时（`Duration::ZERO` 表示一直等）
+    ///
+    /// XREAD 和 XREADGROUP 的阻塞逻辑相同，区别只在 `read` 怎么读。
+    pub async fn read_streams<F>(
+        &self,
+        keys: &[String],
+        block: Option<Duration>,
+        mut read: F,
+    ) -> Result<Vec<(String, Vec<Entry>)>, String>
+    where
+        F: FnMut(usize, &mut Stream) -> Result<Vec<Entry>, String>,
+    {
+        let deadline = block
+            .filter(|d| !d.is_zero())
+            .map(|d| tokio::time::Instant::now() + d);
+
+        loop {
+            // 先注册再检查，避免检查完、开始等待之前发生的 XADD 被漏掉
+            let notified = self.shared.stream_added.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let mut result = Vec::new();
+            for (i, key) in keys.iter().enumerate() {
+                if let Some(entries) = self.with_stream(key, false, |stream| read(i, stream))? {
+                    let entries = entries?;
+                    if !entries.is_empty() {
+                        result.push((key.clone(), entries));
+                    }
+                }
+            }
+
+            if !result.is_empty() || block.is_none() {
+                return Ok(result);
+            }
+
+            match deadline {
+                Some(deadline) => {
+                    if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                        return Ok(result);
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    /// 为一个连接创建用来接收订阅消息的 `Mailbox`，容量和策略来自服务端配置
+    pub fn new_subscriber(&self) -> Subscriber {
+        Arc::new(Mailbox::new(
+            self.shared.slow_consumer,
+            self.shared.pubsub_buffer,
+        ))
+    }
+
+    pub fn subscribe(&self, channel: String, subscriber: &Subscriber) {
+        let mut state = self.shared.state.lock().unwrap();
+        let subscribers = state.pub_sub.entry(channel).or_default();
+        if !subscribers.iter().any(|s| Arc::ptr_eq(s, subscriber)) {
+            subscribers.push(subscriber.clone());
+        }
+    }
+
+    pub fn unsubscribe(&self, channel: &str, subscriber: &Subscriber) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(subscribers) = state.pub_sub.get_mut(channel) {
+            subscribers.retain(|s| !Arc::ptr_eq(s, subscriber));
+            if subscribers.is_empty() {
+                state.pub_sub.remove(channel);
+            }
+        }
+    }
+
+    /// 把消息投递给频道的所有订阅者，返回投递成功的数量
+    ///
+    /// `block` 策略下，如果某个订阅者的缓冲区满了，这里会一直等到它腾出空间，
+    /// 也就是慢订阅者会拖慢发布者。各个订阅者是同时投递的，一个订阅者卡住不会耽误其他订阅者收到消息。
+    /// 发布者自己也订阅了这个频道时，等待期间由连接的主循环继续取它自己队列里的消息，不会自己等自己。
+    pub async fn publish(&self, channel: &str, message: Bytes) -> usize {
+        // 不能持有 std 的锁跨越 `.await`，先把订阅者拷贝出来
+        let subscribers = {
+            let state = self.shared.state.lock().unwrap();
+            match state.pub_sub.get(channel) {
+                Some(subscribers) => subscribers.clone(),
+                None => return 0,
+            }
+        };
+
+        let results = future::join_all(
+            subscribers
+                .iter()
+                .map(|subscriber| subscriber.send((channel.to_string(), message.clone()))),
+        )
+        .await;
+        let delivered = results.iter().filter(|res| res.is_ok()).count();
+        let closed = delivered < results.len();
+
+        // 被断开或者已经退出的订阅者顺手清理掉
+        if closed {
+            let mut state = self.shared.state.lock().unwrap();
+            if let Some(subscribers) = state.pub_sub.get_mut(channel) {
+                subscribers.retain(|s| !s.is_closed());
+                if subscribers.is_empty() {
+                    state.pub_sub.remove(channel);
+                }
+            }
+        }
+
+        delivered
+    }
+}
+
+impl State {
+    fn used_memory(&self) -> usize {
+        self.dbs.iter().map(Keyspace::used_memory).sum()
+    }
+
+    /// 写命令执行前调用：超过 maxmemory 时按策略淘汰 key，直到降回上限以内
+    ///
+    /// 每轮从每个库采样 `samples` 个 key，淘汰其中得分最高的那个。
+    /// `noeviction` 或者没有可以淘汰的 key（例如 volatile-* 策略下没有 key 设置了过期时间）时返回 OOM 错误。
+    fn reserve(&mut self) -> Result<(), String> {
+        if self.maxmemory == 0 {
+            return Ok(());
+        }
+
+        while self.used_memory() > self.maxmemory {
+            if self.policy == EvictionPolicy::NoEviction {
+                return Err(oom());
+            }
+
+            let candidate = if self.policy == EvictionPolicy::AllKeysRandom {
+                self.random_key()
+            } else {
+                self.best_sample()
+            };
+            let Some((index, key)) = candidate else {
+                return Err(oom());
+            };
+            // 已经过期的 key 算在 expired_keys 里，不算淘汰
+            if self.dbs[index].remove(&key).is_some() {
+                self.evicted_keys += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// 每个库采样 `samples` 个 key，返回其中最该淘汰的那个所在的库和 key
+    fn best_sample(&mut self) -> Option<(usize, String)> {
+        let now = Instant::now();
+        let mut best: Option<(u64, usize, String)> = None;
+        for (index, keyspace) in self.dbs.iter_mut().enumerate() {
+            for _ in 0..self.samples {
+                let Some((key, entry)) = keyspace.sample(self.policy.is_volatile()) else {
+                    break;
+                };
+                let score = self.policy.score(entry, now);
+                if best.as_ref().is_none_or(|(best, _, _)| score > *best) {
+                    best = Some((score, index, key.to_string()));
+                }
+            }
+        }
+        best.map(|(_, index, key)| (index, key))
+    }
+
+    /// allkeys-random：在所有库的所有 key 里均匀地随机挑一个
+    ///
+    /// 不能每个库各采样几个再比较，否则 key 少的库里的 key 被挑中的概率更大。
+    fn random_key(&mut self) -> Option<(usize, String)> {
+        let total: usize = self.dbs.iter().map(Keyspace::len).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut n = self.rng.below(total);
+        for (index, keyspace) in self.dbs.iter_mut().enumerate() {
+            if n < keyspace.len() {
+                return keyspace.sample(false).map(|(key, _)| (index, key.to_string()));
+            }
+            n -= keyspace.len();
+        }
+        unreachable!("n is below the total number of keys")
+    }
+}
+
+fn oom() -> String {
+    "OOM command not allowed when used memory > 'maxmemory'.".to_string()
+}
+
+fn wrong_type() -> String {
+    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+}
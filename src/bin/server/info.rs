@@ -0,0 +1,70 @@
+//! INFO 命令
+//!
+//! 和 Redis 一样返回一个 bulk string，每个部分以 `# 名字` 开头，下面是若干行 `字段:值`，
+//! 部分之间空一行。指定了部分时只返回那一部分，不认识的部分返回空字符串。
+
+use std::fmt::Write;
+
+use crate::{clients, db};
+
+/// 按顺序排列的所有部分
+const SECTIONS: &[&str] = &["server", "clients", "memory", "stats", "keyspace"];
+
+/// 渲染 `section` 部分，`None`、`all`、`default` 表示全部
+pub fn render(section: Option<&str>, clients: &clients::Stats, db: &db::Stats) -> String {
+    let sections: Vec<&str> = match section {
+        None | Some("all") | Some("default") | Some("everything") => SECTIONS.to_vec(),
+        Some(section) => SECTIONS.iter().copied().filter(|s| *s == section).collect(),
+    };
+
+    let mut out = String::new();
+    for (i, section) in sections.into_iter().enumerate() {
+        if i > 0 {
+            out.push_str("\r\n");
+        }
+        match section {
+            "server" => server(&mut out, clients),
+            "clients" => connections(&mut out, clients),
+            "memory" => memory(&mut out, db),
+            "stats" => counters(&mut out, clients, db),
+            "keyspace" => keyspace(&mut out, db),
+            _ => unreachable!(),
+        }
+    }
+    out
+}
+
+fn server(out: &mut String, clients: &clients::Stats) {
+    out.push_str("# Server\r\n");
+    write!(out, "redis_version:{}\r\n", env!("CARGO_PKG_VERSION")).unwrap();
+    write!(out, "process_id:{}\r\n", std::process::id()).unwrap();
+    write!(out, "uptime_in_seconds:{}\r\n", clients.uptime_in_seconds).unwrap();
+    write!(out, "uptime_in_days:{}\r\n", clients.uptime_in_seconds / 86400).unwrap();
+}
+
+fn connections(out: &mut String, clients: &clients::Stats) {
+    out.push_str("# Clients\r\n");
+    write!(out, "connected_clients:{}\r\n", clients.connected_clients).unwrap();
+}
+
+fn memory(out: &mut String, stats: &db::Stats) {
+    out.push_str("# Memory\r\n");
+    write!(out, "used_memory:{}\r\n", stats.used_memory).unwrap();
+    write!(out, "maxmemory:{}\r\n", stats.maxmemory).unwrap();
+    write!(out, "maxmemory_policy:{}\r\n", stats.maxmemory_policy).unwrap();
+}
+
+fn counters(out: &mut String, clients: &clients::Stats, stats: &db::Stats) {
+    out.push_str("# Stats\r\n");
+    write!(out, "total_connections_received:{}\r\n", clients.total_connections_received).unwrap();
+    write!(out, "total_commands_processed:{}\r\n", clients.total_commands_processed).unwrap();
+    write!(out, "expired_keys:{}\r\n", stats.expired_keys).unwrap();
+    write!(out, "evicted_keys:{}\r\n", stats.evicted_keys).unwrap();
+}
+
+fn keyspace(out: &mut String, stats: &db::Stats) {
+    out.push_str("# Keyspace\r\n");
+    for (index, keys, expires) in &stats.keyspace {
+        write!(out, "db{}:keys={},expires={}\r\n", index, keys, expires).unwrap();
+    }
+}
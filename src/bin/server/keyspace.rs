@@ -0,0 +1,219 @@
+//! 一个库里的所有 key
+//!
+//! 除了按名字查找，淘汰时还要能随机挑 key，`HashMap` 做不到，所以另外用 `Vec` 存一份 key，
+//! `Entry` 记着自己在 `Vec` 中的下标，删除时 swap_remove。设置了过期时间的 key 再单独存一份，
+//! volatile-* 策略只在它们中间采样。
+//!
+//! 过期是惰性的：访问到已经过期的 key 时才删除，当作不存在。
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use bytes::Bytes;
+
+use crate::memory::{Rng, Usage};
+use crate::stream::Stream;
+
+// 每个 key 的固定开销：哈希表的槽位、`Entry` 本身、两个索引里的 key
+const ENTRY_OVERHEAD: usize = 96;
+// hash 里每个字段的固定开销
+const FIELD_OVERHEAD: usize = 48;
+
+/// keyspace 中的值
+pub enum Value {
+    String(Bytes),
+    Hash(Hash),
+    Stream(Stream),
+}
+
+/// 记着字段总字节数的 hash，估算内存时不用每次都遍历一遍
+#[derive(Default)]
+pub struct Hash {
+    pub fields: HashMap<Bytes, Bytes>,
+    bytes: usize,
+}
+
+pub struct Entry {
+    pub value: Value,
+    /// 估算的内存占用，包括 key 本身
+    pub size: usize,
+    pub expires_at: Option<Instant>,
+    pub usage: Usage,
+    // 在 `Keyspace::keys` / `Keyspace::volatile` 中的下标
+    slot: usize,
+    volatile_slot: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct Keyspace {
+    entries: HashMap<String, Entry>,
+    keys: Vec<String>,
+    volatile: Vec<String>,
+    used_memory: usize,
+    expired_keys: u64,
+    rng: Rng,
+}
+
+impl Value {
+    fn memory_usage(&self) -> usize {
+        match self {
+            Value::String(value) => value.len(),
+            Value::Hash(hash) => hash.bytes,
+            Value::Stream(stream) => stream.memory_usage(),
+        }
+    }
+}
+
+impl Hash {
+    /// 写入一个字段，返回它是不是新增的
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> bool {
+        let (field_len, value_len) = (field.len(), value.len());
+        match self.fields.insert(field, value) {
+            Some(old) => {
+                self.bytes = self.bytes + value_len - old.len();
+                false
+            }
+            None => {
+                self.bytes += field_len + value_len + FIELD_OVERHEAD;
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, field: &Bytes) -> bool {
+        match self.fields.remove(field) {
+            Some(value) => {
+                self.bytes -= field.len() + value.len() + FIELD_OVERHEAD;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl Keyspace {
+    /// 查找 `key` 并记一次访问，已经过期的删掉后当作不存在
+    pub fn get(&mut self, key: &str) -> Option<&mut Value> {
+        let now = Instant::now();
+        self.expire_if_needed(key, now);
+        let entry = self.entries.get_mut(key)?;
+        entry.usage.touch(now, &mut self.rng);
+        Some(&mut entry.value)
+    }
+
+    /// 修改 `key` 的值，改完重新估算它占用的内存
+    pub fn update<R>(&mut self, key: &str, f: impl FnOnce(&mut Value) -> R) -> Option<R> {
+        let result = f(self.get(key)?);
+
+        let entry = self.entries.get_mut(key).unwrap();
+        let size = entry_size(key, &entry.value);
+        self.used_memory = self.used_memory + size - entry.size;
+        entry.size = size;
+        Some(result)
+    }
+
+    pub fn contains_key(&mut self, key: &str) -> bool {
+        self.expire_if_needed(key, Instant::now());
+        self.entries.contains_key(key)
+    }
+
+    /// 写入 `key`，覆盖原来的值和过期时间
+    pub fn insert(&mut self, key: String, value: Value, expires_at: Option<Instant>) {
+        self.remove(&key);
+
+        let size = entry_size(&key, &value);
+        self.used_memory += size;
+        self.keys.push(key.clone());
+        let volatile_slot = expires_at.map(|_| {
+            self.volatile.push(key.clone());
+            self.volatile.len() - 1
+        });
+        let entry = Entry {
+            value,
+            size,
+            expires_at,
+            usage: Usage::new(Instant::now()),
+            slot: self.keys.len() - 1,
+            volatile_slot,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// 删除 `key`，已经过期的也会删掉，但返回 `None`
+    pub fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.used_memory -= entry.size;
+
+        // 末尾的 key 挪到被删的位置上，记得更新它的下标
+        self.keys.swap_remove(entry.slot);
+        if let Some(moved) = self.keys.get(entry.slot) {
+            self.entries.get_mut(moved).unwrap().slot = entry.slot;
+        }
+        if let Some(slot) = entry.volatile_slot {
+            self.volatile.swap_remove(slot);
+            if let Some(moved) = self.volatile.get(slot) {
+                self.entries.get_mut(moved).unwrap().volatile_slot = Some(slot);
+            }
+        }
+
+        if entry.expires_at.is_some_and(|at| at <= Instant::now()) {
+            self.expired_keys += 1;
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// 随机挑一个 key，`volatile` 为 true 时只在设置了过期时间的 key 里挑
+    pub fn sample(&mut self, volatile: bool) -> Option<(&str, &Entry)> {
+        let keys = if volatile { &self.volatile } else { &self.keys };
+        if keys.is_empty() {
+            return None;
+        }
+        let key = &keys[self.rng.below(keys.len())];
+        Some((key, &self.entries[key]))
+    }
+
+    /// FLUSHDB：清空所有 key，返回被清掉的数据，调用者可以在锁外释放
+    pub fn clear(&mut self) -> HashMap<String, Entry> {
+        self.keys.clear();
+        self.volatile.clear();
+        self.used_memory = 0;
+        std::mem::take(&mut self.entries)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// 设置了过期时间的 key 的数量，包括已经过期但还没被删掉的
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.len()
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys
+    }
+
+    fn expire_if_needed(&mut self, key: &str, now: Instant) {
+        let expired = self
+            .entries
+            .get(key)
+            .and_then(|entry| entry.expires_at)
+            .is_some_and(|at| at <= now);
+        if expired {
+            self.remove(key);
+        }
+    }
+}
+
+fn entry_size(key: &str, value: &Value) -> usize {
+    ENTRY_OVERHEAD + key.len() + value.memory_usage()
+}
@@ -0,0 +1,870 @@
+mod acl;
+mod clients;
+mod cmd;
+mod config;
+mod connection;
+mod db;
+mod info;
+mod keyspace;
+mod memory;
+mod slowlog;
+mod stream;
+
+use std::collections::HashSet;
+use std::fs;
+use std::future::poll_fn;
+use std::pin::pin;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use my_redis_project::codec::{self, RespCodec};
+// `Frame` 用于表示 Redis 的数据帧，在 `mini_redis::Frame` 的基础上增加了 RESP3 的类型
+use my_redis_project::frame::{Frame, Protocol};
+use my_redis_project::mailbox::Recv;
+use my_redis_project::tls::{self, TlsAcceptor};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream, unix};
+
+use acl::Acl;
+use clients::{Client, Clients, Monitor};
+use cmd::{Command, GroupReadFrom, ReadFrom};
+use config::Config;
+use connection::Connection;
+use db::{Db, Subscriber};
+use slowlog::SlowLog;
+use stream::{StreamId, entries_frame, id_frame};
+
+// 这么久还没完成 TLS 握手就断开，免得只连不握手的客户端一直占着连接
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::main]
+async fn main() {
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let acl = match build_acl(&config) {
+        Ok(acl) => acl,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(cert, key).unwrap_or_else(|e| {
+            eprintln!("failed to load TLS certificate: {}", e);
+            std::process::exit(1);
+        })),
+        _ => None,
+    };
+
+    // Bind the listener to the address
+    // 监听指定地址，等待 TCP 连接进来；`--addr none` 时只监听 Unix socket
+    let listener = match &config.addr {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr).await.unwrap();
+            let tls_note = if tls.is_some() { " (TLS)" } else { "" };
+            println!("Listening on {}{}", listener.local_addr().unwrap(), tls_note);
+            Some(listener)
+        }
+        None => None,
+    };
+    println!(
+        "Slow subscribers: {} (buffer {} messages)",
+        config.slow_consumer, config.pubsub_buffer
+    );
+
+    if config.maxmemory > 0 {
+        println!("Max memory: {} bytes ({})", config.maxmemory, config.maxmemory_policy);
+    }
+
+    let db = Db::new(&config);
+    let codec = RespCodec::new()
+        .max_bulk_len(config.proto_max_bulk_len)
+        .max_multibulk_len(config.proto_max_multibulk_len)
+        .max_depth(config.proto_max_depth)
+        // 让 `nc`、`telnet` 之类的工具可以直接敲命令
+        .inline_commands(true);
+    // 每条连接登记一下，CLIENT LIST / KILL、INFO 和 MONITOR 都要用
+    let slowlog = SlowLog::new(config.slowlog_log_slower_than, config.slowlog_max_len);
+    let clients = Clients::new(slowlog);
+
+    // Unix socket 用文件权限控制访问，sidecar 之类的同机进程走它可以不暴露 TCP 端口
+    let unix_listener = config.unixsocket.as_ref().map(|path| {
+        let listener = bind_unix(path, config.unixsocketperm).unwrap_or_else(|e| {
+            eprintln!("failed to listen on {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        println!("Listening on {} (mode {:o})", path.display(), config.unixsocketperm);
+        listener
+    });
+
+    loop {
+        tokio::select! {
+            res = accept_tcp(listener.as_ref()) => {
+                // 第二个项中包含有新连接的 `IP` 和端口信息
+                let Some((socket, addr)) = accepted(res).await else {
+                    continue;
+                };
+                println!("Accepted connection from {}", addr);
+                let client = clients.register(addr.to_string());
+                match &tls {
+                    Some(acceptor) => {
+                        spawn_tls_connection(acceptor.clone(), socket, codec.clone(), client, db.clone(), acl.clone())
+                    }
+                    None => spawn_connection(socket, codec.clone(), client, db.clone(), acl.clone()),
+                }
+            }
+            res = accept_unix(unix_listener.as_ref()) => {
+                let Some((socket, _)) = accepted(res).await else {
+                    continue;
+                };
+                let path = config.unixsocket.as_ref().unwrap().display();
+                println!("Accepted connection on {}", path);
+                // 和 Redis 一样，Unix socket 上的连接地址写成 `路径:0`
+                let client = clients.register(format!("{}:0", path));
+                spawn_connection(socket, codec.clone(), client, db.clone(), acl.clone());
+            }
+        }
+    }
+}
+
+/// 按启动参数创建用户，`--requirepass` 和 Redis 一样是给 `default` 用户设置密码
+fn build_acl(config: &Config) -> Result<Acl, String> {
+    let acl = Acl::new();
+    if let Some(password) = &config.requirepass {
+        acl.set_user("default", &[format!(">{}", password)])?;
+    }
+    for (name, rules) in &config.users {
+        acl.set_user(name, rules)?;
+    }
+    Ok(acl)
+}
+
+/// 监听 Unix socket，上次没清理掉的 socket 文件先删掉，否则 bind 会失败
+fn bind_unix(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+/// 没有监听 TCP 时永远等下去，`select!` 里就只剩 Unix socket 的分支
+async fn accept_tcp(listener: Option<&TcpListener>) -> io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 没有配置 Unix socket 时永远不返回，`select!` 里就只剩 TCP 一个分支
+async fn accept_unix(listener: Option<&UnixListener>) -> io::Result<(UnixStream, unix::SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// `accept` 出错（例如文件描述符用完了）时打印出来，歇一会儿再继续接受连接，不让整个服务退出
+async fn accepted<T>(res: io::Result<T>) -> Option<T> {
+    match res {
+        Ok(accepted) => Some(accepted),
+        Err(e) => {
+            eprintln!("accept error: {}", e);
+            // 马上重试多半还是同样的错误，只会空转
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            None
+        }
+    }
+}
+
+fn spawn_connection<S>(socket: S, codec: RespCodec, client: Client, db: Db, acl: Acl)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // 这里的 `tokio::spawn` 是一个异步函数，它会在后台运行一个新的任务
+    // 为每一条连接都生成一个新的任务，
+    // `socket` 的所有权将被移动到新的任务中，并在那里进行处理
+    tokio::spawn(async move {
+        // spawn 了一个新的任务来处理这个连接
+        if let Err(e) = process(socket, codec, client, db, acl).await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+    // 这里的 `process` 函数是一个异步函数，返回一个 `Future`，而不是直接返回一个值
+}
+
+/// 先完成 TLS 握手再处理连接，握手放在连接自己的任务里，慢吞吞的客户端不会卡住 accept
+fn spawn_tls_connection(acceptor: TlsAcceptor, socket: TcpStream, codec: RespCodec, client: Client, db: Db, acl: Acl) {
+    tokio::spawn(async move {
+        let socket = match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+            Ok(Ok(socket)) => socket,
+            Ok(Err(e)) => {
+                eprintln!("TLS handshake with {} failed: {}", client.addr(), e);
+                return;
+            }
+            Err(_) => {
+                eprintln!("TLS handshake with {} timed out", client.addr());
+                return;
+            }
+        };
+        if let Err(e) = process(socket, codec, client, db, acl).await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+}
+
+/// 处理一条连接，TCP、TLS 和 Unix socket 上的都一样
+async fn process<S>(socket: S, codec: RespCodec, client: Client, db: Db, acl: Acl) -> mini_redis::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // 使用返回的 `connection` 可以从 socket 中读取数据并解析为数据帧
+    let mut connection = Connection::new(socket, codec);
+
+    // 这条连接订阅的频道，以及接收订阅消息的队列
+    let mut channels = HashSet::new();
+    let subscriber = db.new_subscriber();
+    // 登录的用户，`default` 用户不需要密码时直接登录
+    let mut user = acl.default_user();
+    client.set_user(user.clone());
+    // SELECT 会换成指向别的库的 `Db`
+    let mut db = db;
+
+    let result = tokio::select! {
+        result = serve(&mut connection, &client, &mut db, &acl, &mut user, &subscriber, &mut channels) => result,
+        // 被 CLIENT KILL 了，直接断开
+        () = client.killed() => Ok(()),
+    };
+
+    // 连接断开后不再接收消息，把它从所有频道中移除
+    subscriber.close();
+    for channel in &channels {
+        db.unsubscribe(channel, &subscriber);
+    }
+
+    // 协议错误时和 Redis 一样先告诉客户端哪里错了再断开，用 `nc` 手敲命令时能看到原因
+    if let Err(e) = &result
+        && let Some(codec::Error::Protocol(msg)) = e.downcast_ref()
+    {
+        let frame = Frame::Error(format!("ERR Protocol error: {}", msg));
+        let _ = connection.write_frame(&frame).await;
+    }
+
+    result
+}
+
+async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    client: &Client,
+    db: &mut Db,
+    acl: &Acl,
+    user: &mut Option<String>,
+    subscriber: &Subscriber,
+    channels: &mut HashSet<String>,
+) -> mini_redis::Result<()> {
+    loop {
+        // 没有订阅时只需要等客户端发命令；订阅后还要同时把频道里的消息推给客户端。
+        // `read_frame` 只会把读到的数据追加进内部缓冲区，被 `select!` 取消也不会丢数据
+        let frame = if channels.is_empty() {
+            connection.read_frame().await?
+        } else {
+            tokio::select! {
+                frame = connection.read_frame() => frame?,
+                message = poll_fn(|cx| subscriber.poll_recv(cx)) => {
+                    connection.write_frame(&push_frame(message)?).await?;
+                    continue;
+                }
+            }
+        };
+
+        // 使用 `read_frame` 方法从连接获取一个数据帧：一条redis命令 + 相应的数据
+        let Some(frame) = frame else {
+            return Ok(());
+        };
+
+        // MONITOR 和 SLOWLOG 要用原始参数，都用不上时就不必留一份
+        let clients = client.clients();
+        let args = (clients.has_monitors() || clients.slowlog().is_enabled()).then(|| frame.clone());
+
+        let command = match Command::from_frame(frame) {
+            Ok(command) => command,
+            Err(e) => {
+                connection.write_frame(&Frame::Error(e)).await?;
+                continue;
+            }
+        };
+
+        // 没有登录或者没有权限时直接拒绝
+        if let Err(e) = acl.check(user.as_deref(), &command) {
+            connection.write_frame(&Frame::Error(e)).await?;
+            continue;
+        }
+
+        if !channels.is_empty()
+            && connection.protocol() == Protocol::Resp2
+            && !matches!(command, Command::Hello { .. } | Command::Subscribe { .. } | Command::Unsubscribe { .. } | Command::Ping { .. })
+        {
+            // 和 Redis 一样，RESP2 的订阅状态下只允许 pub/sub 相关的命令，
+            // 因为客户端分不清收到的数组是命令的回复还是推送的消息。RESP3 有专门的推送帧，就没有这个限制
+            connection.write_frame(&Frame::Error("ERR only (UN)SUBSCRIBE / PING are allowed in this context".to_string())).await?;
+            continue;
+        }
+
+        // 只有真正执行的命令才发给 MONITOR、记慢日志，密码换成 `(redacted)`
+        client.begin(command.name());
+        let args = args.map(|args| redact(&command, args));
+        if let Some(args) = &args
+            && clients.has_monitors()
+        {
+            clients.feed(db.index(), client.addr(), args).await;
+        }
+        // 只计执行命令的时间，写回复的时间不算，否则读得慢的客户端会让命令看起来很慢；
+        // 阻塞读等待新消息的时间也不算
+        let timed = args.filter(|_| {
+            !matches!(command, Command::XRead { block: Some(_), .. } | Command::XReadGroup { block: Some(_), .. })
+        });
+        let started = Instant::now();
+        // MONITOR 回复 OK 之后就进入只推送的模式
+        let mut monitoring = None;
+
+        // 先执行命令得到所有回复，再统一写回去
+        // pub/sub 相关的回复都用 `Frame::Push`：RESP3 连接上是推送帧，RESP2 连接上会按普通数组编码
+        let replies = match command {
+            Command::Hello { protover, auth, setname } => {
+                let protocol = match protover {
+                    None => Some(connection.protocol()),
+                    Some(2) => Some(Protocol::Resp2),
+                    Some(3) => Some(Protocol::Resp3),
+                    Some(_) => None,
+                };
+                match protocol {
+                    Some(protocol) => match hello_options(acl, client, user, auth, setname) {
+                        Ok(()) => {
+                            // 先切换协议，HELLO 的回复本身就按新协议编码
+                            connection.set_protocol(protocol);
+                            vec![hello_frame(client.id(), protocol)]
+                        }
+                        Err(e) => vec![Frame::Error(e)],
+                    },
+                    None => vec![Frame::Error("NOPROTO unsupported protocol version".to_string())],
+                }
+            }
+            Command::Subscribe { channels: to_add } => {
+                // 每个频道都要回复一条确认：[ "subscribe", 频道名, 当前订阅数 ]
+                let mut replies = Vec::new();
+                for channel in to_add {
+                    db.subscribe(channel.clone(), subscriber);
+                    channels.insert(channel.clone());
+                    replies.push(Frame::Push(vec![
+                        bulk("subscribe"),
+                        Frame::Bulk(Bytes::from(channel)),
+                        Frame::Integer(channels.len() as i64),
+                    ]));
+                }
+                replies
+            }
+            Command::Unsubscribe { channels: mut to_remove } => {
+                if to_remove.is_empty() {
+                    to_remove = channels.iter().cloned().collect();
+                }
+                let mut replies = Vec::new();
+                for channel in to_remove {
+                    db.unsubscribe(&channel, subscriber);
+                    channels.remove(&channel);
+                    replies.push(Frame::Push(vec![
+                        bulk("unsubscribe"),
+                        Frame::Bulk(Bytes::from(channel)),
+                        Frame::Integer(channels.len() as i64),
+                    ]));
+                }
+                // 什么都没订阅时也要回复，和 Redis 一样频道名是 nil，否则客户端会一直等下去
+                if replies.is_empty() {
+                    replies.push(Frame::Push(vec![bulk("unsubscribe"), Frame::Null, Frame::Integer(0)]));
+                }
+                replies
+            }
+            Command::Auth { username, password } => {
+                let frame = match acl.authenticate(username.as_deref(), &password) {
+                    Ok(name) => {
+                        *user = Some(name);
+                        client.set_user(user.clone());
+                        Frame::Simple("OK".to_string())
+                    }
+                    Err(e) => Frame::Error(e),
+                };
+                vec![frame]
+            }
+            Command::AclWhoami => vec![Frame::Bulk(Bytes::from(user.clone().unwrap_or_default()))],
+            Command::AclList => {
+                vec![Frame::Array(acl.list().into_iter().map(|line| Frame::Bulk(Bytes::from(line))).collect())]
+            }
+            Command::AclSetUser { username, rules } => {
+                let frame = match acl.set_user(&username, &rules) {
+                    Ok(()) => Frame::Simple("OK".to_string()),
+                    Err(e) => Frame::Error(e),
+                };
+                vec![frame]
+            }
+            Command::Select { index } => {
+                let frame = match db.select(index) {
+                    Ok(selected) => {
+                        *db = selected;
+                        client.set_db(index);
+                        Frame::Simple("OK".to_string())
+                    }
+                    Err(e) => Frame::Error(e),
+                };
+                vec![frame]
+            }
+            Command::Info { section } => {
+                let info = info::render(section.as_deref(), &clients.stats(), &db.stats());
+                vec![Frame::Bulk(Bytes::from(info))]
+            }
+            Command::ClientId => vec![Frame::Integer(client.id() as i64)],
+            Command::ClientSetName { name } => {
+                let frame = match client.set_name(name) {
+                    Ok(()) => Frame::Simple("OK".to_string()),
+                    Err(e) => Frame::Error(e),
+                };
+                vec![frame]
+            }
+            Command::ClientGetName => {
+                let frame = match client.name() {
+                    Some(name) => Frame::Bulk(Bytes::from(name)),
+                    None => Frame::Null,
+                };
+                vec![frame]
+            }
+            Command::ClientList => vec![Frame::Bulk(Bytes::from(clients.list()))],
+            Command::ClientKill { filter, legacy } => {
+                let killed = clients.kill(&filter, client.id());
+                let frame = match (legacy, killed) {
+                    (false, killed) => Frame::Integer(killed as i64),
+                    (true, 0) => Frame::Error("ERR No such client".to_string()),
+                    (true, _) => Frame::Simple("OK".to_string()),
+                };
+                vec![frame]
+            }
+            Command::Monitor => {
+                monitoring = Some(client.monitor());
+                vec![Frame::Simple("OK".to_string())]
+            }
+            Command::SlowLogGet { count } => vec![clients.slowlog().get(count)],
+            Command::SlowLogLen => vec![Frame::Integer(clients.slowlog().len() as i64)],
+            Command::SlowLogReset => {
+                clients.slowlog().reset();
+                vec![Frame::Simple("OK".to_string())]
+            }
+            command => vec![forwarding(connection, subscriber, !channels.is_empty(), apply(command, db)).await?],
+        };
+
+        if let Some(args) = &timed {
+            client.finish(args, started.elapsed());
+        }
+
+        // 将请求响应返回给客户端
+        for reply in &replies {
+            connection.write_frame(reply).await?;
+        }
+        if let Some(monitor_queue) = monitoring {
+            return monitor(connection, &monitor_queue).await;
+        }
+    }
+}
+
+/// 把命令参数里的密码换成 `(redacted)`：AUTH 的全部参数、HELLO 的 AUTH 选项、ACL SETUSER 的规则
+fn redact(command: &Command, args: Frame) -> Frame {
+    let Frame::Array(mut parts) = args else {
+        return args;
+    };
+    let secret = match command {
+        Command::Auth { .. } => 1..parts.len(),
+        Command::AclSetUser { .. } => 3.min(parts.len())..parts.len(),
+        Command::Hello { auth: Some(_), .. } => {
+            // 跳过 SETNAME 的参数，免得把名字叫 auth 的连接名当成选项
+            let is = |i: usize, name: &[u8]| matches!(&parts[i], Frame::Bulk(b) if b.eq_ignore_ascii_case(name));
+            let mut i = 2;
+            while i < parts.len() && !is(i, b"auth") {
+                i += if is(i, b"setname") { 2 } else { 1 };
+            }
+            (i + 1).min(parts.len())..(i + 3).min(parts.len())
+        }
+        _ => return Frame::Array(parts),
+    };
+    for part in &mut parts[secret] {
+        *part = bulk("(redacted)");
+    }
+    Frame::Array(parts)
+}
+
+/// 执行一条命令，已经订阅了频道的话，等待结果的同时继续把订阅消息推给客户端
+///
+/// block 策略下 PUBLISH 要等订阅者的队列腾出空间。RESP3 连接可以一边订阅一边 PUBLISH，
+/// 如果这时没人取自己队列里的消息，向自己订阅的频道发布、或者两个连接互相发布就会永远等下去。
+/// 能走到这里的订阅连接一定是 RESP3 的，推送帧可以夹在命令回复之前。
+async fn forwarding<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    subscriber: &Subscriber,
+    subscribed: bool,
+    execute: impl Future<Output = Frame>,
+) -> mini_redis::Result<Frame> {
+    if !subscribed {
+        return Ok(execute.await);
+    }
+
+    let mut execute = pin!(execute);
+    loop {
+        tokio::select! {
+            frame = &mut execute => return Ok(frame),
+            message = poll_fn(|cx| subscriber.poll_recv(cx)) => {
+                connection.write_frame(&push_frame(message)?).await?;
+            }
+        }
+    }
+}
+
+/// 把订阅队列里取出的内容转成推送帧，按 `disconnect` 策略被踢掉时返回错误
+fn push_frame(message: Option<Recv<(String, Bytes)>>) -> mini_redis::Result<Frame> {
+    match message {
+        Some(Recv::Item((channel, payload))) => Ok(message_frame(channel, payload)),
+        // 这不是 Redis 协议的一部分：告诉客户端有多少条消息因为它消费太慢被丢掉了
+        Some(Recv::Lagged(n)) => Ok(Frame::Push(vec![bulk("lagged"), Frame::Integer(n as i64)])),
+        None => Err("subscriber too slow, disconnecting".into()),
+    }
+}
+
+/// MONITOR 之后连接只用来推送其他连接执行的命令，直到客户端断开
+async fn monitor<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    monitor: &Monitor,
+) -> mini_redis::Result<()> {
+    loop {
+        tokio::select! {
+            frame = connection.read_frame() => {
+                if frame?.is_none() {
+                    return Ok(());
+                }
+                let frame = Frame::Error("ERR only streaming is allowed in MONITOR mode".to_string());
+                connection.write_frame(&frame).await?;
+            }
+            line = poll_fn(|cx| monitor.poll_recv(cx)) => match line {
+                Some(Recv::Item(line)) => connection.write_frame(&Frame::Simple(line)).await?,
+                // 跟不上时丢掉的命令就不补了
+                Some(Recv::Lagged(_)) => {}
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+/// 执行普通的（非订阅类）命令，返回要回复给客户端的帧
+async fn apply(command: Command, db: &Db) -> Frame {
+    match command {
+        Command::Set { key, value, expire } => match db.set(key, value, expire) {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(e) => Frame::Error(e),
+        },
+        Command::Get { key } => match db.get(&key) {
+            // `Frame::Bulk` 期待数据的类型是 `Bytes`， 该类型会在后面章节讲解，
+            // 此时，你只要知道 `&Vec<u8>` 可以使用 `into()` 方法转换成 `Bytes` 类型
+            Ok(Some(value)) => Frame::Bulk(value),
+            Ok(None) => Frame::Null,
+            Err(e) => Frame::Error(e),
+        },
+        Command::Publish { channel, message } => {
+            let delivered = db.publish(&channel, message).await;
+            Frame::Integer(delivered as i64)
+        }
+        Command::Ping { message: Some(message) } => Frame::Bulk(message),
+        Command::Ping { message: None } => Frame::Simple("PONG".to_string()),
+        Command::HSet { key, fields } => match db.hset(&key, fields) {
+            Ok(added) => Frame::Integer(added as i64),
+            Err(e) => Frame::Error(e),
+        },
+        Command::HGet { key, field } => match db.with_hash(&key, |h| h.get(&field).cloned()) {
+            Ok(Some(Some(value))) => Frame::Bulk(value),
+            Ok(_) => Frame::Null,
+            Err(e) => Frame::Error(e),
+        },
+        Command::HGetAll { key } => match db.with_hash(&key, |h| h.clone()) {
+            // RESP3 下是 map，RESP2 下退化成 [field1, value1, field2, value2, ...]
+            Ok(hash) => Frame::Map(
+                hash.unwrap_or_default()
+                    .into_iter()
+                    .map(|(field, value)| (Frame::Bulk(field), Frame::Bulk(value)))
+                    .collect(),
+            ),
+            Err(e) => Frame::Error(e),
+        },
+        Command::HDel { key, fields } => match db.hdel(&key, &fields) {
+            Ok(removed) => Frame::Integer(removed as i64),
+            Err(e) => Frame::Error(e),
+        },
+        Command::Move { key, db: to } => match db.move_key(&key, to) {
+            Ok(moved) => Frame::Integer(moved as i64),
+            Err(e) => Frame::Error(e),
+        },
+        Command::SwapDb { a, b } => match db.swap(a, b) {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(e) => Frame::Error(e),
+        },
+        Command::FlushDb => {
+            db.flush();
+            Frame::Simple("OK".to_string())
+        }
+        Command::Hello { .. }
+        | Command::Subscribe { .. }
+        | Command::Unsubscribe { .. }
+        | Command::Auth { .. }
+        | Command::AclWhoami
+        | Command::AclList
+        | Command::AclSetUser { .. }
+        | Command::Select { .. }
+        | Command::Info { .. }
+        | Command::ClientId
+        | Command::ClientSetName { .. }
+        | Command::ClientGetName
+        | Command::ClientList
+        | Command::ClientKill { .. }
+        | Command::Monitor
+        | Command::SlowLogGet { .. }
+        | Command::SlowLogLen
+        | Command::SlowLogReset => {
+            unreachable!("connection level commands are handled by the connection loop")
+        }
+        command => match apply_stream(command, db).await {
+            Ok(frame) => frame,
+            Err(e) => Frame::Error(e),
+        },
+    }
+}
+
+/// 执行 stream 相关的命令
+async fn apply_stream(command: Command, db: &Db) -> Result<Frame, String> {
+    let frame = match command {
+        Command::XAdd {
+            key,
+            id,
+            maxlen,
+            fields,
+        } => id_frame(db.xadd(&key, id, fields, maxlen)?),
+        Command::XRange {
+            key,
+            start,
+            end,
+            count,
+            rev,
+        } => {
+            let entries = db.with_stream(&key, false, |s| s.range(start, end, count, rev))?;
+            entries_frame(entries.unwrap_or_default())
+        }
+        Command::XLen { key } => {
+            let len = db.with_stream(&key, false, |s| s.len())?;
+            Frame::Integer(len.unwrap_or(0) as i64)
+        }
+        Command::XTrim { key, maxlen } => {
+            let removed = db.with_stream(&key, false, |s| s.trim(maxlen))?;
+            Frame::Integer(removed.unwrap_or(0) as i64)
+        }
+        Command::XRead {
+            count,
+            block,
+            streams,
+        } => {
+            // `$` 要在阻塞之前确定下来，否则等待期间新增的消息也会被当成“旧消息”跳过
+            let mut keys = Vec::with_capacity(streams.len());
+            let mut after = Vec::with_capacity(streams.len());
+            for (key, from) in streams {
+                after.push(match from {
+                    ReadFrom::After(id) => id,
+                    ReadFrom::Last => db
+                        .with_stream(&key, false, |s| s.last_id())?
+                        .unwrap_or(StreamId::MIN),
+                });
+                keys.push(key);
+            }
+
+            let result = db
+                .read_streams(&keys, block, |i, s| Ok(s.read_after(after[i], count)))
+                .await?;
+            streams_frame(result)
+        }
+        Command::XGroupCreate {
+            key,
+            group,
+            start,
+            mkstream,
+        } => {
+            // 新的组和它的 PEL 也占内存，和其他写命令一样先检查 maxmemory
+            db.reserve()?;
+            let created = db.with_stream(&key, mkstream, |s| {
+                let start = match start {
+                    ReadFrom::After(id) => id,
+                    ReadFrom::Last => s.last_id(),
+                };
+                s.create_group(group, start)
+            })?;
+            match created {
+                Some(result) => {
+                    result?;
+                    Frame::Simple("OK".to_string())
+                }
+                None => {
+                    return Err(
+                        "ERR The XGROUP subcommand requires the key to exist. \
+                         Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."
+                            .to_string(),
+                    );
+                }
+            }
+        }
+        Command::XGroupDestroy { key, group } => {
+            let destroyed = db.with_stream(&key, false, |s| s.destroy_group(&group))?;
+            Frame::Integer(destroyed.unwrap_or(false) as i64)
+        }
+        Command::XReadGroup {
+            group,
+            consumer,
+            count,
+            block,
+            noack,
+            streams,
+        } => {
+            // 读到的消息会记进 PEL，是写操作
+            if !noack {
+                db.reserve()?;
+            }
+            // 组不存在时直接报错，而不是一直阻塞下去
+            for (key, _) in &streams {
+                if db.with_stream(key, false, |s| s.has_group(&group))? != Some(true) {
+                    return Err(format!(
+                        "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                        key, group
+                    ));
+                }
+            }
+
+            let (keys, from): (Vec<_>, Vec<_>) = streams.into_iter().unzip();
+            // 读取历史（PEL）时即使没有数据也不阻塞，和 Redis 一致
+            let block = if from.iter().all(|f| matches!(f, GroupReadFrom::New)) {
+                block
+            } else {
+                None
+            };
+            let result = db
+                .read_streams(&keys, block, |i, s| match from[i] {
+                    GroupReadFrom::New => s.read_group_new(&group, &consumer, count, noack),
+                    GroupReadFrom::Pending(after) => {
+                        s.read_group_pending(&group, &consumer, after, count)
+                    }
+                })
+                .await?;
+            streams_frame(result)
+        }
+        Command::XAck { key, group, ids } => {
+            let acked = db.with_stream(&key, false, |s| s.ack(&group, &ids))?;
+            Frame::Integer(acked.transpose()?.unwrap_or(0) as i64)
+        }
+        Command::XPending { key, group, query } => {
+            let pending = db.with_stream(&key, false, |s| match &query {
+                Some(query) => s.pending_range(&group, query),
+                None => s.pending_summary(&group),
+            })?;
+            match pending {
+                Some(frame) => frame?,
+                None => return Err(format!("NOGROUP No such key '{}' or consumer group '{}'", key, group)),
+            }
+        }
+        Command::XClaim {
+            key,
+            group,
+            consumer,
+            min_idle,
+            ids,
+            justid,
+        } => {
+            let claimed = db
+                .with_stream(&key, false, |s| s.claim(&group, &consumer, min_idle, &ids))?
+                .transpose()?
+                .unwrap_or_default();
+            if justid {
+                Frame::Array(claimed.into_iter().map(|(id, _)| id_frame(id)).collect())
+            } else {
+                entries_frame(claimed)
+            }
+        }
+        command => unreachable!("not a stream command: {:?}", command),
+    };
+
+    Ok(frame)
+}
+
+/// XREAD / XREADGROUP 的回复：[[key, entries], ...]，没有数据时返回 nil
+fn streams_frame(result: Vec<(String, Vec<stream::Entry>)>) -> Frame {
+    if result.is_empty() {
+        return Frame::Null;
+    }
+    Frame::Array(
+        result
+            .into_iter()
+            .map(|(key, entries)| Frame::Array(vec![Frame::Bulk(Bytes::from(key)), entries_frame(entries)]))
+            .collect(),
+    )
+}
+
+fn message_frame(channel: String, payload: Bytes) -> Frame {
+    Frame::Push(vec![bulk("message"), Frame::Bulk(Bytes::from(channel)), Frame::Bulk(payload)])
+}
+
+/// HELLO 的 AUTH 和 SETNAME 选项：和 Redis 一样先检查名字、再登录、最后改名字，
+/// 任何一步失败都不切换协议
+fn hello_options(
+    acl: &Acl,
+    client: &Client,
+    user: &mut Option<String>,
+    auth: Option<(String, String)>,
+    setname: Option<String>,
+) -> Result<(), String> {
+    if let Some(name) = &setname {
+        clients::check_name(name)?;
+    }
+    if let Some((username, password)) = auth {
+        *user = Some(acl.authenticate(Some(&username), &password)?);
+        client.set_user(user.clone());
+    }
+    if let Some(name) = setname {
+        client.set_name(name)?;
+    }
+    Ok(())
+}
+
+/// HELLO 的回复，描述服务端信息和当前协议版本
+fn hello_frame(id: u64, protocol: Protocol) -> Frame {
+    let pair = |key: &'static str, value: Frame| (bulk(key), value);
+    Frame::Map(vec![
+        pair("server", bulk("my-redis-project")),
+        pair("version", bulk(env!("CARGO_PKG_VERSION"))),
+        pair("proto", Frame::Integer(protocol.version())),
+        pair("id", Frame::Integer(id as i64)),
+        pair("mode", bulk("standalone")),
+        pair("role", bulk("master")),
+        pair("modules", Frame::Array(vec![])),
+    ])
+}
+
+fn bulk(s: &'static str) -> Frame {
+    Frame::Bulk(Bytes::from_static(s.as_bytes()))
+}
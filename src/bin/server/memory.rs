@@ -0,0 +1,184 @@
+//! maxmemory 和淘汰策略
+//!
+//! 内存占用是估算出来的：每个 key 按 key、值的字节数加上固定的开销计算，不是真实的分配量。
+//! 超过 `maxmemory` 时，写命令执行前先按策略淘汰 key；和 Redis 一样不维护精确的 LRU 链表，
+//! 而是每个库随机采样几个 key，从样本里挑最该淘汰的那个，样本越多越接近精确的 LRU/LFU。
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::keyspace::Entry;
+
+/// 超过 maxmemory 时淘汰谁
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// 不淘汰，写命令直接返回 OOM 错误
+    NoEviction,
+    /// 最久没被访问的
+    AllKeysLru,
+    /// 设置了过期时间的 key 里最久没被访问的
+    VolatileLru,
+    /// 访问频率最低的
+    AllKeysLfu,
+    AllKeysRandom,
+    /// 设置了过期时间的 key 里最快过期的
+    VolatileTtl,
+}
+
+/// 一个 key 的访问记录，LRU 看最后访问时间，LFU 看对数计数器
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    last_access: Instant,
+    // 和 Redis 一样是对数计数器：越大越难再加一，最大 255
+    counter: u8,
+    // 上次按时间衰减计数器的时间
+    decayed_at: Instant,
+}
+
+/// xorshift 伪随机数，采样和 LFU 计数器用，不需要密码学强度
+pub struct Rng(u64);
+
+// 新 key 的 LFU 计数器初值，免得刚写入就因为频率低被淘汰
+const LFU_INIT: u8 = 5;
+// 越大计数器涨得越慢，Redis 的默认值
+const LFU_LOG_FACTOR: f64 = 10.0;
+// 每过这么久没有访问，计数器减一
+const LFU_DECAY: Duration = Duration::from_secs(60);
+
+impl EvictionPolicy {
+    /// 只在设置了过期时间的 key 里挑
+    pub fn is_volatile(self) -> bool {
+        matches!(self, EvictionPolicy::VolatileLru | EvictionPolicy::VolatileTtl)
+    }
+
+    /// 越大越该被淘汰
+    pub fn score(self, entry: &Entry, now: Instant) -> u64 {
+        match self {
+            // 这两种不按得分挑：noeviction 不淘汰，allkeys-random 直接均匀随机挑一个
+            EvictionPolicy::NoEviction | EvictionPolicy::AllKeysRandom => 0,
+            EvictionPolicy::AllKeysLru | EvictionPolicy::VolatileLru => entry.usage.idle(now).as_micros() as u64,
+            EvictionPolicy::AllKeysLfu => u64::from(u8::MAX - entry.usage.frequency(now)),
+            EvictionPolicy::VolatileTtl => match entry.expires_at {
+                Some(at) => u64::MAX - at.saturating_duration_since(now).as_micros() as u64,
+                None => 0,
+            },
+        }
+    }
+}
+
+impl Usage {
+    pub fn new(now: Instant) -> Usage {
+        Usage {
+            last_access: now,
+            counter: LFU_INIT,
+            decayed_at: now,
+        }
+    }
+
+    /// 记一次访问
+    pub fn touch(&mut self, now: Instant, rng: &mut Rng) {
+        self.counter = self.frequency(now);
+        self.decayed_at = now;
+        self.last_access = now;
+
+        // 计数器越大，加一的概率越小，255 能代表上百万次访问
+        if self.counter < u8::MAX {
+            let base = f64::from(self.counter.saturating_sub(LFU_INIT));
+            if rng.next_f64() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+                self.counter += 1;
+            }
+        }
+    }
+
+    pub fn idle(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_access)
+    }
+
+    /// 按时间衰减之后的 LFU 计数器
+    pub fn frequency(&self, now: Instant) -> u8 {
+        let periods = now.saturating_duration_since(self.decayed_at).as_secs() / LFU_DECAY.as_secs();
+        self.counter.saturating_sub(periods.min(u64::from(u8::MAX)) as u8)
+    }
+}
+
+impl Rng {
+    pub fn new() -> Rng {
+        // `RandomState` 每次创建都带一组新的随机 key，拿它当种子
+        Rng(RandomState::new().build_hasher().finish() | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// [0, 1) 之间的浮点数
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// [0, n) 之间的整数
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::new()
+    }
+}
+
+/// 解析 `100mb`、`1gb`、`4096` 这样的内存大小，单位和 Redis 的配置文件一样
+pub fn parse_memory(s: &str) -> Result<usize, String> {
+    let lower = s.to_lowercase();
+    let units = [("kb", 1 << 10), ("mb", 1 << 20), ("gb", 1 << 30), ("k", 1000), ("m", 1_000_000), ("g", 1_000_000_000), ("b", 1)];
+    let (digits, unit) = units
+        .iter()
+        .find_map(|(suffix, unit)| lower.strip_suffix(suffix).map(|digits| (digits, *unit)))
+        .unwrap_or((lower.as_str(), 1));
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or(format!("invalid memory size `{}`", s))
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EvictionPolicy, String> {
+        match s {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+            "volatile-lru" => Ok(EvictionPolicy::VolatileLru),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+            "allkeys-random" => Ok(EvictionPolicy::AllKeysRandom),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            _ => Err(format!(
+                "unknown maxmemory policy `{}`, expected noeviction, allkeys-lru, volatile-lru, \
+                 allkeys-lfu, allkeys-random or volatile-ttl",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        };
+        name.fmt(f)
+    }
+}
@@ -0,0 +1,143 @@
+//! SLOWLOG：记录执行时间超过阈值的命令
+//!
+//! 和 Redis 一样只保留最近的若干条，旧的从另一头挤出去。参数太多、太长时截断，
+//! 免得一条慢日志本身占掉大量内存。
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use my_redis_project::frame::Frame;
+
+// 最多记录几个参数，多出来的合并成一个 `... (N more arguments)`
+const MAX_ARGS: usize = 32;
+// 每个参数最多记录多少字节
+const MAX_ARG_LEN: usize = 128;
+
+/// 所有连接共享的慢日志，clone 之后指向同一份数据
+#[derive(Clone)]
+pub struct SlowLog {
+    state: Arc<Mutex<State>>,
+    // `None` 表示关闭
+    threshold: Option<Duration>,
+    max_len: usize,
+}
+
+struct State {
+    // 新的在前
+    entries: VecDeque<Entry>,
+    // SLOWLOG RESET 之后也继续递增
+    next_id: u64,
+}
+
+struct Entry {
+    id: u64,
+    // Unix 时间戳，秒
+    timestamp: u64,
+    duration: Duration,
+    args: Vec<Bytes>,
+    addr: String,
+    name: String,
+}
+
+impl SlowLog {
+    /// 执行时间不少于 `threshold` 的命令会被记录，最多保留 `max_len` 条
+    pub fn new(threshold: Option<Duration>, max_len: usize) -> SlowLog {
+        SlowLog {
+            state: Arc::new(Mutex::new(State {
+                entries: VecDeque::new(),
+                next_id: 0,
+            })),
+            threshold,
+            max_len,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold.is_some() && self.max_len > 0
+    }
+
+    pub fn is_slow(&self, duration: Duration) -> bool {
+        self.max_len > 0 && self.threshold.is_some_and(|threshold| duration >= threshold)
+    }
+
+    /// 记录一条命令，`args` 是客户端发来的原始帧
+    pub fn record(&self, duration: Duration, args: &Frame, addr: &str, name: Option<String>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.entries.push_front(Entry {
+            id,
+            timestamp,
+            duration,
+            args: truncate(args),
+            addr: addr.to_string(),
+            name: name.unwrap_or_default(),
+        });
+        state.entries.truncate(self.max_len);
+    }
+
+    /// SLOWLOG GET：最新的 `count` 条，`None` 表示全部
+    pub fn get(&self, count: Option<usize>) -> Frame {
+        let state = self.state.lock().unwrap();
+        let count = count.unwrap_or(state.entries.len());
+        Frame::Array(state.entries.iter().take(count).map(Entry::to_frame).collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn reset(&self) {
+        self.state.lock().unwrap().entries.clear();
+    }
+}
+
+impl Entry {
+    /// `[id, 时间戳, 耗时（微秒）, [参数...], 客户端地址, 客户端名字]`
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            Frame::Integer(self.id as i64),
+            Frame::Integer(self.timestamp as i64),
+            Frame::Integer(self.duration.as_micros() as i64),
+            Frame::Array(self.args.iter().cloned().map(Frame::Bulk).collect()),
+            Frame::Bulk(Bytes::from(self.addr.clone())),
+            Frame::Bulk(Bytes::from(self.name.clone())),
+        ])
+    }
+}
+
+/// 取出命令参数，超过 `MAX_ARGS` 个、`MAX_ARG_LEN` 字节的部分截掉
+fn truncate(args: &Frame) -> Vec<Bytes> {
+    let Frame::Array(parts) = args else {
+        return Vec::new();
+    };
+
+    let keep = if parts.len() > MAX_ARGS { MAX_ARGS - 1 } else { parts.len() };
+    let mut args: Vec<Bytes> = parts[..keep]
+        .iter()
+        .map(|part| {
+            let data = match part {
+                Frame::Bulk(data) => data.clone(),
+                Frame::Simple(s) => Bytes::from(s.clone()),
+                frame => Bytes::from(format!("{:?}", frame)),
+            };
+            if data.len() <= MAX_ARG_LEN {
+                return data;
+            }
+            let mut truncated = data[..MAX_ARG_LEN].to_vec();
+            truncated.extend_from_slice(format!("... ({} more bytes)", data.len() - MAX_ARG_LEN).as_bytes());
+            Bytes::from(truncated)
+        })
+        .collect();
+    if keep < parts.len() {
+        args.push(Bytes::from(format!("... ({} more arguments)", parts.len() - keep)));
+    }
+    args
+}
@@ -0,0 +1,501 @@
+//! Redis Streams 风格的日志类型
+//!
+//! pub/sub 是“发完就忘”的，订阅者不在线时消息就丢了。Stream 把消息按 ID 顺序保存在 keyspace 中，
+//! 消费者可以从任意位置往后读；消费组在此基础上记录每条消息投递给了谁（PEL，pending entries list），
+//! 没有 XACK 的消息可以再次读取或者被别的消费者 XCLAIM，从而实现至少一次投递。
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use my_redis_project::frame::Frame;
+
+/// 消息 ID：毫秒时间戳 + 同一毫秒内的序号，按字典序比较
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// 紧挨着的下一个 ID，用于实现 `(` 开头的开区间
+    fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => self.ms.checked_add(1).map(|ms| StreamId { ms, seq: 0 }),
+        }
+    }
+
+    fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => self.ms.checked_sub(1).map(|ms| StreamId { ms, seq: u64::MAX }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = String;
+
+    /// 接受 `ms-seq`，或者只有 `ms`（此时 seq 为 0）
+    fn from_str(s: &str) -> Result<StreamId, String> {
+        let invalid = || "ERR Invalid stream ID specified as stream command argument".to_string();
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse().map_err(|_| invalid())?),
+            None => (s, 0),
+        };
+        Ok(StreamId {
+            ms: ms.parse().map_err(|_| invalid())?,
+            seq,
+        })
+    }
+}
+
+/// XADD 中 ID 的写法
+#[derive(Debug, Clone, Copy)]
+pub enum IdSpec {
+    /// `*`：完全由服务端生成
+    Auto,
+    /// `ms-*`：毫秒部分由客户端指定，序号由服务端生成
+    Partial(u64),
+    /// `ms-seq`：完全由客户端指定，必须比当前最后一条大
+    Explicit(StreamId),
+}
+
+impl FromStr for IdSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IdSpec, String> {
+        if s == "*" {
+            return Ok(IdSpec::Auto);
+        }
+        if let Some(ms) = s.strip_suffix("-*") {
+            let ms = ms
+                .parse()
+                .map_err(|_| "ERR Invalid stream ID specified as stream command argument".to_string())?;
+            return Ok(IdSpec::Partial(ms));
+        }
+        s.parse().map(IdSpec::Explicit)
+    }
+}
+
+/// 解析 XRANGE/XPENDING 的区间端点：`-`、`+`、`ms`、`ms-seq`，以及 `(` 开头的开区间
+pub fn parse_range_bound(s: &str, is_start: bool) -> Result<Bound<StreamId>, String> {
+    match s {
+        "-" => return Ok(Bound::Included(StreamId::MIN)),
+        "+" => return Ok(Bound::Included(StreamId::MAX)),
+        _ => {}
+    }
+
+    if let Some(id) = s.strip_prefix('(') {
+        return Ok(Bound::Excluded(id.parse()?));
+    }
+
+    // 只给了毫秒时，起点取该毫秒的第一条，终点取该毫秒的最后一条
+    let mut id: StreamId = s.parse()?;
+    if !is_start && !s.contains('-') {
+        id.seq = u64::MAX;
+    }
+    Ok(Bound::Included(id))
+}
+
+/// 一条消息：ID + 若干字段/值
+pub type Entry = (StreamId, Vec<(Bytes, Bytes)>);
+
+// 估算内存占用时每条消息、每个组、每条 pending 记录的固定开销
+const ENTRY_OVERHEAD: usize = 64;
+const GROUP_OVERHEAD: usize = 128;
+const PENDING_OVERHEAD: usize = 64;
+
+#[derive(Debug, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(Bytes, Bytes)>>,
+    // 曾经添加过的最大 ID，即使对应的消息已经被 XTRIM 删掉，新的 ID 也必须比它大
+    last_id: StreamId,
+    groups: HashMap<String, Group>,
+    // 所有消息的字段和值一共多少字节，估算内存占用用
+    bytes: usize,
+}
+
+#[derive(Debug)]
+struct Group {
+    // 已经投递给组内某个消费者的最大 ID，`>` 从这之后开始读
+    last_delivered: StreamId,
+    pending: BTreeMap<StreamId, Pending>,
+}
+
+/// 已经投递但还没有 XACK 的消息
+#[derive(Debug)]
+struct Pending {
+    consumer: String,
+    delivered_at: Instant,
+    delivery_count: u64,
+}
+
+/// XPENDING 扩展形式的参数
+#[derive(Debug)]
+pub struct PendingQuery {
+    pub min_idle: Duration,
+    pub start: Bound<StreamId>,
+    pub end: Bound<StreamId>,
+    pub count: usize,
+    pub consumer: Option<String>,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// 估算的内存占用：消息内容加上每条消息、每个组、每条 pending 记录的固定开销
+    pub fn memory_usage(&self) -> usize {
+        let groups: usize = self
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                GROUP_OVERHEAD + name.len() + group.pending.len() * PENDING_OVERHEAD
+            })
+            .sum();
+        self.bytes + self.entries.len() * ENTRY_OVERHEAD + groups
+    }
+
+    /// XADD：追加一条消息，返回它的 ID
+    pub fn add(&mut self, spec: IdSpec, fields: Vec<(Bytes, Bytes)>) -> Result<StreamId, String> {
+        let last = self.last_id;
+        let id = match spec {
+            IdSpec::Auto => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0);
+                // 时钟回拨时沿用上一条的毫秒数，只增加序号，保证 ID 单调递增
+                if now > last.ms {
+                    StreamId { ms: now, seq: 0 }
+                } else {
+                    last.next().ok_or_else(id_too_small)?
+                }
+            }
+            IdSpec::Partial(ms) if ms == last.ms => last.next().ok_or_else(id_too_small)?,
+            IdSpec::Partial(ms) => StreamId { ms, seq: 0 },
+            IdSpec::Explicit(id) => id,
+        };
+
+        if id == StreamId::MIN {
+            return Err("ERR The ID specified in XADD must be greater than 0-0".to_string());
+        }
+        if id <= last {
+            return Err(id_too_small());
+        }
+
+        self.bytes += fields_size(&fields);
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        Ok(id)
+    }
+
+    /// XTRIM MAXLEN：只保留最新的 `maxlen` 条，返回删除的数量
+    pub fn trim(&mut self, maxlen: usize) -> usize {
+        let mut removed = 0;
+        while self.entries.len() > maxlen {
+            if let Some((_, fields)) = self.entries.pop_first() {
+                self.bytes -= fields_size(&fields);
+            }
+            removed += 1;
+        }
+        removed
+    }
+
+    /// XRANGE / XREVRANGE
+    pub fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Vec<Entry> {
+        if is_empty_range(start, end) {
+            return Vec::new();
+        }
+
+        let range = self.entries.range((start, end));
+        let count = count.unwrap_or(usize::MAX);
+        let clone = |(id, fields): (&StreamId, &Vec<(Bytes, Bytes)>)| (*id, fields.clone());
+        if rev {
+            range.rev().take(count).map(clone).collect()
+        } else {
+            range.take(count).map(clone).collect()
+        }
+    }
+
+    /// XREAD：读取 ID 大于 `after` 的消息
+    pub fn read_after(&self, after: StreamId, count: Option<usize>) -> Vec<Entry> {
+        self.range(Bound::Excluded(after), Bound::Unbounded, count, false)
+    }
+
+    /// XGROUP CREATE：`start` 之后的消息才会投递给这个组
+    pub fn create_group(&mut self, name: String, start: StreamId) -> Result<(), String> {
+        if self.groups.contains_key(&name) {
+            return Err("BUSYGROUP Consumer Group name already exists".to_string());
+        }
+        self.groups.insert(
+            name,
+            Group {
+                last_delivered: start,
+                pending: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn has_group(&self, name: &str) -> bool {
+        self.groups.contains_key(name)
+    }
+
+    pub fn destroy_group(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// XREADGROUP 的 `>`：把组内还没投递过的消息交给 `consumer`
+    ///
+    /// 除非指定了 NOACK，投递出去的消息都会记录到 PEL，直到被 XACK。
+    pub fn read_group_new(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<Entry>, String> {
+        let Stream { entries, groups, .. } = self;
+        let group = groups.get_mut(group).ok_or_else(|| no_group(group))?;
+
+        let now = Instant::now();
+        let mut delivered = Vec::new();
+        for (id, fields) in entries
+            .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+            .take(count.unwrap_or(usize::MAX))
+        {
+            group.last_delivered = *id;
+            if !noack {
+                group.pending.insert(
+                    *id,
+                    Pending {
+                        consumer: consumer.to_string(),
+                        delivered_at: now,
+                        delivery_count: 1,
+                    },
+                );
+            }
+            delivered.push((*id, fields.clone()));
+        }
+
+        Ok(delivered)
+    }
+
+    /// XREADGROUP 指定具体 ID 时：返回该消费者自己 PEL 中 ID 大于 `after` 的消息，用于故障恢复后重新处理
+    ///
+    /// 已经被删除的消息仍然会出现，只是字段为空，和 Redis 的行为一致。
+    pub fn read_group_pending(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        after: StreamId,
+        count: Option<usize>,
+    ) -> Result<Vec<Entry>, String> {
+        let group = self.groups.get_mut(group).ok_or_else(|| no_group(group))?;
+
+        let now = Instant::now();
+        let mut result = Vec::new();
+        for (id, pending) in group
+            .pending
+            .range_mut((Bound::Excluded(after), Bound::Unbounded))
+            .filter(|(_, p)| p.consumer == consumer)
+            .take(count.unwrap_or(usize::MAX))
+        {
+            pending.delivered_at = now;
+            pending.delivery_count += 1;
+            let fields = self.entries.get(id).cloned().unwrap_or_default();
+            result.push((*id, fields));
+        }
+
+        Ok(result)
+    }
+
+    /// XACK：把消息从 PEL 中移除，返回实际移除的数量
+    pub fn ack(&mut self, group: &str, ids: &[StreamId]) -> Result<usize, String> {
+        let group = self.groups.get_mut(group).ok_or_else(|| no_group(group))?;
+        Ok(ids
+            .iter()
+            .filter(|id| group.pending.remove(id).is_some())
+            .count())
+    }
+
+    /// XPENDING 的简单形式：[总数, 最小 ID, 最大 ID, [[消费者, 数量], ...]]
+    pub fn pending_summary(&self, group: &str) -> Result<Frame, String> {
+        let group = self.groups.get(group).ok_or_else(|| no_group(group))?;
+
+        let (Some((min, _)), Some((max, _))) =
+            (group.pending.first_key_value(), group.pending.last_key_value())
+        else {
+            return Ok(Frame::Array(vec![
+                Frame::Integer(0),
+                Frame::Null,
+                Frame::Null,
+                Frame::Null,
+            ]));
+        };
+
+        let mut per_consumer = BTreeMap::new();
+        for pending in group.pending.values() {
+            *per_consumer.entry(pending.consumer.as_str()).or_insert(0u64) += 1;
+        }
+        let consumers = per_consumer
+            .into_iter()
+            .map(|(name, n)| {
+                Frame::Array(vec![
+                    Frame::Bulk(Bytes::from(name.to_string())),
+                    Frame::Bulk(Bytes::from(n.to_string())),
+                ])
+            })
+            .collect();
+
+        Ok(Frame::Array(vec![
+            Frame::Integer(group.pending.len() as i64),
+            id_frame(*min),
+            id_frame(*max),
+            Frame::Array(consumers),
+        ]))
+    }
+
+    /// XPENDING 的扩展形式：[[ID, 消费者, 空闲毫秒数, 投递次数], ...]
+    pub fn pending_range(&self, group: &str, query: &PendingQuery) -> Result<Frame, String> {
+        let group = self.groups.get(group).ok_or_else(|| no_group(group))?;
+        if is_empty_range(query.start, query.end) {
+            return Ok(Frame::Array(vec![]));
+        }
+
+        let now = Instant::now();
+        let rows = group
+            .pending
+            .range((query.start, query.end))
+            .filter(|(_, p)| query.consumer.as_deref().is_none_or(|c| c == p.consumer))
+            .filter(|(_, p)| now.duration_since(p.delivered_at) >= query.min_idle)
+            .take(query.count)
+            .map(|(id, p)| {
+                Frame::Array(vec![
+                    id_frame(*id),
+                    Frame::Bulk(Bytes::from(p.consumer.clone())),
+                    Frame::Integer(now.duration_since(p.delivered_at).as_millis() as i64),
+                    Frame::Integer(p.delivery_count as i64),
+                ])
+            })
+            .collect();
+
+        Ok(Frame::Array(rows))
+    }
+
+    /// XCLAIM：把空闲超过 `min_idle` 的待确认消息转交给 `consumer`
+    ///
+    /// 用于某个消费者崩溃后，由其他消费者接手它没处理完的消息。
+    /// 已经被删除的消息会直接从 PEL 中移除，不会返回。
+    pub fn claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        min_idle: Duration,
+        ids: &[StreamId],
+    ) -> Result<Vec<Entry>, String> {
+        let Stream { entries, groups, .. } = self;
+        let group = groups.get_mut(group).ok_or_else(|| no_group(group))?;
+
+        let now = Instant::now();
+        let mut claimed = Vec::new();
+        for id in ids {
+            let Some(pending) = group.pending.get_mut(id) else {
+                continue;
+            };
+            if now.duration_since(pending.delivered_at) < min_idle {
+                continue;
+            }
+            let Some(fields) = entries.get(id) else {
+                group.pending.remove(id);
+                continue;
+            };
+
+            pending.consumer = consumer.to_string();
+            pending.delivered_at = now;
+            pending.delivery_count += 1;
+            claimed.push((*id, fields.clone()));
+        }
+
+        Ok(claimed)
+    }
+}
+
+/// 把消息列表转成回复：[[ID, [field, value, ...]], ...]
+pub fn entries_frame(entries: Vec<Entry>) -> Frame {
+    Frame::Array(
+        entries
+            .into_iter()
+            .map(|(id, fields)| {
+                let fields = fields
+                    .into_iter()
+                    .flat_map(|(field, value)| [Frame::Bulk(field), Frame::Bulk(value)])
+                    .collect();
+                Frame::Array(vec![id_frame(id), Frame::Array(fields)])
+            })
+            .collect(),
+    )
+}
+
+pub fn id_frame(id: StreamId) -> Frame {
+    Frame::Bulk(Bytes::from(id.to_string()))
+}
+
+fn is_empty_range(start: Bound<StreamId>, end: Bound<StreamId>) -> bool {
+    // `BTreeMap::range` 在起点大于终点时会 panic，需要先排除掉
+    let start = match start {
+        Bound::Included(id) => Some(id),
+        Bound::Excluded(id) => id.next(),
+        Bound::Unbounded => Some(StreamId::MIN),
+    };
+    let end = match end {
+        Bound::Included(id) => Some(id),
+        Bound::Excluded(id) => id.prev(),
+        Bound::Unbounded => Some(StreamId::MAX),
+    };
+    match (start, end) {
+        (Some(start), Some(end)) => start > end,
+        _ => true,
+    }
+}
+
+fn fields_size(fields: &[(Bytes, Bytes)]) -> usize {
+    fields.iter().map(|(field, value)| field.len() + value.len()).sum()
+}
+
+fn id_too_small() -> String {
+    "ERR The ID specified in XADD is equal or smaller than the target stream top item".to_string()
+}
+
+fn no_group(group: &str) -> String {
+    format!("NOGROUP No such consumer group '{}' for key name", group)
+}
@@ -1,44 +1,173 @@
+use std::env;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use bytes::Bytes;
+use futures::SinkExt;
+use my_redis_project::codec::RespCodec;
+use my_redis_project::connect::{self, STAGGER};
+use my_redis_project::frame::Frame;
+use my_redis_project::mailbox::Policy;
+use my_redis_project::subscription::{Message, Subscription};
+use my_redis_project::tls;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{UnixStream, lookup_host};
 use tokio_stream::StreamExt;
-use mini_redis::client;
+use tokio_util::codec::Framed;
+
+/// 和订阅连接连到同一个服务端，`-s <path>` 时也走 Unix socket，给了 `--tls-ca` 时也走 TLS
+async fn publish(server: Server) -> mini_redis::Result<()> {
+    match server {
+        Server::Tcp { addrs, tls: None } => {
+            let (socket, _) = connect::race(&addrs, STAGGER).await?;
+            publish_on(socket).await
+        }
+        Server::Tcp {
+            addrs,
+            tls: Some(Tls { ca, name }),
+        } => {
+            let connector = tls::connector(Path::new(&ca))?;
+            let (socket, _) = connect::race(&addrs, STAGGER).await?;
+            publish_on(connector.connect(tls::server_name(&name)?, socket).await?).await
+        }
+        Server::Unix(path) => publish_on(UnixStream::connect(path).await?).await,
+    }
+}
 
-async fn publish() -> mini_redis::Result<()> {
-    let mut client = client::connect("127.0.0.1:6379").await?;
+async fn publish_on<S: AsyncRead + AsyncWrite + Unpin>(socket: S) -> mini_redis::Result<()> {
+    let mut connection = Framed::new(socket, RespCodec::new());
 
     // 发布一些数据
-    client.publish("numbers", "1".into()).await?;
-    client.publish("numbers", "two".into()).await?;
-    client.publish("numbers", "3".into()).await?;
-    client.publish("numbers", "four".into()).await?;
-    client.publish("numbers", "five".into()).await?;
-    client.publish("numbers", "6".into()).await?;
+    let messages = [
+        ("numbers", "1"),
+        ("numbers", "two"),
+        ("numbers", "3"),
+        ("letters", "a"),
+        ("numbers", "four"),
+        ("numbers", "five"),
+        ("letters", "b"),
+        ("numbers", "6"),
+    ];
+    for (channel, message) in messages {
+        let parts = ["publish", channel, message];
+        let frame = Frame::Array(parts.iter().map(|s| Frame::Bulk(Bytes::from(*s))).collect());
+        connection.send(frame).await?;
+        match connection.next().await {
+            Some(Ok(Frame::Error(e))) => return Err(e.into()),
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e.into()),
+            None => return Err("connection closed by server".into()),
+        }
+    }
     Ok(())
 }
 
+/// 订阅连接连到哪里
+enum Server {
+    Tcp {
+        addrs: Vec<SocketAddr>,
+        tls: Option<Tls>,
+    },
+    /// 和 redis-cli 一样用 `-s <path>` 指定 Unix socket
+    Unix(String),
+}
+
+/// 服务端开了 TLS 时用 `--tls-ca` 指定签发它证书的 CA，`--tls-name` 要和证书里的名字一致
+struct Tls {
+    ca: String,
+    name: String,
+}
+
+/// 命令行上给出的服务端地址，没给就用 localhost（通常会解析出 `::1` 和 `127.0.0.1` 两个地址）
+///
+/// 例如：`cargo run --bin stream_client -- localhost:6379 --tls-ca ca.pem --tls-name localhost`
+async fn server() -> mini_redis::Result<Server> {
+    let mut hosts = Vec::new();
+    let mut unix = None;
+    let mut ca = None;
+    let mut name = "localhost".to_string();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for `{}`", arg));
+        match arg.as_str() {
+            "-s" => unix = Some(value()?),
+            "--tls-ca" => ca = Some(value()?),
+            "--tls-name" => name = value()?,
+            _ => hosts.push(arg),
+        }
+    }
+
+    if let Some(path) = unix {
+        return Ok(Server::Unix(path));
+    }
+    if hosts.is_empty() {
+        hosts.push("localhost:6379".to_string());
+    }
+
+    let mut addrs = Vec::new();
+    for host in hosts {
+        addrs.extend(lookup_host(host).await?);
+    }
+    let tls = ca.map(|ca| Tls { ca, name });
+    Ok(Server::Tcp { addrs, tls })
+}
+
 async fn subscribe() -> mini_redis::Result<()> {
-    let client = client::connect("127.0.0.1:6379").await?;
-    let subscriber = client.subscribe(vec!["numbers".to_string()]).await?;
-    // let messages = subscriber
-    // .into_stream()
-    // .filter(|msg| match msg {
-    //     Ok(msg) if msg.content.len() == 1 => true,
-    //     _ => false,
-    // })
-    // .map(|msg|msg.unwrap().content)
-    // .take(3);
-    let messages = subscriber
-    .into_stream()
-    .filter_map(|msg| match msg {
-        Ok(msg) if msg.content.len() == 1 => Some(msg.content),
-        _ => None,
-    })
-    .take(3);
-
-
-
-    tokio::pin!(messages);
-
-    while let Some(msg) = messages.next().await {
-        println!("got = {:?}", msg);
+    // 本地最多缓冲 16 条消息，处理不过来就丢掉最旧的，丢掉的数量会作为 `Message::Lagged` 出现在 stream 中
+    let channels = ["numbers".to_string()];
+    let server = server().await?;
+    let mut subscription = match &server {
+        // 所有地址同时去连，用最先连上的那个
+        Server::Tcp { addrs, tls: None } => {
+            Subscription::connect(&addrs[..], &channels, Policy::DropOldest, 16).await?
+        }
+        Server::Tcp {
+            addrs,
+            tls: Some(tls),
+        } => {
+            Subscription::connect_tls(
+                &addrs[..],
+                &tls.ca,
+                &tls.name,
+                &channels,
+                Policy::DropOldest,
+                16,
+            )
+            .await?
+        }
+        Server::Unix(path) => {
+            Subscription::connect_unix(path, &channels, Policy::DropOldest, 16).await?
+        }
+    };
+
+    // 订阅之后再开始发布，否则前面几条消息会因为还没订阅上而收不到
+    tokio::spawn(async {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        publish(server).await
+    });
+
+    // 消费的同时也可以增减订阅的频道
+    subscription.subscribe(&["letters".to_string()])?;
+
+    // `Subscription` 本身就是 `Stream`，消息里直接带着频道名和内容，不需要再手动解析
+    let mut got = 0;
+    while let Some(msg) = subscription.next().await {
+        match msg {
+            Message::Published { channel, payload } if payload.len() == 1 => {
+                println!("got = {:?} from {}", payload, channel);
+                got += 1;
+
+                if got == 3 {
+                    // 收够 3 条单字符的消息之后不再关心 numbers，只剩 letters
+                    subscription.unsubscribe(&["numbers".to_string()])?;
+                } else if got == 4 {
+                    break;
+                }
+            }
+            Message::Published { .. } => {}
+            Message::Lagged(n) => println!("missed {} messages", n),
+        }
     }
 
     Ok(())
@@ -46,13 +175,9 @@ async fn subscribe() -> mini_redis::Result<()> {
 
 #[tokio::main]
 async fn main() -> mini_redis::Result<()> {
-    tokio::spawn(async {
-        publish().await
-    });
-
     subscribe().await?;
 
     println!("DONE");
 
     Ok(())
-}
\ No newline at end of file
+}
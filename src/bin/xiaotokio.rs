@@ -1,224 +0,0 @@
-use std::collections::VecDeque;
-use std::future::Future;
-use std::pin::Pin;
-use std::sync::{Arc, Mutex};
-use std::task::{Context, Poll, Waker};
-use std::thread;
-use std::time::{Duration, Instant};
-
-use crossbeam::channel;
-use futures::task::{self, ArcWake};
-use tokio::sync::Notify;
-
-// struct Delay {
-//     when: Instant,
-// }
-
-// // 为我们的 Delay 类型实现 Future 特征
-// impl Future for Delay {
-//     type Output = &'static str;
-
-//     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>)
-//         -> Poll<&'static str>
-//     {
-//         if Instant::now() >= self.when {
-//             // 时间到了，Future 可以结束
-//             println!("Hello world");
-//             // Future 执行结束并返回 "done" 字符串
-//             Poll::Ready("done")
-//         } else {
-//             // // 目前先忽略下面这行代码
-//             // cx.waker().wake_by_ref();
-//             // 为当前任务克隆一个 waker 的句柄
-//             let waker = cx.waker().clone();
-//             let when = self.when;
-
-//             // 生成一个计时器线程
-//             thread::spawn(move || {
-//                 let now = Instant::now();
-
-//                 if now < when {
-//                     thread::sleep(when - now);
-//                 }
-
-//                 waker.wake();
-//             });
-//             Poll::Pending
-//         }
-//     }
-// }
-
-struct Delay {
-    when: Instant,
-    // 用于说明是否已经生成一个线程
-    // Some 代表已经生成， None 代表还没有
-    waker: Option<Arc<Mutex<Waker>>>,
-}
-
-impl Future for Delay {
-    type Output = ();
-
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
-        // 若这是 Future 第一次被调用，那么需要先生成一个计时器线程。
-        // 若不是第一次调用(该线程已在运行)，那要确保已存储的 `Waker` 跟当前任务的 `waker` 匹配
-        if let Some(waker) = &self.waker {
-            let mut waker = waker.lock().unwrap();
-
-            // 检查之前存储的 `waker` 是否跟当前任务的 `waker` 相匹配.
-            // 这是必要的，原因是 `Delay Future` 的实例可能会在两次 `poll` 之间被转移到另一个任务中，然后
-            // 存储的 waker 被该任务进行了更新。
-            // 这种情况一旦发生，`Context` 包含的 `waker` 将不同于存储的 `waker`。
-            // 因此我们必须对存储的 `waker` 进行更新
-            if !waker.will_wake(cx.waker()) {
-                *waker = cx.waker().clone();
-            }
-        } else {
-            let when = self.when;
-            let waker = Arc::new(Mutex::new(cx.waker().clone()));
-            self.waker = Some(waker.clone());
-
-            // 第一次调用 `poll`，生成计时器线程
-            thread::spawn(move || {
-                let now = Instant::now();
-
-                if now < when {
-                    thread::sleep(when - now);
-                }
-
-                // 计时结束，通过调用 `waker` 来通知执行器
-                let waker = waker.lock().unwrap();
-                waker.wake_by_ref();
-            });
-        }
-
-        // 一旦 waker 被存储且计时器线程已经开始，我们就需要检查 `delay` 是否已经完成
-        // 若计时已完成，则当前 Future 就可以完成并返回 `Poll::Ready`
-        if Instant::now() >= self.when {
-            Poll::Ready(())
-        } else {
-            // 计时尚未结束，Future 还未完成，因此返回 `Poll::Pending`.
-            //
-            // `Future` 特征要求当 `Pending` 被返回时，那我们要确保当资源准备好时，必须调用 `waker` 以通
-            // 知执行器。 在我们的例子中，会通过生成的计时线程来保证
-            //
-            // 如果忘记调用 waker， 那等待我们的将是深渊：该任务将被永远的挂起，无法再执行
-            Poll::Pending
-        }
-    }
-}
-
-async fn delay(dur: Duration) {
-    let when = Instant::now() + dur;
-    let notify = Arc::new(Notify::new());
-    let notify2 = notify.clone();
-
-    thread::spawn(move || {
-        let now = Instant::now();
-
-        if now < when {
-            thread::sleep(when - now);
-        }
-
-        notify2.notify_one();
-    });
-
-
-    notify.notified().await;
-}
-
-
-fn main() {
-    let mut mini_tokio = MiniTokio::new();
-
-    mini_tokio.spawn(async {
-        let when = Instant::now() + Duration::from_millis(10);
-        // let future = Delay { when };
-
-        // let out = future.await;
-        // assert_eq!(out, "done");
-    });
-
-    mini_tokio.run();
-}
-
-struct MiniTokio {
-    scheduled: channel::Receiver<Arc<Task>>,
-    sender: channel::Sender<Arc<Task>>,
-}
-
-// type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
-
-
-struct Task {
-    // `Mutex` 是为了让 `Task` 实现 `Sync` 特征，它能保证同一时间只有一个线程可以访问 `Future`。
-    // 事实上 `Mutex` 并没有在 Tokio 中被使用，这里我们只是为了简化： Tokio 的真实代码实在太长了 :D
-    future: Mutex<Pin<Box<dyn Future<Output = ()> + Send>>>,
-    executor: channel::Sender<Arc<Task>>,
-}
-
-impl Task {
-    fn schedule(self: &Arc<Self>) {
-        self.executor.send(self.clone());
-    }
-}
-
-impl ArcWake for Task {
-    fn wake_by_ref(arc_self: &Arc<Self>) {
-        arc_self.schedule();
-    }
-}
-
-impl MiniTokio {
-    // 从消息通道中接收任务，然后通过 poll 来执行
-    fn run(&self) {
-        while let Ok(task) = self.scheduled.recv() {
-            task.poll();
-        }
-    }
-
-    /// 初始化一个新的 mini-tokio 实例
-    fn new() -> MiniTokio {
-        let (sender, scheduled) = channel::unbounded();
-
-        MiniTokio { scheduled, sender }
-    }
-
-
-    /// 在下面函数中，通过参数传入的 future 被 `Task` 包裹起来，然后会被推入到调度队列中，当 `run` 被调用时，该 future 将被执行
-    fn spawn<F>(&self, future: F)
-    where
-        F: Future<Output = ()> + Send + 'static,
-    {
-        Task::spawn(future, &self.sender);
-    }
-}
-
-impl Task {
-    fn poll(self: Arc<Self>) {
-        // 基于 Task 实例创建一个 waker, 它使用了之前的 `ArcWake`
-        let waker = task::waker(self.clone());
-        let mut cx = Context::from_waker(&waker);
-
-        // 没有其他线程在竞争锁时，我们将获取到目标 future
-        let mut future = self.future.try_lock().unwrap();
-
-        // 对 future 进行 poll
-        let _ = future.as_mut().poll(&mut cx);
-    }
-
-    // 使用给定的 future 来生成新的任务
-    //
-    // 新的任务会被推到 `sender` 中，接着该消息通道的接收端就可以获取该任务，然后执行
-    fn spawn<F>(future: F, sender: &channel::Sender<Arc<Task>>)
-    where
-        F: Future<Output = ()> + Send + 'static,
-    {
-        let task = Arc::new(Task {
-            future: Mutex::new(Box::pin(future)),
-            executor: sender.clone(),
-        });
-
-        let _ = sender.send(task);
-    }
-
-}
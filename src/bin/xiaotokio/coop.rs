@@ -0,0 +1,182 @@
+//! 协作式调度
+//!
+//! 任务只有在返回 `Pending` 时才会把 worker 让出来。如果一个任务等待的东西总是已经就绪
+//! （socket 里一直有数据、`JoinHandle` 早就结束了、`sleep` 的时间已经过了），它在一个循环里
+//! 反复 `.await` 就永远不会返回 `Pending`，同一个 worker 上的其他任务都被饿死。
+//!
+//! 所以每次 poll 任务时给它一份预算，runtime 提供的资源每完成一次操作就扣一点，预算用完后
+//! 即使资源已经就绪也返回 `Pending`（并且立刻唤醒任务），让任务回到队尾排队。
+//! 自己写的 future 想主动让出 worker 可以用 `yield_now`。
+
+use std::cell::Cell;
+use std::future;
+use std::task::{Context, Poll};
+
+// 每次 poll 任务时的预算
+const BUDGET: u8 = 128;
+
+thread_local! {
+    // 当前任务还剩多少预算，`None` 表示不在任务里，不做限制
+    static CURRENT: Cell<Option<u8>> = const { Cell::new(None) };
+    // 当前这次 poll 里有没有资源完成过操作，用来统计没有进展的唤醒
+    static PROGRESS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// 给 `f` 里的 poll 一份新的预算，结束后恢复原来的；同时返回 `f` 里有没有资源完成过操作
+pub fn budget<R>(f: impl FnOnce() -> R) -> (R, bool) {
+    struct Reset(Option<u8>, bool);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.set(self.0));
+            // 嵌套的 poll（比如 `LocalSet` 里的任务）有进展，外面这次 poll 也算有进展
+            PROGRESS.with(|progress| progress.set(self.1 || progress.get()));
+        }
+    }
+
+    let prev = CURRENT.with(|current| current.replace(Some(BUDGET)));
+    let progressed = PROGRESS.with(|progress| progress.replace(false));
+    let _reset = Reset(prev, progressed);
+    let output = f();
+    (output, PROGRESS.with(Cell::get))
+}
+
+/// 资源开始一次操作前调用：预算用完时唤醒任务并返回 `Pending`，否则先扣掉一点预算
+///
+/// 如果这次操作最后没完成（资源返回了 `Pending`），返回的 `RestoreOnPending` 被丢弃时会把预算还回去，
+/// 完成了就调用 `made_progress`。
+pub fn poll_proceed(cx: &mut Context<'_>) -> Poll<RestoreOnPending> {
+    CURRENT.with(|current| match current.get() {
+        Some(0) => {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+        Some(n) => {
+            current.set(Some(n - 1));
+            Poll::Ready(RestoreOnPending(true))
+        }
+        None => Poll::Ready(RestoreOnPending(false)),
+    })
+}
+
+pub struct RestoreOnPending(bool);
+
+impl RestoreOnPending {
+    pub fn made_progress(&mut self) {
+        self.0 = false;
+        PROGRESS.with(|progress| progress.set(true));
+    }
+}
+
+impl Drop for RestoreOnPending {
+    fn drop(&mut self) {
+        if self.0 {
+            CURRENT.with(|current| current.set(current.get().map(|n| n + 1)));
+        }
+    }
+}
+
+/// 让出 worker：唤醒自己后返回一次 `Pending`，任务回到队尾，等其他任务执行过再继续
+pub async fn yield_now() {
+    let mut yielded = false;
+    future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Poll;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::yield_now;
+    use crate::runtime::MiniTokio;
+    use crate::runtime::tests::serial;
+    use crate::time;
+
+    /// 只有一个 worker 时，一直就绪、一直唤醒自己的任务也不能饿死其他任务
+    #[test]
+    fn fairness() {
+        let _serial = serial();
+        let mini_tokio = MiniTokio::with_workers(1);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // 睡到一个已经过去的时刻每次都立即就绪，没有预算的话这个循环永远不会返回 `Pending`
+        let busy = {
+            let stop = stop.clone();
+            let past = Instant::now() - Duration::from_millis(1);
+            mini_tokio.spawn(async move {
+                let mut spins = 0u64;
+                while !stop.load(Ordering::SeqCst) {
+                    time::sleep_until(past).await;
+                    spins += 1;
+                }
+                spins
+            })
+        };
+
+        // 和 future_such.rs 里的 `Delay` 一样，每次 poll 都唤醒自己
+        let spinning = {
+            let stop = stop.clone();
+            mini_tokio.spawn(future::poll_fn(move |cx| {
+                if stop.load(Ordering::SeqCst) {
+                    return Poll::Ready(());
+                }
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }))
+        };
+
+        let yielding = {
+            let stop = stop.clone();
+            mini_tokio.spawn(async move {
+                while !stop.load(Ordering::SeqCst) {
+                    yield_now().await;
+                }
+            })
+        };
+
+        // 上面三个任务一直在本地队列里转，计时器照样能按时到期
+        let ticker = mini_tokio.spawn(async {
+            let start = Instant::now();
+            let mut interval = time::interval(Duration::from_millis(1));
+            for _ in 0..20 {
+                interval.tick().await;
+            }
+            start.elapsed()
+        });
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                // 从 runtime 外面 spawn 的任务进的是全局队列，worker 要定期去看一眼才轮得到它
+                let stop = stop.clone();
+                mini_tokio.spawn(async move { stop.store(true, Ordering::SeqCst) });
+            });
+            mini_tokio.run();
+        });
+
+        let (spins, elapsed) = MiniTokio::with_workers(1).block_on(async {
+            let spins = busy.await.unwrap();
+            spinning.await.unwrap();
+            yielding.await.unwrap();
+            (spins, ticker.await.unwrap())
+        });
+        assert!(spins > 0);
+        assert!(
+            elapsed < Duration::from_millis(100),
+            "ticker starved: {:?}",
+            elapsed
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! `spawn` 返回的 `JoinHandle`
+//!
+//! 调度器里的 `Task` 只认识 `Future<Output = ()>`，所以 spawn 时用 `Harness` 把用户的 future
+//! 包一层：它把 future 的返回值（或者 panic）写进和 `JoinHandle` 共享的 `JoinState`，
+//! 自己的 `Output` 则是 `()`。任务被 abort 时 `Harness` 会在 poll 完成前被丢弃，
+//! 它的 `Drop` 负责把结果记成“已取消”。
+
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker, ready};
+
+use crate::coop;
+
+/// 任务没能正常返回的原因
+pub enum JoinError {
+    /// 任务被 `JoinHandle::abort` 取消了
+    Cancelled,
+    /// 任务 panic 了，里面是 panic 时的参数
+    Panic(Box<dyn Any + Send>),
+}
+
+pub struct JoinHandle<T> {
+    state: Arc<JoinState<T>>,
+    task: Arc<dyn Abort>,
+}
+
+/// 能被 `JoinHandle::abort` 取消的任务：调度器里的 `Task`，或者 `LocalSet` 里的本地任务
+pub(crate) trait Abort: Send + Sync {
+    fn abort(self: Arc<Self>);
+}
+
+pub(crate) struct JoinState<T> {
+    inner: Mutex<Inner<T>>,
+}
+
+struct Inner<T> {
+    // 任务已经结束，`output` 可能已经被 `JoinHandle` 取走了
+    complete: bool,
+    output: Option<Result<T, JoinError>>,
+    // 等待结果的 `JoinHandle`
+    waker: Option<Waker>,
+}
+
+/// 包装用户的 future，结束时把结果交给 `JoinHandle`
+pub(crate) struct Harness<F: Future> {
+    // `Harness` 总是被 `Box::pin` 固定，`future` 不会再移动
+    future: Pin<Box<F>>,
+    state: Arc<JoinState<F::Output>>,
+}
+
+impl JoinError {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panic(_))
+    }
+
+    /// 取出 panic 的参数，可以用 `std::panic::resume_unwind` 继续抛出
+    pub fn into_panic(self) -> Box<dyn Any + Send> {
+        match self {
+            JoinError::Panic(payload) => payload,
+            JoinError::Cancelled => panic!("task was cancelled, not panicked"),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => "task was cancelled".fmt(f),
+            JoinError::Panic(payload) => match panic_message(payload.as_ref()) {
+                Some(msg) => write!(f, "task panicked: {}", msg),
+                None => "task panicked".fmt(f),
+            },
+        }
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => f.write_str("Cancelled"),
+            JoinError::Panic(payload) => f
+                .debug_tuple("Panic")
+                .field(&panic_message(payload.as_ref()).unwrap_or("..."))
+                .finish(),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// `panic!` 的参数通常是 `&str` 或者 `String`
+fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(state: Arc<JoinState<T>>, task: Arc<dyn Abort>) -> JoinHandle<T> {
+        JoinHandle { state, task }
+    }
+
+    /// 取消任务。任务会在下一次被调度时丢弃它的 future，`JoinHandle` 得到 `JoinError::Cancelled`；
+    /// 如果任务已经结束，什么都不会发生
+    pub fn abort(&self) {
+        self.task.clone().abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.inner.lock().unwrap().complete
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T, JoinError>> {
+        let mut coop = ready!(coop::poll_proceed(cx));
+        let mut inner = self.state.inner.lock().unwrap();
+
+        if let Some(output) = inner.output.take() {
+            coop.made_progress();
+            return Poll::Ready(output);
+        }
+        assert!(!inner.complete, "`JoinHandle` polled after completion");
+
+        inner.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> JoinState<T> {
+    pub(crate) fn new() -> JoinState<T> {
+        JoinState {
+            inner: Mutex::new(Inner {
+                complete: false,
+                output: None,
+                waker: None,
+            }),
+        }
+    }
+
+    /// 记录任务的结果并唤醒 `JoinHandle`，只有第一次调用有效
+    fn complete(&self, output: Result<T, JoinError>) {
+        let waker = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.complete {
+                return;
+            }
+            inner.complete = true;
+            inner.output = Some(output);
+            inner.waker.take()
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<F: Future> Harness<F> {
+    pub(crate) fn new(future: F, state: Arc<JoinState<F::Output>>) -> Harness<F> {
+        Harness {
+            future: Box::pin(future),
+            state,
+        }
+    }
+}
+
+impl<F: Future> Future for Harness<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // panic 只影响这一个任务，结果交给 `JoinHandle`，worker 线程照常运行
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.future.as_mut().poll(cx)));
+
+        match result {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => {
+                self.state.complete(Ok(output));
+                Poll::Ready(())
+            }
+            Err(payload) => {
+                self.state.complete(Err(JoinError::Panic(payload)));
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl<F: Future> Drop for Harness<F> {
+    fn drop(&mut self) {
+        // 还没结束就被丢弃，说明任务被取消了
+        self.state.complete(Err(JoinError::Cancelled));
+    }
+}
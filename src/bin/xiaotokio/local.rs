@@ -0,0 +1,348 @@
+//! `LocalSet`：在当前线程上执行 `!Send` 的 future
+//!
+//! worker 之间会互相偷任务，所以 `runtime::spawn` 要求 future 是 `Send` 的，像 rc_try.rs 里
+//! 那样跨 `.await` 持有 `Rc` 的 future 就没法 spawn。`LocalSet` 自己保管这些任务，只在 poll 它的
+//! 那个线程上 poll 它们：把 `LocalSet::run_until` 交给 `block_on`，里面就可以用 `spawn_local`。
+//!
+//! 任务本身不会离开这个线程，但它的 waker 可能被计时器、reactor 在别的线程上调用，
+//! 所以唤醒时只把任务编号放进一个加锁的就绪队列，再唤醒正在 poll `LocalSet` 的那个任务。
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::task::{self, ArcWake};
+
+use crate::coop;
+use crate::join::{Abort, Harness, JoinHandle, JoinState};
+
+// 每次被 poll 最多执行这么多个本地任务，剩下的下次再说，免得 `run_until` 里的 future 一直轮不到
+const MAX_TASKS_PER_TICK: usize = 61;
+
+type LocalFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+/// 一组只在当前线程上执行的任务
+pub struct LocalSet {
+    shared: Rc<LocalShared>,
+}
+
+struct LocalShared {
+    // 任务被 poll 时会先从这里拿出来，poll 完还没结束再放回去，
+    // 这样任务里调用 `spawn_local` 时不会重复借用
+    tasks: RefCell<HashMap<u64, LocalFuture>>,
+    next_id: Cell<u64>,
+    queue: Arc<Queue>,
+}
+
+/// 就绪队列，任何线程都可以往里放
+struct Queue {
+    inner: Mutex<QueueInner>,
+}
+
+#[derive(Default)]
+struct QueueInner {
+    // 同一个任务可能被唤醒好几次而在队列里出现多次，已经结束的任务编号会被跳过
+    ready: VecDeque<u64>,
+    aborted: HashSet<u64>,
+    // 正在 poll `LocalSet` 的任务
+    waker: Option<Waker>,
+}
+
+/// 本地任务的 waker，也是 `JoinHandle` 用来取消它的句柄
+struct LocalTask {
+    queue: Arc<Queue>,
+    id: u64,
+}
+
+thread_local! {
+    // 当前线程上正在被 poll 的 `LocalSet`
+    static CURRENT: RefCell<Option<Rc<LocalShared>>> = const { RefCell::new(None) };
+}
+
+impl LocalSet {
+    pub fn new() -> LocalSet {
+        LocalSet {
+            shared: Rc::new(LocalShared {
+                tasks: RefCell::new(HashMap::new()),
+                next_id: Cell::new(0),
+                queue: Arc::new(Queue {
+                    inner: Mutex::new(QueueInner::default()),
+                }),
+            }),
+        }
+    }
+
+    /// 往这个 `LocalSet` 里放一个任务，它在 `LocalSet` 被 poll 时才会执行
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        self.shared.spawn(future)
+    }
+
+    /// 执行 `future` 直到它结束，期间同时执行这个 `LocalSet` 里的任务
+    ///
+    /// `future` 结束时还没执行完的任务留在 `LocalSet` 里，可以再 `run_until` 一次或者直接 `.await`
+    /// 这个 `LocalSet` 等它们全部结束。
+    pub fn run_until<F: Future>(&self, future: F) -> RunUntil<'_, F> {
+        RunUntil {
+            local: self,
+            future: Box::pin(future),
+        }
+    }
+}
+
+/// 在 `LocalSet` 里 spawn 一个 `!Send` 的任务，只能在 `LocalSet` 的任务或者 `run_until` 中调用
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let shared = CURRENT.with(|current| {
+        current
+            .borrow()
+            .clone()
+            .expect("`spawn_local` must be called from inside a `LocalSet`")
+    });
+    shared.spawn(future)
+}
+
+impl LocalShared {
+    fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        let state = Arc::new(JoinState::new());
+        self.tasks
+            .borrow_mut()
+            .insert(id, Box::pin(Harness::new(future, state.clone())));
+
+        let task = Arc::new(LocalTask {
+            queue: self.queue.clone(),
+            id,
+        });
+        task.queue.push(id, false);
+        JoinHandle::new(state, task)
+    }
+
+    /// 执行就绪的任务，返回是不是还有任务没来得及执行
+    fn tick(&self) -> bool {
+        for _ in 0..MAX_TASKS_PER_TICK {
+            let Some((id, aborted)) = self.queue.pop() else {
+                return false;
+            };
+            let Some(mut future) = self.tasks.borrow_mut().remove(&id) else {
+                continue;
+            };
+            // 被取消的任务直接丢弃，`Harness` 会把结果记成已取消
+            if aborted {
+                continue;
+            }
+
+            let waker = task::waker(Arc::new(LocalTask {
+                queue: self.queue.clone(),
+                id,
+            }));
+            // 每个本地任务都有自己的预算
+            let mut cx = Context::from_waker(&waker);
+            let (poll, _) = coop::budget(|| future.as_mut().poll(&mut cx));
+            if poll.is_pending() {
+                self.tasks.borrow_mut().insert(id, future);
+            }
+        }
+        !self.queue.is_empty()
+    }
+
+    /// 把 `LocalSet` 设为当前线程的 `LocalSet`，返回的 guard 被丢弃时恢复原来的
+    fn enter(self: &Rc<Self>) -> impl Drop {
+        struct Reset(Option<Rc<LocalShared>>);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+            }
+        }
+
+        let prev = CURRENT.with(|current| current.borrow_mut().replace(self.clone()));
+        Reset(prev)
+    }
+}
+
+impl Queue {
+    fn push(&self, id: u64, abort: bool) {
+        let waker = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.ready.push_back(id);
+            if abort {
+                inner.aborted.insert(id);
+            }
+            inner.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn pop(&self) -> Option<(u64, bool)> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.ready.pop_front()?;
+        let aborted = inner.aborted.remove(&id);
+        Some((id, aborted))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().ready.is_empty()
+    }
+
+    fn register(&self, waker: &Waker) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.waker.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            inner.waker = Some(waker.clone());
+        }
+    }
+}
+
+impl ArcWake for LocalTask {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.queue.push(arc_self.id, false);
+    }
+}
+
+impl Abort for LocalTask {
+    fn abort(self: Arc<Self>) {
+        self.queue.push(self.id, true);
+    }
+}
+
+/// `LocalSet::run_until` 返回的 future
+pub struct RunUntil<'a, F> {
+    local: &'a LocalSet,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for RunUntil<'_, F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let shared = self.local.shared.clone();
+        let _current = shared.enter();
+        shared.queue.register(cx.waker());
+
+        if let Poll::Ready(output) = self.future.as_mut().poll(cx) {
+            return Poll::Ready(output);
+        }
+        if shared.tick() {
+            // 还有就绪的任务，让出一下马上回来
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+/// 直接 `.await` 一个 `LocalSet` 会等到它里面的任务全部结束
+impl Future for LocalSet {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let shared = &self.shared;
+        let _current = shared.enter();
+        shared.queue.register(cx.waker());
+
+        if shared.tick() {
+            cx.waker().wake_by_ref();
+        } else if shared.tasks.borrow().is_empty() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for LocalSet {
+    fn drop(&mut self) {
+        // 丢弃还没结束的任务，它们的 `JoinHandle` 得到 `JoinError::Cancelled`。
+        // 任务的析构函数里也可能用到 `spawn_local`
+        let _current = self.shared.enter();
+        let tasks = self.shared.tasks.take();
+        drop(tasks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::future;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{LocalSet, spawn_local};
+    use crate::runtime::MiniTokio;
+    use crate::runtime::tests::serial;
+    use crate::time;
+
+    /// `LocalSet` 里的任务可以跨 `.await` 持有 `Rc`，它们都在 `block_on` 所在的线程上执行
+    #[test]
+    fn run_until() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let local = LocalSet::new();
+            let finished = Rc::new(RefCell::new(Vec::new()));
+            let threads = Rc::new(RefCell::new(HashSet::new()));
+
+            let sum = local
+                .run_until(async {
+                    let handles: Vec<_> = (0..10u64)
+                        .map(|i| {
+                            let (finished, threads) = (finished.clone(), threads.clone());
+                            spawn_local(async move {
+                                // 和 rc_try.rs 不同，`rc` 活过了 `.await`，这个 future 不是 `Send`
+                                let rc = Rc::new(i);
+                                time::sleep(Duration::from_millis(10 - i)).await;
+                                finished.borrow_mut().push(*rc);
+                                threads.borrow_mut().insert(thread::current().id());
+                                rc
+                            })
+                        })
+                        .collect();
+
+                    let mut sum = 0;
+                    for handle in handles {
+                        sum += *handle.await.unwrap();
+                    }
+                    sum
+                })
+                .await;
+            assert_eq!(sum, 45);
+            assert_eq!(finished.borrow().len(), 10);
+            assert_eq!(*threads.borrow(), HashSet::from([thread::current().id()]));
+        });
+    }
+
+    /// `run_until` 结束后剩下的任务，直接 `.await` 这个 `LocalSet` 等它们跑完
+    #[test]
+    fn await_remaining() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let local = LocalSet::new();
+            let pending = local.spawn_local(future::pending::<()>());
+            pending.abort();
+            let late = local.spawn_local(async {
+                time::sleep(Duration::from_millis(5)).await;
+                Rc::new("late")
+            });
+            local.await;
+            assert!(pending.await.unwrap_err().is_cancelled());
+            assert_eq!(*late.await.unwrap(), "late");
+        });
+    }
+}
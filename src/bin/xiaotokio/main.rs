@@ -0,0 +1,190 @@
+// 各个模块仿照 tokio 提供了完整的 API，有一部分只在各模块的测试里用到
+#![cfg_attr(not(test), allow(dead_code, unused_imports, unused_macros))]
+
+mod coop;
+mod join;
+mod local;
+mod metrics;
+mod model;
+mod net;
+mod reactor;
+mod runtime;
+mod sync;
+mod task;
+mod task_local;
+mod time;
+
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+use net::TcpListener;
+use runtime::MiniTokio;
+use sync::{Mutex, mpsc};
+
+// 之前这里的 `Delay` 和 `delay()` 每个计时器都要开一个线程去睡眠，
+// 现在换成了 runtime 自带的时间轮，见 `time` 模块
+
+fn main() {
+    // `cargo run --bin xiaotokio -- echo` 在 MiniTokio 上运行 echo-server.rs 的 echo 服务，
+    // 设置了 `MINI_TOKIO_METRICS=<毫秒>` 时定期打印运行指标
+    if std::env::args().nth(1).as_deref() == Some("echo") {
+        let mini_tokio = MiniTokio::new();
+        if let Some(period) = std::env::var("MINI_TOKIO_METRICS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+        {
+            mini_tokio.dump_metrics(Duration::from_millis(period));
+        }
+        let result = mini_tokio.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:6142")?;
+            println!("Listening on {}", listener.local_addr()?);
+            echo_server(listener).await
+        });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // 原来的例子：spawn 一个任务然后 run，任务结束后 `run` 就返回了
+    let mini_tokio = MiniTokio::new();
+    mini_tokio.spawn(async {
+        let start = Instant::now();
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        println!("sleep ok");
+    });
+    mini_tokio.run();
+
+    // `block_on` 在当前线程上驱动根 future，它 spawn 的任务由 4 个 worker 执行
+    MiniTokio::with_workers(4).block_on(while_let_await());
+    println!("block_on ok");
+}
+
+/// 和 while_let_await_example.rs 一样的生产者、消费者，通道、锁、计时器都来自 MiniTokio
+async fn while_let_await() {
+    // 创建一个通道用于演示
+    let (tx, mut rx) = mpsc::channel(10);
+
+    // 创建一个共享的异步互斥锁
+    let counter = Arc::new(Mutex::new(0));
+
+    // 生产者任务
+    let producer = runtime::spawn(async move {
+        for i in 1..=5 {
+            tx.send(i).await.unwrap();
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    });
+
+    // 消费者任务 - 使用while let循环处理异步流
+    let counter_clone = counter.clone();
+    let consumer = runtime::spawn(async move {
+        while let Some(value) = rx.recv().await {
+            println!("接收到值: {}", value);
+
+            // 在循环体内使用异步锁
+            let mut lock = counter_clone.lock().await;
+            *lock += value;
+
+            // 重要：在进行其他异步操作前释放锁
+            drop(lock);
+
+            time::sleep(Duration::from_millis(5)).await;
+        }
+    });
+
+    producer.await.unwrap();
+    consumer.await.unwrap();
+
+    let final_count = *counter.lock().await;
+    println!("最终计数: {}", final_count);
+    assert_eq!(final_count, 15);
+    println!("while let ok");
+}
+
+/// 和 echo-server.rs 一样，只是 socket 和任务都来自 MiniTokio
+async fn echo_server(listener: TcpListener) -> io::Result<()> {
+    loop {
+        let (mut socket, _) = listener.accept().await?;
+
+        runtime::spawn(async move {
+            let mut buf = vec![0; 1024];
+
+            loop {
+                match socket.read(&mut buf).await {
+                    // 返回值 `Ok(0)` 说明对端已经关闭
+                    Ok(0) => return,
+                    Ok(n) => {
+                        // 将数据拷贝回 socket 中
+                        if socket.write_all(&buf[..n]).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => return,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::echo_server;
+    use crate::net::{TcpListener, TcpStream};
+    use crate::runtime::tests::serial;
+    use crate::runtime::{self, MiniTokio};
+
+    /// 启动 echo 服务，很多客户端同时连上去收发数据
+    #[test]
+    fn echo() {
+        const CLIENTS: usize = 100;
+
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = runtime::spawn(echo_server(listener));
+
+            let clients: Vec<_> = (0..CLIENTS)
+                .map(|i| {
+                    runtime::spawn(async move {
+                        let socket = TcpStream::connect(addr).await?;
+                        // 比服务端的缓冲区大，要分好几次才能 echo 完
+                        let message: Vec<u8> = (0..10_000).map(|j| (i + j) as u8).collect();
+                        let mut echoed = vec![0; message.len()];
+
+                        // 一边写一边读，避免双方的内核缓冲区都写满后互相等待
+                        let (mut reader, mut writer) = (&socket, &socket);
+                        let write = async {
+                            writer.write_all(&message).await?;
+                            writer.close().await
+                        };
+                        let (written, read) = futures::join!(write, reader.read_exact(&mut echoed));
+                        written?;
+                        read?;
+                        assert_eq!(echoed, message);
+
+                        // 我们关闭了写的一端，服务端读到 EOF 后也会关闭连接
+                        assert_eq!(reader.read(&mut [0; 1]).await?, 0);
+                        io::Result::Ok(())
+                    })
+                })
+                .collect();
+
+            for client in clients {
+                client.await.unwrap().unwrap();
+            }
+            server.abort();
+            assert!(server.await.unwrap_err().is_cancelled());
+        });
+    }
+}
@@ -0,0 +1,299 @@
+//! runtime 的运行指标
+//!
+//! 调度器在 spawn、poll、任务结束时更新这些计数器，`MiniTokio::metrics` 随时取一份快照，
+//! `MiniTokio::dump_metrics` 则定期把快照打印出来。
+//!
+//! 排查忙等的 future 主要看“没有进展的唤醒”：任务被唤醒后 poll 了一次又返回 `Pending`，期间没有任何
+//! 计时器、socket、`JoinHandle` 完成操作（见 `coop` 模块）。像 future_such.rs 里的 `Delay`
+//! 那样每次都唤醒自己的任务，这个数会跟着 poll 次数一起飞涨。
+//!
+//! 只统计 runtime 调度的任务，`LocalSet` 里的任务算在 poll 它的那个任务头上。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// poll 耗时直方图的分界，最后一个桶是 10ms 以上
+pub const POLL_BUCKETS: [Duration; 4] = [
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+];
+
+// `Display` 里最多列出多少个任务
+const TOP_TASKS: usize = 10;
+
+/// runtime 里的计数器
+pub struct Metrics {
+    next_id: AtomicU64,
+    spawned: AtomicU64,
+    completed: AtomicU64,
+    polls: AtomicU64,
+    // 单位是纳秒
+    busy: AtomicU64,
+    noop_wakeups: AtomicU64,
+    histogram: [AtomicU64; POLL_BUCKETS.len() + 1],
+    // 还没结束的任务
+    tasks: Mutex<HashMap<u64, Arc<TaskStats>>>,
+}
+
+/// 单个任务的计数器
+pub struct TaskStats {
+    id: u64,
+    location: &'static Location<'static>,
+    polls: AtomicU64,
+    busy: AtomicU64,
+    max_poll: AtomicU64,
+    noop_wakeups: AtomicU64,
+}
+
+/// `MiniTokio::metrics` 返回的快照
+#[derive(Debug, Clone)]
+pub struct RuntimeMetrics {
+    pub workers: usize,
+    pub spawned_tasks: u64,
+    pub completed_tasks: u64,
+    pub live_tasks: usize,
+    pub total_polls: u64,
+    /// 所有 poll 加起来花的时间
+    pub total_busy: Duration,
+    pub wakeups_without_progress: u64,
+    /// poll 耗时的分布，第 i 个桶是小于 `POLL_BUCKETS[i]` 的次数，最后一个桶是剩下的
+    pub poll_histogram: [u64; POLL_BUCKETS.len() + 1],
+    /// 全局队列里等待的任务数
+    pub injection_queue_depth: usize,
+    /// 各个 worker 本地队列里等待的任务数
+    pub worker_queue_depths: Vec<usize>,
+    /// 还没结束的任务，按 id 排序
+    pub tasks: Vec<TaskMetrics>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskMetrics {
+    pub id: u64,
+    /// 在哪里被 spawn 的
+    pub spawned_at: &'static Location<'static>,
+    pub polls: u64,
+    pub busy: Duration,
+    /// 最慢的一次 poll
+    pub max_poll: Duration,
+    pub wakeups_without_progress: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            next_id: AtomicU64::new(0),
+            spawned: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            polls: AtomicU64::new(0),
+            busy: AtomicU64::new(0),
+            noop_wakeups: AtomicU64::new(0),
+            histogram: Default::default(),
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn task_spawned(&self, location: &'static Location<'static>) -> Arc<TaskStats> {
+        let stats = Arc::new(TaskStats {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            location,
+            polls: AtomicU64::new(0),
+            busy: AtomicU64::new(0),
+            max_poll: AtomicU64::new(0),
+            noop_wakeups: AtomicU64::new(0),
+        });
+        self.spawned.fetch_add(1, Ordering::Relaxed);
+        self.tasks.lock().unwrap().insert(stats.id, stats.clone());
+        stats
+    }
+
+    pub fn task_completed(&self, stats: &TaskStats) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        self.tasks.lock().unwrap().remove(&stats.id);
+    }
+
+    /// 记录一次 poll：花了多久，返回的是不是 `Ready`，期间有没有资源完成操作
+    pub fn record_poll(&self, stats: &TaskStats, elapsed: Duration, ready: bool, progressed: bool) {
+        let nanos = elapsed.as_nanos() as u64;
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.busy.fetch_add(nanos, Ordering::Relaxed);
+        let bucket = POLL_BUCKETS
+            .iter()
+            .position(|&bound| elapsed < bound)
+            .unwrap_or(POLL_BUCKETS.len());
+        self.histogram[bucket].fetch_add(1, Ordering::Relaxed);
+
+        // 第一次 poll 不是被唤醒的，不算
+        let polls = stats.polls.fetch_add(1, Ordering::Relaxed);
+        stats.busy.fetch_add(nanos, Ordering::Relaxed);
+        stats.max_poll.fetch_max(nanos, Ordering::Relaxed);
+        if polls > 0 && !ready && !progressed {
+            self.noop_wakeups.fetch_add(1, Ordering::Relaxed);
+            stats.noop_wakeups.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 队列长度由调度器传进来
+    pub fn snapshot(
+        &self,
+        injection_queue_depth: usize,
+        worker_queue_depths: Vec<usize>,
+    ) -> RuntimeMetrics {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .map(|stats| TaskMetrics {
+                id: stats.id,
+                spawned_at: stats.location,
+                polls: stats.polls.load(Ordering::Relaxed),
+                busy: Duration::from_nanos(stats.busy.load(Ordering::Relaxed)),
+                max_poll: Duration::from_nanos(stats.max_poll.load(Ordering::Relaxed)),
+                wakeups_without_progress: stats.noop_wakeups.load(Ordering::Relaxed),
+            })
+            .collect();
+        tasks.sort_by_key(|task| task.id);
+
+        RuntimeMetrics {
+            workers: worker_queue_depths.len(),
+            spawned_tasks: self.spawned.load(Ordering::Relaxed),
+            completed_tasks: self.completed.load(Ordering::Relaxed),
+            live_tasks: tasks.len(),
+            total_polls: self.polls.load(Ordering::Relaxed),
+            total_busy: Duration::from_nanos(self.busy.load(Ordering::Relaxed)),
+            wakeups_without_progress: self.noop_wakeups.load(Ordering::Relaxed),
+            poll_histogram: self.histogram.each_ref().map(|n| n.load(Ordering::Relaxed)),
+            injection_queue_depth,
+            worker_queue_depths,
+            tasks,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "tasks: {} spawned, {} completed, {} live",
+            self.spawned_tasks, self.completed_tasks, self.live_tasks
+        )?;
+        writeln!(
+            f,
+            "polls: {} in {:?}, {} wakeups without progress",
+            self.total_polls, self.total_busy, self.wakeups_without_progress
+        )?;
+
+        write!(f, "poll time:")?;
+        for (i, count) in self.poll_histogram.iter().enumerate() {
+            match POLL_BUCKETS.get(i) {
+                Some(bound) => write!(f, " <{:?}: {}", bound, count)?,
+                None => write!(f, " >={:?}: {}", POLL_BUCKETS[i - 1], count)?,
+            }
+        }
+        writeln!(f)?;
+
+        write!(
+            f,
+            "queues: global {}, workers {:?}",
+            self.injection_queue_depth, self.worker_queue_depths
+        )?;
+
+        // 最可疑的任务排在前面：没有进展的唤醒最多的，其次是 poll 最多的
+        let mut tasks: Vec<_> = self.tasks.iter().collect();
+        tasks.sort_by_key(|task| std::cmp::Reverse((task.wakeups_without_progress, task.polls)));
+        for task in tasks.iter().take(TOP_TASKS) {
+            write!(
+                f,
+                "\n  task {} ({}): {} polls, {} without progress, busy {:?}, max {:?}",
+                task.id,
+                task.spawned_at,
+                task.polls,
+                task.wakeups_without_progress,
+                task.busy,
+                task.max_poll
+            )?;
+        }
+        if tasks.len() > TOP_TASKS {
+            write!(f, "\n  ... {} more", tasks.len() - TOP_TASKS)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Poll;
+    use std::time::Duration;
+
+    use crate::runtime::tests::serial;
+    use crate::runtime::{MiniTokio, spawn};
+    use crate::time;
+
+    /// 运行指标能看出哪个任务在忙等
+    #[test]
+    fn busy_task() {
+        let _serial = serial();
+        let mini_tokio = MiniTokio::with_workers(2);
+        mini_tokio.block_on(async {
+            let stop = Arc::new(AtomicBool::new(false));
+
+            // 和 future_such.rs 里的 `Delay` 一样不停唤醒自己，每次唤醒都没有任何进展
+            let spinner = {
+                let stop = stop.clone();
+                spawn(future::poll_fn(move |cx| {
+                    if stop.load(Ordering::SeqCst) {
+                        return Poll::Ready(());
+                    }
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }))
+            };
+            // 正常等待计时器的任务被唤醒时计时器已经到期，不算没有进展
+            let sleeper = spawn(time::sleep(Duration::from_millis(10)));
+            sleeper.await.unwrap();
+            time::sleep(Duration::from_millis(10)).await;
+
+            let snapshot = mini_tokio.metrics();
+            assert_eq!(snapshot.workers, 2);
+            assert_eq!(snapshot.spawned_tasks, 2);
+            assert_eq!(snapshot.completed_tasks, 1);
+            assert_eq!(snapshot.live_tasks, 1);
+
+            let task = &snapshot.tasks[0];
+            assert!(task.spawned_at.file().ends_with("metrics.rs"));
+            assert!(task.wakeups_without_progress > 100);
+            // 除了第一次 poll，每次都是白忙一场；另一个 worker 可能正在 poll 它，允许差一点
+            assert!(task.wakeups_without_progress + 2 >= task.polls);
+            assert!(snapshot.wakeups_without_progress >= task.wakeups_without_progress);
+
+            stop.store(true, Ordering::SeqCst);
+            spinner.await.unwrap();
+
+            // `JoinHandle` 在任务的 poll 返回之前就拿到了结果，稍等 worker 把任务记成结束
+            let mut snapshot = mini_tokio.metrics();
+            for _ in 0..100 {
+                if snapshot.live_tasks == 0 {
+                    break;
+                }
+                time::sleep(Duration::from_millis(1)).await;
+                snapshot = mini_tokio.metrics();
+            }
+            assert_eq!(snapshot.completed_tasks, 2);
+            assert_eq!(snapshot.live_tasks, 0);
+            assert!(snapshot.tasks.is_empty());
+            assert_eq!(
+                snapshot.poll_histogram.iter().sum::<u64>(),
+                snapshot.total_polls
+            );
+        });
+    }
+}
@@ -0,0 +1,201 @@
+//! 交错测试
+//!
+//! 借鉴 loom 的思路：把几个 future 放进一个只在当前线程上运行的小调度器，每一步可以选择 poll
+//! 任意一个已经被唤醒的 future（或者丢弃一个标记为可取消的 future），把所有选择的组合都跑一遍。
+//! 每一种顺序都要跑到所有 future 结束；还有 future 没结束却没有一个被唤醒，说明有唤醒丢了，
+//! 这时会报告出问题的调度顺序。
+//!
+//! 和 loom 不同，这里只穷举 future 之间的先后顺序，不模拟多核 CPU 的内存模型。
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Context;
+
+use futures::task::{self, ArcWake};
+
+// 顺序太多说明测试写得太大了
+const MAX_RUNS: usize = 1_000_000;
+// 一种顺序里最多走多少步，超过了多半是有 future 在忙等
+const MAX_STEPS: usize = 1_000;
+
+/// 一次执行里的所有 future
+pub struct Model {
+    threads: Vec<Thread>,
+}
+
+struct Thread {
+    future: Option<Pin<Box<dyn Future<Output = ()>>>>,
+    cancellable: bool,
+    woken: Arc<Woken>,
+}
+
+struct Woken(AtomicBool);
+
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Poll(usize),
+    Cancel(usize),
+}
+
+impl Model {
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'static) {
+        self.push(Box::pin(future), false);
+    }
+
+    /// 这个 future 可能在任意一步被丢弃，用来检查取消时会不会把锁、许可、通知带走
+    pub fn spawn_cancellable(&mut self, future: impl Future<Output = ()> + 'static) {
+        self.push(Box::pin(future), true);
+    }
+
+    fn push(&mut self, future: Pin<Box<dyn Future<Output = ()>>>, cancellable: bool) {
+        self.threads.push(Thread {
+            future: Some(future),
+            cancellable,
+            woken: Arc::new(Woken(AtomicBool::new(true))),
+        });
+    }
+
+    /// 当前可以走的所有步
+    fn steps(&self) -> Vec<Step> {
+        let mut steps = Vec::new();
+        for (i, thread) in self.threads.iter().enumerate() {
+            if thread.future.is_none() {
+                continue;
+            }
+            if thread.woken.0.load(Ordering::SeqCst) {
+                steps.push(Step::Poll(i));
+            }
+            if thread.cancellable {
+                steps.push(Step::Cancel(i));
+            }
+        }
+        steps
+    }
+
+    fn run(&mut self, step: Step) {
+        match step {
+            Step::Poll(i) => {
+                let thread = &mut self.threads[i];
+                thread.woken.0.store(false, Ordering::SeqCst);
+                let waker = task::waker(thread.woken.clone());
+                let future = thread.future.as_mut().unwrap();
+                if future
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_ready()
+                {
+                    thread.future = None;
+                }
+            }
+            Step::Cancel(i) => {
+                let future = self.threads[i].future.take();
+                drop(future);
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.threads.iter().all(|thread| thread.future.is_none())
+    }
+}
+
+impl ArcWake for Woken {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// 穷举 `build` 建出来的 future 的所有调度顺序，返回一共试了多少种
+///
+/// `build` 每次都要建一组全新的 future，返回的闭包在所有 future 结束后调用，用来检查最终状态。
+pub fn check<B, C>(build: B) -> usize
+where
+    B: Fn(&mut Model) -> C,
+    C: FnOnce(),
+{
+    // 每一步选了第几个，以及当时一共有几个可选
+    let mut path: Vec<(usize, usize)> = Vec::new();
+    let mut runs = 0;
+
+    loop {
+        runs += 1;
+        assert!(runs <= MAX_RUNS, "too many interleavings");
+
+        let mut model = Model {
+            threads: Vec::new(),
+        };
+        let finish = build(&mut model);
+        let mut schedule = Vec::new();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            for depth in 0.. {
+                assert!(depth < MAX_STEPS, "too many steps, is a future spinning?");
+                let steps = model.steps();
+                if steps.is_empty() {
+                    assert!(model.is_done(), "lost wakeup: no future can make progress");
+                    break;
+                }
+
+                // 先照着上一次的路走，走到头后每一步都先选第一个
+                if depth == path.len() {
+                    path.push((0, steps.len()));
+                }
+                let (choice, choices) = path[depth];
+                assert_eq!(choices, steps.len(), "the model is not deterministic");
+
+                schedule.push(steps[choice]);
+                model.run(steps[choice]);
+            }
+            drop(model);
+            finish();
+        }));
+        if let Err(payload) = result {
+            eprintln!("interleaving #{} failed, schedule: {:?}", runs, schedule);
+            panic::resume_unwind(payload);
+        }
+
+        // 回溯：找到最后一个还有别的选择的步，换成下一个选择
+        while let Some((choice, choices)) = path.pop() {
+            if choice + 1 < choices {
+                path.push((choice + 1, choices));
+                break;
+            }
+        }
+        if path.is_empty() {
+            return runs;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+    use std::task::Poll;
+
+    use super::check;
+    use crate::coop;
+
+    /// 两个各让出一次的 future：每个要 poll 两次，一共 C(4, 2) 种先后顺序
+    #[test]
+    fn counts_interleavings() {
+        let runs = check(|m| {
+            m.spawn(coop::yield_now());
+            m.spawn(coop::yield_now());
+            || {}
+        });
+        assert_eq!(runs, 6);
+    }
+
+    /// 返回 `Pending` 却没有登记 waker 的 future 永远不会再被 poll
+    #[test]
+    #[should_panic(expected = "lost wakeup")]
+    fn detects_lost_wakeup() {
+        check(|m| {
+            m.spawn(future::poll_fn(|_| Poll::<()>::Pending));
+            || {}
+        });
+    }
+}
@@ -0,0 +1,195 @@
+//! 跑在 MiniTokio reactor 上的 `TcpListener` 和 `TcpStream`
+//!
+//! 底层就是设置成非阻塞的 `std::net` socket，读写遇到 `WouldBlock` 时交给 reactor 等待就绪。
+//! `TcpStream` 实现了 `futures` 的 `AsyncRead` / `AsyncWrite`，可以直接用
+//! `AsyncReadExt::read`、`AsyncWriteExt::write_all` 这些方法。
+
+use std::future::poll_fn;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{self, Shutdown, SocketAddr, ToSocketAddrs};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use crate::reactor::{Interest, Registration};
+
+pub struct TcpListener {
+    // 先于 socket 被丢弃，从 epoll 中注销时 fd 还没关
+    registration: Registration,
+    inner: net::TcpListener,
+}
+
+pub struct TcpStream {
+    registration: Registration,
+    inner: net::TcpStream,
+}
+
+impl TcpListener {
+    /// 绑定地址并注册到当前 runtime 的 reactor 上，只能在 MiniTokio 里调用
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
+        let inner = net::TcpListener::bind(addr)?;
+        inner.set_nonblocking(true)?;
+        let registration = Registration::new(inner.as_raw_fd())?;
+        Ok(TcpListener {
+            registration,
+            inner,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (socket, addr) = poll_fn(|cx| {
+            self.registration
+                .poll_io(Interest::Read, cx, || self.inner.accept())
+        })
+        .await?;
+        Ok((TcpStream::new(socket)?, addr))
+    }
+}
+
+impl TcpStream {
+    fn new(inner: net::TcpStream) -> io::Result<TcpStream> {
+        inner.set_nonblocking(true)?;
+        let registration = Registration::new(inner.as_raw_fd())?;
+        Ok(TcpStream {
+            registration,
+            inner,
+        })
+    }
+
+    /// 非阻塞地建立连接，等 socket 可写后再检查连接是否成功
+    pub async fn connect(addr: SocketAddr) -> io::Result<TcpStream> {
+        let domain = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let flags = libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+        let fd = unsafe { libc::socket(domain, flags, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // 从这里开始 fd 由 `inner` 负责关闭
+        let inner = unsafe { net::TcpStream::from_raw_fd(fd) };
+
+        let (storage, len) = socket_addr(&addr);
+        let ret = unsafe { libc::connect(fd, (&raw const storage).cast(), len) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINPROGRESS) {
+                return Err(err);
+            }
+        }
+
+        let stream = TcpStream::new(inner)?;
+        poll_fn(|cx| stream.registration.poll_ready(Interest::Write, cx)).await;
+        if let Some(err) = stream.inner.take_error()? {
+            return Err(err);
+        }
+        Ok(stream)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+}
+
+// 读写两个方向各自记录 waker，所以可以像 `std::net::TcpStream` 一样通过 `&TcpStream` 同时读写
+impl AsyncRead for &TcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = *self;
+        this.registration
+            .poll_io(Interest::Read, cx, || (&this.inner).read(buf))
+    }
+}
+
+impl AsyncWrite for &TcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = *self;
+        this.registration
+            .poll_io(Interest::Write, cx, || (&this.inner).write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // 没有用户态缓冲，写进内核就算 flush 了
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.inner.shutdown(Shutdown::Write))
+    }
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_close(cx)
+    }
+}
+
+/// 把 `SocketAddr` 转成 `connect` 需要的 `sockaddr`
+fn socket_addr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: addr.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(addr.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe { (&raw mut storage).cast::<libc::sockaddr_in>().write(sin) };
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                },
+                sin6_scope_id: addr.scope_id(),
+            };
+            unsafe { (&raw mut storage).cast::<libc::sockaddr_in6>().write(sin6) };
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
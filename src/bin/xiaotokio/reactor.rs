@@ -0,0 +1,276 @@
+//! 基于 epoll 的 I/O reactor
+//!
+//! 每个 socket 以边沿触发的方式注册到 epoll，读、写两个方向各自记录“是否就绪”和等待它的 waker。
+//! 任务读写时遇到 `WouldBlock` 就清掉就绪标记，把 waker 存起来返回 `Pending`；
+//! epoll 报告事件后重新置位并唤醒任务。
+//!
+//! 和时间轮一样，reactor 由 worker 自己驱动：空闲的 worker 中有一个阻塞在 `epoll_wait` 上，
+//! 超时时间就是下一个计时器的到期时间，其他空闲 worker 在条件变量上睡眠。
+//! 有新任务要执行时通过 eventfd 把阻塞在 `epoll_wait` 里的 worker 叫醒。
+
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker, ready};
+use std::time::Duration;
+
+use crate::{coop, runtime};
+
+// eventfd 在 epoll 中的 token，其他 token 从 0 开始递增，不会和它冲突
+const WAKE_TOKEN: u64 = u64::MAX;
+// 一次 `epoll_wait` 最多取回多少个事件
+const MAX_EVENTS: usize = 1024;
+
+pub struct Reactor {
+    epoll: OwnedFd,
+    // 用来打断 `epoll_wait` 的 eventfd
+    wake: OwnedFd,
+    sources: Mutex<HashMap<u64, Arc<ScheduledIo>>>,
+    next_token: AtomicU64,
+    // 谁拿到这把锁谁就负责调用 `epoll_wait`，里面是事件缓冲区
+    driver: Mutex<Vec<libc::epoll_event>>,
+    // 有 worker 正阻塞在 `epoll_wait` 里
+    parked: AtomicBool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    Read,
+    Write,
+}
+
+/// 一个 fd 的就绪状态
+struct ScheduledIo {
+    state: Mutex<IoState>,
+}
+
+#[derive(Default)]
+struct IoState {
+    // 每收到一次事件加一，用来判断清除就绪标记时有没有新事件进来
+    tick: u64,
+    readable: bool,
+    writable: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+/// 注册在 reactor 上的 fd，被丢弃时自动注销
+pub struct Registration {
+    fd: RawFd,
+    token: u64,
+    io: Arc<ScheduledIo>,
+    shared: Arc<runtime::Shared>,
+}
+
+impl Reactor {
+    pub fn new() -> io::Result<Reactor> {
+        let epoll = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        let epoll = unsafe { OwnedFd::from_raw_fd(epoll) };
+        let wake = cvt(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) })?;
+        let wake = unsafe { OwnedFd::from_raw_fd(wake) };
+
+        let reactor = Reactor {
+            epoll,
+            wake,
+            sources: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(0),
+            driver: Mutex::new(Vec::with_capacity(MAX_EVENTS)),
+            parked: AtomicBool::new(false),
+        };
+        reactor.ctl(libc::EPOLL_CTL_ADD, reactor.wake.as_raw_fd(), WAKE_TOKEN)?;
+        Ok(reactor)
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, token: u64) -> io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET) as u32,
+            u64: token,
+        };
+        cvt(unsafe { libc::epoll_ctl(self.epoll.as_raw_fd(), op, fd, &mut event) })?;
+        Ok(())
+    }
+
+    /// 没人在驱动 reactor 时拿到驱动权
+    pub fn try_drive(&self) -> Option<MutexGuard<'_, Vec<libc::epoll_event>>> {
+        self.driver.try_lock().ok()
+    }
+
+    pub fn set_parked(&self, parked: bool) {
+        self.parked.store(parked, Ordering::SeqCst);
+    }
+
+    /// 等待 I/O 事件并唤醒对应的任务，`timeout` 为 `None` 时一直等
+    pub fn poll(&self, events: &mut Vec<libc::epoll_event>, timeout: Option<Duration>) {
+        // 向上取整到毫秒，避免在计时器到期前一点点醒来再空转一次
+        let timeout = timeout.map_or(-1, |t| {
+            t.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32
+        });
+
+        events.clear();
+        let n = unsafe {
+            libc::epoll_wait(
+                self.epoll.as_raw_fd(),
+                events.as_mut_ptr(),
+                MAX_EVENTS as libc::c_int,
+                timeout,
+            )
+        };
+        // 被信号打断（EINTR）时当作没有事件，调用方会重新来过
+        let Ok(n) = cvt(n) else {
+            return;
+        };
+        unsafe { events.set_len(n as usize) };
+
+        let mut wakers = Vec::new();
+        {
+            let sources = self.sources.lock().unwrap();
+            for event in events.iter() {
+                let (token, flags) = (event.u64, event.events as libc::c_int);
+                if token == WAKE_TOKEN {
+                    let mut buf = [0u8; 8];
+                    unsafe { libc::read(self.wake.as_raw_fd(), buf.as_mut_ptr().cast(), 8) };
+                    continue;
+                }
+                if let Some(io) = sources.get(&token) {
+                    io.set_ready(flags, &mut wakers);
+                }
+            }
+        }
+
+        // 唤醒任务会去碰调度器的锁，先放开 `sources`
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// 叫醒阻塞在 `epoll_wait` 里的 worker
+    pub fn unpark(&self) {
+        if self.parked.load(Ordering::SeqCst) {
+            let buf = 1u64.to_ne_bytes();
+            unsafe { libc::write(self.wake.as_raw_fd(), buf.as_ptr().cast(), 8) };
+        }
+    }
+}
+
+impl ScheduledIo {
+    fn set_ready(&self, flags: libc::c_int, wakers: &mut Vec<Waker>) {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+
+        // 出错或者对端关闭时两个方向都算就绪，让读写操作自己把错误报出来
+        let closed = flags & (libc::EPOLLERR | libc::EPOLLHUP) != 0;
+        if closed || flags & (libc::EPOLLIN | libc::EPOLLRDHUP) != 0 {
+            state.readable = true;
+            wakers.extend(state.read_waker.take());
+        }
+        if closed || flags & libc::EPOLLOUT != 0 {
+            state.writable = true;
+            wakers.extend(state.write_waker.take());
+        }
+    }
+}
+
+impl Registration {
+    /// 把非阻塞的 `fd` 注册到当前 runtime 的 reactor 上
+    pub fn new(fd: RawFd) -> io::Result<Registration> {
+        let shared = runtime::current();
+        let reactor = shared.io();
+        let token = reactor.next_token.fetch_add(1, Ordering::Relaxed);
+        let io = Arc::new(ScheduledIo {
+            state: Mutex::new(IoState::default()),
+        });
+
+        // 先放进 `sources` 再注册，注册后马上到来的事件才找得到它
+        reactor.sources.lock().unwrap().insert(token, io.clone());
+        if let Err(e) = reactor.ctl(libc::EPOLL_CTL_ADD, fd, token) {
+            reactor.sources.lock().unwrap().remove(&token);
+            return Err(e);
+        }
+
+        Ok(Registration {
+            fd,
+            token,
+            io,
+            shared,
+        })
+    }
+
+    /// 等到 `interest` 方向就绪
+    pub fn poll_ready(&self, interest: Interest, cx: &mut Context<'_>) -> Poll<()> {
+        let mut coop = ready!(coop::poll_proceed(cx));
+        ready!(self.readiness(interest, cx));
+        coop.made_progress();
+        Poll::Ready(())
+    }
+
+    /// `interest` 方向就绪时返回当时的 tick，清除就绪标记时要带上它
+    fn readiness(&self, interest: Interest, cx: &mut Context<'_>) -> Poll<u64> {
+        let mut state = self.io.state.lock().unwrap();
+        let (ready, waker) = match interest {
+            Interest::Read => (state.readable, &mut state.read_waker),
+            Interest::Write => (state.writable, &mut state.write_waker),
+        };
+
+        if ready {
+            return Poll::Ready(state.tick);
+        }
+        if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+            *waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    /// 读写遇到 `WouldBlock` 后调用。如果期间 tick 变了，说明又来了新事件，就绪标记要保留
+    fn clear_ready(&self, interest: Interest, tick: u64) {
+        let mut state = self.io.state.lock().unwrap();
+        if state.tick != tick {
+            return;
+        }
+        match interest {
+            Interest::Read => state.readable = false,
+            Interest::Write => state.writable = false,
+        }
+    }
+
+    /// 在 fd 就绪时执行 `op`，`op` 返回 `WouldBlock` 时等下一次就绪
+    pub fn poll_io<T>(
+        &self,
+        interest: Interest,
+        cx: &mut Context<'_>,
+        mut op: impl FnMut() -> io::Result<T>,
+    ) -> Poll<io::Result<T>> {
+        // 一直有数据可读的 socket 不会返回 `Pending`，每次读写都要扣预算
+        let mut coop = ready!(coop::poll_proceed(cx));
+
+        loop {
+            let tick = ready!(self.readiness(interest, cx));
+
+            match op() {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.clear_ready(interest, tick),
+                result => {
+                    coop.made_progress();
+                    return Poll::Ready(result);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let reactor = self.shared.io();
+        // fd 还没关闭（它由外层的 socket 持有，在这之后才被关闭），注销失败也没什么可做的
+        let _ = reactor.ctl(libc::EPOLL_CTL_DEL, self.fd, self.token);
+        reactor.sources.lock().unwrap().remove(&self.token);
+    }
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
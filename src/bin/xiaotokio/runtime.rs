@@ -0,0 +1,612 @@
+//! 多线程、work-stealing 的调度器
+//!
+//! 每个 worker 线程都有自己的本地队列，任务在 worker 上被唤醒或者 spawn 时优先放进本地队列，
+//! 不用和其他线程竞争。本地队列空了就先从全局队列（injector）批量取一些，再去偷其他 worker
+//! 的任务。从 runtime 外面 spawn、唤醒的任务都进全局队列。都没有任务时 worker 在条件变量上睡眠，
+//! 有新任务进队时再被叫醒。
+//!
+//! 调度器记录还没结束的任务数量，`run` 在所有任务都结束后返回；`block_on` 则在当前线程上
+//! 驱动一个根 future，它结束时 runtime 就关闭，不再等其他任务。
+//!
+//! 计时器由 worker 在空闲时推进，睡眠时最多睡到下一个计时器到期，见 `time` 模块。
+//! 空闲的 worker 中有一个会阻塞在 epoll 上等待 I/O 事件，见 `reactor` 模块。
+//! 调度过程中的各种计数见 `metrics` 模块。
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::iter;
+use std::panic::Location;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, fence};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll};
+use std::thread::{self, JoinHandle as ThreadHandle, Thread};
+use std::time::{Duration, Instant};
+
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+use futures::task::{self, ArcWake};
+
+use crate::coop;
+use crate::join::JoinHandle;
+use crate::metrics::{Metrics, RuntimeMetrics, TaskStats};
+use crate::reactor::Reactor;
+use crate::task::Task;
+use crate::time::Driver;
+
+// worker 每 poll 这么多次任务就先看一眼全局队列、推进一次时间轮、检查一次 I/O 事件，
+// 本地队列里的任务一直互相唤醒时，外面 spawn 的任务、计时器、socket 也不会饿死
+const EVENT_INTERVAL: u32 = 61;
+
+pub struct MiniTokio {
+    shared: Arc<Shared>,
+    // 各个 worker 的本地队列，`run` 时分给各个线程
+    workers: Mutex<Vec<Worker<Arc<Task>>>>,
+}
+
+/// 所有 worker 共享的调度器状态
+pub struct Shared {
+    injector: Injector<Arc<Task>>,
+    stealers: Vec<Stealer<Arc<Task>>>,
+    // 正在睡眠的 worker 数量，没人睡眠时入队就不用去碰锁
+    sleepers: AtomicUsize,
+    // 还没结束的任务数量，`block_on` 的根 future 也算一个
+    live: AtomicUsize,
+    // 置位后 worker 线程退出
+    shutdown: AtomicBool,
+    timer: Driver,
+    io: Reactor,
+    metrics: Metrics,
+    lock: Mutex<()>,
+    condvar: Condvar,
+}
+
+/// 当前线程所属的 runtime，不在 runtime 里时为 `None`
+struct Local {
+    shared: Arc<Shared>,
+    // 只有 worker 线程才有本地队列，`block_on` 所在的线程没有
+    queue: Option<Worker<Arc<Task>>>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<Option<Local>> = const { RefCell::new(None) };
+}
+
+impl MiniTokio {
+    /// 初始化一个新的 mini-tokio 实例，worker 数量和 CPU 核数相同
+    pub fn new() -> MiniTokio {
+        let workers = thread::available_parallelism().map_or(4, |n| n.get());
+        MiniTokio::with_workers(workers)
+    }
+
+    /// 使用 `workers` 个线程执行任务
+    pub fn with_workers(workers: usize) -> MiniTokio {
+        assert!(workers > 0, "at least one worker is required");
+
+        let queues: Vec<_> = (0..workers).map(|_| Worker::new_fifo()).collect();
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: queues.iter().map(Worker::stealer).collect(),
+            sleepers: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            timer: Driver::new(),
+            io: Reactor::new().expect("failed to create epoll reactor"),
+            metrics: Metrics::new(),
+            lock: Mutex::new(()),
+            condvar: Condvar::new(),
+        });
+
+        MiniTokio {
+            shared,
+            workers: Mutex::new(queues),
+        }
+    }
+
+    /// 启动所有 worker，当前线程也作为其中一个 worker 执行任务，所有任务都结束后返回
+    pub fn run(&self) {
+        let mut queues = self.take_queues();
+        let first = queues.pop().unwrap();
+
+        // 没有任何任务时直接结束
+        if self.shared.live.load(Ordering::SeqCst) == 0 {
+            self.shared.shutdown();
+        }
+
+        let threads = self.start_workers(queues);
+        self.shared.clone().work(first);
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    /// 在当前线程上执行 `future` 直到它结束，期间它 spawn 的任务由 worker 线程执行
+    ///
+    /// `future` 结束后 runtime 随之关闭，还没执行完的任务会被丢弃。
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let threads = self.start_workers(self.take_queues());
+
+        // 根 future 也算一个活着的任务，保证它结束前 worker 不会因为暂时没有任务而退出
+        self.shared.live.fetch_add(1, Ordering::SeqCst);
+        let _context = enter(self.shared.clone(), None);
+
+        // 根 future 不是 `Task`，被唤醒时直接 unpark 当前线程
+        let waker = task::waker(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        let output = loop {
+            match coop::budget(|| future.as_mut().poll(&mut cx)).0 {
+                Poll::Ready(output) => break output,
+                Poll::Pending => thread::park(),
+            }
+        };
+
+        self.shared.shutdown();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        output
+    }
+
+    fn take_queues(&self) -> Vec<Worker<Arc<Task>>> {
+        let queues = std::mem::take(&mut *self.workers.lock().unwrap());
+        assert!(
+            !queues.is_empty(),
+            "`run` or `block_on` can only be called once"
+        );
+        queues
+    }
+
+    fn start_workers(&self, queues: Vec<Worker<Arc<Task>>>) -> Vec<ThreadHandle<()>> {
+        queues
+            .into_iter()
+            .enumerate()
+            .map(|(i, queue)| {
+                let shared = self.shared.clone();
+                thread::Builder::new()
+                    .name(format!("mini-tokio-worker-{}", i + 1))
+                    .spawn(move || shared.work(queue))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    /// 在下面函数中，通过参数传入的 future 被 `Task` 包裹起来，然后会被推入到调度队列中，当 `run` 被调用时，该 future 将被执行
+    ///
+    /// 通过返回的 `JoinHandle` 可以拿到 future 的输出
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        Task::spawn(future, &self.shared)
+    }
+
+    /// 当前的运行指标
+    pub fn metrics(&self) -> RuntimeMetrics {
+        self.shared.metrics()
+    }
+
+    /// 每隔 `period` 把运行指标打印到标准错误，runtime 关闭后停止
+    pub fn dump_metrics(&self, period: Duration) {
+        let shared = Arc::downgrade(&self.shared);
+        thread::Builder::new()
+            .name("mini-tokio-metrics".to_string())
+            .spawn(move || {
+                loop {
+                    thread::sleep(period);
+                    let Some(shared) = shared.upgrade() else {
+                        return;
+                    };
+                    if shared.shutdown.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    eprintln!("{}", shared.metrics());
+                }
+            })
+            .unwrap();
+    }
+}
+
+/// 在当前 runtime 上 spawn 一个任务，只能在 MiniTokio 的任务或者 `block_on` 中调用
+#[track_caller]
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Task::spawn(future, &current())
+}
+
+/// 当前线程所在的 runtime
+pub fn current() -> Arc<Shared> {
+    LOCAL.with(|local| {
+        let local = local.borrow();
+        let local = local
+            .as_ref()
+            .expect("must be called from inside a MiniTokio runtime");
+        local.shared.clone()
+    })
+}
+
+impl Shared {
+    /// 把任务放进队列：当前线程是这个调度器的 worker 就放本地队列，否则放全局队列
+    pub fn schedule(self: &Arc<Self>, task: Arc<Task>) {
+        let task = LOCAL.with(|local| match local.borrow().as_ref() {
+            Some(Local {
+                shared,
+                queue: Some(queue),
+            }) if Arc::ptr_eq(shared, self) => {
+                queue.push(task);
+                None
+            }
+            _ => Some(task),
+        });
+        if let Some(task) = task {
+            self.injector.push(task);
+        }
+
+        self.wake_sleeper();
+    }
+
+    pub fn timer(&self) -> &Driver {
+        &self.timer
+    }
+
+    pub fn io(&self) -> &Reactor {
+        &self.io
+    }
+
+    /// 有睡眠的 worker 就叫醒一个，让它来取（或者偷）刚入队的任务，或者重新计算该睡多久
+    pub fn wake_sleeper(&self) {
+        // 和 `sleep` 里的 fence 配对：要么我们看到了对方在睡，要么对方睡前的检查能看到新任务
+        fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            {
+                let _guard = self.lock.lock().unwrap();
+                self.condvar.notify_one();
+            }
+            // 睡着的可能是阻塞在 epoll 里的那个 worker
+            self.io.unpark();
+        }
+    }
+
+    pub fn task_spawned(&self, location: &'static Location<'static>) -> Arc<TaskStats> {
+        self.live.fetch_add(1, Ordering::SeqCst);
+        self.metrics.task_spawned(location)
+    }
+
+    /// 任务结束（或者被丢弃）时调用，最后一个任务结束后关闭 runtime
+    pub fn task_finished(&self, stats: &TaskStats) {
+        self.metrics.task_completed(stats);
+        if self.live.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown();
+        }
+    }
+
+    pub fn record_poll(&self, stats: &TaskStats, elapsed: Duration, ready: bool, progressed: bool) {
+        self.metrics.record_poll(stats, elapsed, ready, progressed);
+    }
+
+    pub fn metrics(&self) -> RuntimeMetrics {
+        self.metrics.snapshot(
+            self.injector.len(),
+            self.stealers.iter().map(Stealer::len).collect(),
+        )
+    }
+
+    fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        {
+            let _guard = self.lock.lock().unwrap();
+            self.condvar.notify_all();
+        }
+        self.io.unpark();
+    }
+
+    /// worker 线程的主循环
+    fn work(self: Arc<Self>, queue: Worker<Arc<Task>>) {
+        let _context = enter(self.clone(), Some(queue));
+        let mut polls = 0u32;
+
+        while !self.shutdown.load(Ordering::SeqCst) {
+            // 取任务时只短暂借用本地队列，poll 期间任务可能会再往本地队列里放东西
+            let task = LOCAL.with(|local| {
+                let local = local.borrow();
+                let queue = local.as_ref().unwrap().queue.as_ref().unwrap();
+                if polls.is_multiple_of(EVENT_INTERVAL)
+                    && let Some(task) = self.injector.steal().success()
+                {
+                    return Some(task);
+                }
+                self.find_task(queue)
+            });
+
+            match task {
+                Some(task) => {
+                    task.poll();
+                    polls = polls.wrapping_add(1);
+                    if polls.is_multiple_of(EVENT_INTERVAL) {
+                        self.timer.process();
+                        if let Some(mut events) = self.io.try_drive() {
+                            self.io.poll(&mut events, Some(Duration::ZERO));
+                        }
+                    }
+                }
+                // 没事可做时先看看有没有到期的计时器，它们唤醒的任务会进本地队列
+                None if self.timer.process() => {}
+                None => self.sleep(),
+            }
+        }
+
+        // runtime 关闭了，丢弃还在全局队列里的任务，本地队列在 `_context` 被丢弃时一起清掉
+        while self.injector.steal().is_success() {}
+    }
+
+    /// 依次从本地队列、全局队列、其他 worker 的队列中找一个任务
+    fn find_task(&self, local: &Worker<Arc<Task>>) -> Option<Arc<Task>> {
+        local.pop().or_else(|| {
+            iter::repeat_with(|| {
+                // 从全局队列取一批放进本地队列，减少之后访问全局队列的次数
+                self.injector.steal_batch_and_pop(local).or_else(|| {
+                    self.stealers
+                        .iter()
+                        .map(Stealer::steal)
+                        .collect::<Steal<_>>()
+                })
+            })
+            // 有竞争时 `steal` 会返回 `Retry`，重新试一次
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+
+    fn has_work(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|s| !s.is_empty())
+    }
+
+    fn sleep(&self) {
+        let guard = self.lock.lock().unwrap();
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+
+        // 睡眠前再检查一次，避免在找任务和睡眠之间入队的任务没人处理；
+        // 有计时器的话最多睡到它到期，醒来后由 `work` 推进时间轮
+        if self.has_work() || self.shutdown.load(Ordering::SeqCst) {
+            drop(guard);
+        } else {
+            let timeout = self
+                .timer
+                .next_deadline()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+            if let Some(mut events) = self.io.try_drive() {
+                // 没有其他 worker 在等 I/O，由我们阻塞在 epoll 上。
+                // 放开调度器的锁之后再检查一次，和 `wake_sleeper` 里的 `unpark` 配对
+                drop(guard);
+                self.io.set_parked(true);
+                if !self.has_work() && !self.shutdown.load(Ordering::SeqCst) {
+                    self.io.poll(&mut events, timeout);
+                }
+                self.io.set_parked(false);
+            } else {
+                match timeout {
+                    None => drop(self.condvar.wait(guard).unwrap()),
+                    Some(timeout) => drop(self.condvar.wait_timeout(guard, timeout).unwrap()),
+                }
+            }
+        }
+
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// 把当前线程登记到 runtime 中，返回的 guard 被丢弃时撤销登记
+fn enter(shared: Arc<Shared>, queue: Option<Worker<Arc<Task>>>) -> impl Drop {
+    struct Exit;
+
+    impl Drop for Exit {
+        fn drop(&mut self) {
+            // 本地队列里剩下的任务也在这里被丢弃
+            LOCAL.with(|local| local.borrow_mut().take());
+        }
+    }
+
+    LOCAL.with(|local| *local.borrow_mut() = Some(Local { shared, queue }));
+    Exit
+}
+
+/// `block_on` 用的 waker：唤醒时 unpark 调用 `block_on` 的线程
+struct ThreadWaker(Thread);
+
+impl ArcWake for ThreadWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.unpark();
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::HashMap;
+    use std::future;
+    use std::panic;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex, MutexGuard};
+    use std::task::Poll;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{MiniTokio, spawn};
+    use crate::time;
+
+    /// 这些测试对时间很敏感，一个接一个地跑，免得几个 runtime 互相抢 CPU
+    pub fn serial() -> MutexGuard<'static, ()> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        // 前面的测试失败了也不影响后面的
+        LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 任务里再 spawn 出来的任务也要等，全部结束后 `run` 才返回
+    #[test]
+    fn run_until_idle() {
+        let _serial = serial();
+        let finished = Arc::new(AtomicUsize::new(0));
+        let mini_tokio = MiniTokio::with_workers(2);
+
+        for _ in 0..10 {
+            let finished = finished.clone();
+            mini_tokio.spawn(async move {
+                for _ in 0..10 {
+                    let finished = finished.clone();
+                    spawn(async move {
+                        time::sleep(Duration::from_millis(5)).await;
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+        }
+
+        mini_tokio.run();
+        assert_eq!(finished.load(Ordering::SeqCst), 100);
+    }
+
+    /// 和 spawn_number.rs 一样，通过 `JoinHandle` 拿到任务的返回值
+    #[test]
+    fn join() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let handle = spawn(async { 10086 });
+            assert_eq!(handle.await.unwrap(), 10086);
+
+            // panic 只会让这个任务失败，错误通过 `JoinHandle` 返回
+            let handle = spawn(async {
+                panic!("boom");
+            });
+            let err = handle.await.unwrap_err();
+            assert!(err.is_panic());
+            assert_eq!(err.into_panic().downcast_ref::<&str>(), Some(&"boom"));
+
+            let handle = spawn(future::pending::<()>());
+            assert!(!handle.is_finished());
+            handle.abort();
+            let err = handle.await.unwrap_err();
+            assert!(err.is_cancelled());
+        });
+    }
+
+    /// 任务在 poll 的过程中被其他线程唤醒：不会被两个 worker 同时 poll，这次唤醒也不会丢
+    #[test]
+    fn wake_during_poll() {
+        const TASKS: usize = 100;
+        const POLLS: usize = 100;
+
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let handles: Vec<_> = (0..TASKS)
+                .map(|_| {
+                    let polling = AtomicBool::new(false);
+                    let mut polls = 0;
+                    spawn(future::poll_fn(move |cx| {
+                        assert!(
+                            !polling.swap(true, Ordering::SeqCst),
+                            "task polled concurrently"
+                        );
+                        polls += 1;
+                        let done = polls == POLLS;
+                        if !done {
+                            // 另一个线程立刻唤醒我们，这时多半还在 poll 里面
+                            let waker = cx.waker().clone();
+                            thread::spawn(move || waker.wake());
+                            let start = Instant::now();
+                            while start.elapsed() < Duration::from_micros(50) {}
+                        }
+                        polling.store(false, Ordering::SeqCst);
+                        if done {
+                            Poll::Ready(polls)
+                        } else {
+                            Poll::Pending
+                        }
+                    }))
+                })
+                .collect();
+
+            for handle in handles {
+                assert_eq!(handle.await.unwrap(), POLLS);
+            }
+        });
+    }
+
+    /// 大量任务 panic 也不影响 worker 线程和其他任务
+    #[test]
+    fn panics() {
+        let _serial = serial();
+        // 不让默认的 panic hook 打印一千遍
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+
+        MiniTokio::with_workers(4).block_on(async {
+            let handles: Vec<_> = (0..2000)
+                .map(|i| {
+                    spawn(async move {
+                        time::sleep(Duration::from_millis(1)).await;
+                        if i % 2 == 0 {
+                            panic!("task {} panicked", i);
+                        }
+                        i
+                    })
+                })
+                .collect();
+
+            for (i, handle) in handles.into_iter().enumerate() {
+                match handle.await {
+                    Ok(out) => assert_eq!(out, i),
+                    Err(err) => {
+                        assert_eq!(i % 2, 0);
+                        assert_eq!(
+                            err.to_string(),
+                            format!("task panicked: task {} panicked", i)
+                        );
+                    }
+                }
+            }
+        });
+
+        panic::set_hook(hook);
+    }
+
+    /// 一个任务在 worker 上 spawn 大量子任务，它们都进了这个 worker 的本地队列，
+    /// 其他 worker 只有靠偷才能分到活干
+    #[test]
+    fn stealing() {
+        const TASKS: usize = 10_000;
+
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            // 先跳到 worker 线程上，`block_on` 所在的线程没有本地队列
+            let handles = spawn(async {
+                (0..TASKS)
+                    .map(|_| {
+                        spawn(async {
+                            // 模拟一点计算量
+                            let start = Instant::now();
+                            while start.elapsed() < Duration::from_micros(20) {}
+
+                            thread::current().name().unwrap().to_string()
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await
+            .unwrap();
+
+            let mut threads = HashMap::new();
+            for handle in handles {
+                *threads.entry(handle.await.unwrap()).or_insert(0) += 1;
+            }
+            assert_eq!(threads.values().sum::<usize>(), TASKS);
+            assert!(threads.len() > 1, "no task was stolen by other workers");
+        });
+    }
+}
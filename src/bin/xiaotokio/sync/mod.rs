@@ -0,0 +1,18 @@
+//! 异步同步原语
+//!
+//! 全部只靠 `Waker` 实现，不依赖 MiniTokio 的调度器，放在别的 executor 上也能用：
+//! 等待的一方把 waker 登记在原语里返回 `Pending`，另一方改完状态后把它叫醒。
+//! 等待中的 future 被丢弃时会把自己从等待队列里摘掉，已经分给它的许可、通知会转交给下一个人。
+//!
+//! 内部状态用 `std::sync::Mutex` 保护，锁只在改状态时短暂持有，不会跨过 `.await`。
+
+mod mutex;
+mod notify;
+mod semaphore;
+
+pub mod mpsc;
+pub mod oneshot;
+
+pub use mutex::Mutex;
+pub use notify::Notify;
+pub use semaphore::{Semaphore, TryAcquireError};
@@ -0,0 +1,284 @@
+//! 有界的多生产者、单消费者通道
+//!
+//! 缓冲区的空位用信号量表示：发送前先拿一个许可，接收方取走一个值后还一个许可，
+//! 缓冲区满了发送方就在信号量上排队等。
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker, ready};
+
+use super::semaphore::{Semaphore, TryAcquireError};
+use crate::coop;
+
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+struct Chan<T> {
+    state: Mutex<State<T>>,
+    // 缓冲区里的空位
+    semaphore: Semaphore,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    rx_waker: Option<Waker>,
+}
+
+/// 接收端已经关闭，没发出去的值原样还回来
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+/// 最多缓冲 `buffer` 个值的通道
+pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(buffer > 0, "mpsc bounded channel requires buffer > 0");
+    let chan = Arc::new(Chan {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(buffer),
+            senders: 1,
+            rx_waker: None,
+        }),
+        semaphore: Semaphore::new(buffer),
+    });
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+impl<T> Sender<T> {
+    /// 发送 `value`，缓冲区满了就等到有空位
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        // 接收端关闭时会关闭信号量
+        match self.chan.semaphore.acquire_permits(1).await {
+            Ok(()) => {
+                self.chan.push(value);
+                Ok(())
+            }
+            Err(_) => Err(SendError(value)),
+        }
+    }
+
+    /// 不等待，缓冲区满了就返回 `TrySendError::Full`
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match self.chan.semaphore.try_acquire() {
+            Ok(permit) => {
+                // 许可跟着值进了缓冲区，等接收方取走时再还
+                permit.forget();
+                self.chan.push(value);
+                Ok(())
+            }
+            Err(TryAcquireError::NoPermits) => Err(TrySendError::Full(value)),
+            Err(TryAcquireError::Closed) => Err(TrySendError::Closed(value)),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.chan.state.lock().unwrap().senders += 1;
+        Sender {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.chan.state.lock().unwrap();
+        state.senders -= 1;
+        // 最后一个发送方没了，叫醒接收方让它看到通道已经关闭
+        let waker = if state.senders == 0 {
+            state.rx_waker.take()
+        } else {
+            None
+        };
+        drop(state);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Chan<T> {
+    fn push(&self, value: T) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.queue.push_back(value);
+            state.rx_waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// 接收下一个值，所有发送方都被丢弃（或者通道被关闭）并且缓冲区空了时返回 `None`
+    pub async fn recv(&mut self) -> Option<T> {
+        std::future::poll_fn(|cx| {
+            // `while let Some(v) = rx.recv().await` 在缓冲区一直有数据时不会让出 worker
+            let mut coop = ready!(coop::poll_proceed(cx));
+            let mut state = self.chan.state.lock().unwrap();
+
+            if let Some(value) = state.queue.pop_front() {
+                drop(state);
+                self.chan.semaphore.add_permits(1);
+                coop.made_progress();
+                return Poll::Ready(Some(value));
+            }
+            // 接收端自己关闭了通道，缓冲区取空后也算结束
+            if state.senders == 0 || self.chan.semaphore.is_closed() {
+                return Poll::Ready(None);
+            }
+            state.rx_waker = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// 关闭通道：之后的发送都会失败，已经在缓冲区里的值还能收到
+    pub fn close(&mut self) {
+        self.chan.semaphore.close();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+        // 缓冲区里的值没人要了，现在就丢掉，不用等最后一个发送方
+        let queue = std::mem::take(&mut self.chan.state.lock().unwrap().queue);
+        drop(queue);
+    }
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "channel closed".fmt(f)
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => "channel full".fmt(f),
+            TrySendError::Closed(_) => "channel closed".fmt(f),
+        }
+    }
+}
+
+impl<T> std::error::Error for TrySendError<T> {}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{SendError, TrySendError, channel};
+    use crate::model;
+    use crate::runtime::tests::serial;
+    use crate::runtime::{MiniTokio, spawn};
+
+    /// 缓冲区只有 1：每个发送方的值按顺序到达，一个不少
+    #[test]
+    fn interleavings() {
+        model::check(|m| {
+            let (tx, mut rx) = channel(1);
+            let received = Rc::new(RefCell::new(Vec::new()));
+            for sender in 0..2 {
+                let tx = tx.clone();
+                m.spawn(async move {
+                    for i in 0..2 {
+                        tx.send((sender, i)).await.unwrap();
+                    }
+                });
+            }
+            drop(tx);
+
+            let out = received.clone();
+            m.spawn(async move {
+                while let Some(value) = rx.recv().await {
+                    out.borrow_mut().push(value);
+                }
+            });
+            move || {
+                let received = received.borrow();
+                assert_eq!(received.len(), 4);
+                for sender in 0..2 {
+                    let values: Vec<_> = received.iter().filter(|v| v.0 == sender).collect();
+                    assert_eq!(values, [&(sender, 0), &(sender, 1)]);
+                }
+            }
+        });
+    }
+
+    /// 很多发送方挤一个小缓冲区
+    #[test]
+    fn many_senders() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let (tx, mut rx) = channel(16);
+            for sender in 0..8u64 {
+                let tx = tx.clone();
+                spawn(async move {
+                    for i in 0..1000 {
+                        tx.send(sender * 1000 + i).await.unwrap();
+                    }
+                });
+            }
+            drop(tx);
+            let mut sum = 0;
+            while let Some(value) = rx.recv().await {
+                sum += value;
+            }
+            assert_eq!(sum, (0..8000).sum::<u64>());
+        });
+    }
+
+    #[test]
+    fn try_send_and_close() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let (tx, mut rx) = channel(1);
+            tx.try_send(1).unwrap();
+            assert!(matches!(tx.try_send(2), Err(TrySendError::Full(2))));
+            rx.close();
+            assert!(tx.is_closed());
+            assert!(matches!(tx.try_send(3), Err(TrySendError::Closed(3))));
+            assert_eq!(tx.send(4).await, Err(SendError(4)));
+            assert_eq!(rx.recv().await, Some(1));
+            assert_eq!(rx.recv().await, None);
+        });
+    }
+}
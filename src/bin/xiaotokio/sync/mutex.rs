@@ -0,0 +1,154 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use super::semaphore::{Semaphore, TryAcquireError};
+
+/// 异步互斥锁：拿不到锁时让出 worker，而不是阻塞线程
+///
+/// 用只有一个许可的信号量实现，所以等锁的任务按先来后到拿到锁。
+pub struct Mutex<T: ?Sized> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+// 和 `std::sync::Mutex` 一样，只要求 `T: Send`：同一时间只有拿着锁的那个线程能访问 `value`
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+/// 锁已经被别人拿着
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TryLockError;
+
+impl<T> Mutex<T> {
+    pub fn new(value: T) -> Mutex<T> {
+        Mutex {
+            semaphore: Semaphore::new(1),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        // 信号量不会被关闭
+        self.semaphore.acquire_permits(1).await.unwrap();
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, T>, TryLockError> {
+        match self.semaphore.try_acquire() {
+            Ok(permit) => {
+                permit.forget();
+                Ok(MutexGuard { mutex: self })
+            }
+            Err(TryAcquireError::NoPermits) => Err(TryLockError),
+            Err(TryAcquireError::Closed) => unreachable!(),
+        }
+    }
+
+    /// 有 `&mut self` 就不可能有别人拿着锁
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.semaphore.add_permits(1);
+    }
+}
+
+impl fmt::Display for TryLockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "operation would block".fmt(f)
+    }
+}
+
+impl std::error::Error for TryLockError {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::coop;
+    use crate::model;
+    use crate::runtime::tests::serial;
+    use crate::runtime::{MiniTokio, spawn};
+    use crate::sync::Mutex;
+
+    /// 两个任务拿着锁做“读-让出-写”，第三个任务等锁等到一半可能被取消
+    #[test]
+    fn interleavings() {
+        model::check(|m| {
+            let mutex = Arc::new(Mutex::new(0));
+            for _ in 0..2 {
+                let mutex = mutex.clone();
+                m.spawn(async move {
+                    let mut guard = mutex.lock().await;
+                    let value = *guard;
+                    coop::yield_now().await;
+                    *guard = value + 1;
+                });
+            }
+            let waiter = mutex.clone();
+            m.spawn_cancellable(async move {
+                let _guard = waiter.lock().await;
+                coop::yield_now().await;
+            });
+            move || assert_eq!(*mutex.try_lock().expect("lock leaked"), 2)
+        });
+    }
+
+    /// 多个 worker 上锁里跨过 `.await` 的读改写也不会丢更新
+    #[test]
+    fn read_modify_write() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let counter = Arc::new(Mutex::new(0));
+            let handles: Vec<_> = (0..100)
+                .map(|_| {
+                    let counter = counter.clone();
+                    spawn(async move {
+                        for _ in 0..10 {
+                            let mut guard = counter.lock().await;
+                            let value = *guard;
+                            coop::yield_now().await;
+                            *guard = value + 1;
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            let mut counter = Arc::try_unwrap(counter).ok().unwrap();
+            *counter.get_mut() += 1;
+            assert_eq!(counter.into_inner(), 1001);
+        });
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker, ready};
+
+use crate::coop;
+
+/// 通知一个或者所有等待的任务，没有数据，只有“发生了”
+///
+/// `notify_one` 时如果没人在等，会存下一个许可，下一次 `notified().await` 直接返回（最多存一个）；
+/// `notify_waiters` 只通知此刻已经在等的任务，不存许可。
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+struct State {
+    permit: bool,
+    waiters: VecDeque<Waiter>,
+    next_id: u64,
+    // 每次 `notify_waiters` 加一，`Notified` 创建时记下它，没来得及排队也不会错过
+    generation: u64,
+}
+
+struct Waiter {
+    id: u64,
+    waker: Waker,
+    notified: Option<Notification>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Notification {
+    One,
+    All,
+}
+
+/// `Notify::notified` 返回的 future
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    generation: u64,
+    // 排进等待队列后的编号
+    id: Option<u64>,
+    done: bool,
+}
+
+impl Notify {
+    pub fn new() -> Notify {
+        Notify {
+            state: Mutex::new(State {
+                permit: false,
+                waiters: VecDeque::new(),
+                next_id: 0,
+                generation: 0,
+            }),
+        }
+    }
+
+    /// 等待通知，第一次 poll 时才开始排队，但在这之前调用的 `notify_waiters` 也算数
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            generation: self.state.lock().unwrap().generation,
+            id: None,
+            done: false,
+        }
+    }
+
+    /// 唤醒最早开始等待的任务，没有人在等就存一个许可
+    pub fn notify_one(&self) {
+        let waker = self.state.lock().unwrap().notify_one();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// 唤醒所有正在等待的任务
+    pub fn notify_waiters(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        let wakers: Vec<_> = state
+            .waiters
+            .iter_mut()
+            .filter(|w| w.notified.is_none())
+            .map(|w| {
+                w.notified = Some(Notification::All);
+                w.waker.clone()
+            })
+            .collect();
+        drop(state);
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl State {
+    fn notify_one(&mut self) -> Option<Waker> {
+        match self.waiters.iter_mut().find(|w| w.notified.is_none()) {
+            Some(waiter) => {
+                waiter.notified = Some(Notification::One);
+                Some(waiter.waker.clone())
+            }
+            None => {
+                self.permit = true;
+                None
+            }
+        }
+    }
+
+    fn position(&self, id: u64) -> usize {
+        self.waiters.iter().position(|w| w.id == id).unwrap()
+    }
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        assert!(!self.done, "`Notified` polled after completion");
+        let mut coop = ready!(coop::poll_proceed(cx));
+        let mut state = self.notify.state.lock().unwrap();
+
+        let ready = match self.id {
+            None if state.permit => {
+                state.permit = false;
+                true
+            }
+            None if state.generation != self.generation => true,
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters.push_back(Waiter {
+                    id,
+                    waker: cx.waker().clone(),
+                    notified: None,
+                });
+                self.id = Some(id);
+                false
+            }
+            Some(id) => {
+                let i = state.position(id);
+                let waiter = &mut state.waiters[i];
+                if waiter.notified.is_some() {
+                    state.waiters.remove(i);
+                    self.id = None;
+                    true
+                } else {
+                    if !waiter.waker.will_wake(cx.waker()) {
+                        waiter.waker = cx.waker().clone();
+                    }
+                    false
+                }
+            }
+        };
+
+        if ready {
+            self.done = true;
+            coop.made_progress();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+
+        let mut state = self.notify.state.lock().unwrap();
+        let i = state.position(id);
+        let waiter = state.waiters.remove(i).unwrap();
+        // `notify_one` 选中了我们，但我们不等了，把通知转给下一个人，免得它就这么丢了
+        let waker = if waiter.notified == Some(Notification::One) {
+            state.notify_one()
+        } else {
+            None
+        };
+        drop(state);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::model;
+    use crate::runtime::MiniTokio;
+    use crate::runtime::tests::serial;
+    use crate::sync::Notify;
+
+    /// 被 `notify_one` 选中的任务如果被取消，通知要转给下一个等待的任务
+    #[test]
+    fn interleavings() {
+        model::check(|m| {
+            let notify = Arc::new(Notify::new());
+            let first = notify.clone();
+            m.spawn_cancellable(async move {
+                first.notified().await;
+                first.notify_one();
+            });
+            let second = notify.clone();
+            m.spawn(async move { second.notified().await });
+            m.spawn(async move { notify.notify_one() });
+            || {}
+        });
+    }
+
+    /// 在排队之前调用的 `notify_waiters` 也算数，`notify_one` 没人等时存一个许可
+    #[test]
+    fn notify_before_wait() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let notify = Notify::new();
+            let notified = notify.notified();
+            notify.notify_waiters();
+            notified.await;
+            notify.notify_one();
+            notify.notified().await;
+        });
+    }
+}
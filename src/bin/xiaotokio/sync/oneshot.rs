@@ -0,0 +1,223 @@
+//! 只能发送一个值的通道
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker, ready};
+
+use crate::coop;
+
+pub struct Sender<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+}
+
+pub struct Receiver<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+}
+
+struct Inner<T> {
+    value: Option<T>,
+    // `Sender` 已经发送或者被丢弃了
+    complete: bool,
+    // `Receiver` 被关闭或者被丢弃了
+    closed: bool,
+    rx_waker: Option<Waker>,
+    // `Sender::closed` 的等待者
+    tx_waker: Option<Waker>,
+}
+
+/// `Sender` 没发送就被丢弃了
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// 值还没发过来
+    Empty,
+    /// `Sender` 没发送就被丢弃了
+    Closed,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Mutex::new(Inner {
+        value: None,
+        complete: false,
+        closed: false,
+        rx_waker: None,
+        tx_waker: None,
+    }));
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+impl<T> Sender<T> {
+    /// 发送 `value`，接收端已经关闭时原样还回来
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return Err(value);
+        }
+        inner.value = Some(value);
+        inner.complete = true;
+        let waker = inner.rx_waker.take();
+        drop(inner);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock().unwrap().closed
+    }
+
+    /// 等到接收端关闭，发送方可以借此放弃还没算完的结果
+    pub async fn closed(&mut self) {
+        std::future::poll_fn(|cx| {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.closed {
+                return Poll::Ready(());
+            }
+            inner.tx_waker = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.complete {
+            return;
+        }
+        inner.complete = true;
+        let waker = inner.rx_waker.take();
+        drop(inner);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// 关闭通道，之后 `send` 会失败；已经发过来的值还能收到
+    pub fn close(&mut self) {
+        let waker = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.closed = true;
+            inner.tx_waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.value.take() {
+            Some(value) => Ok(value),
+            None if inner.complete => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut coop = ready!(coop::poll_proceed(cx));
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(value) = inner.value.take() {
+            coop.made_progress();
+            return Poll::Ready(Ok(value));
+        }
+        if inner.complete {
+            return Poll::Ready(Err(RecvError));
+        }
+        inner.rx_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "channel closed".fmt(f)
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => "channel empty".fmt(f),
+            TryRecvError::Closed => "channel closed".fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecvError, TryRecvError, channel};
+    use crate::coop;
+    use crate::model;
+    use crate::runtime::tests::serial;
+    use crate::runtime::{MiniTokio, spawn};
+
+    /// 发送方可能在发送前被取消，接收方总能等到结果
+    #[test]
+    fn interleavings() {
+        model::check(|m| {
+            let (tx, rx) = channel();
+            m.spawn_cancellable(async move {
+                coop::yield_now().await;
+                let _ = tx.send(1);
+            });
+            m.spawn(async move {
+                let result = rx.await;
+                assert!(result == Ok(1) || result == Err(RecvError));
+            });
+            || {}
+        });
+    }
+
+    /// 跨线程发送，接收方关闭时发送方能察觉
+    #[test]
+    fn send_and_close() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let (tx, rx) = channel();
+            spawn(async move { tx.send("hello").unwrap() });
+            assert_eq!(rx.await, Ok("hello"));
+
+            let (mut tx, mut rx) = channel::<()>();
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+            let closed = spawn(async move {
+                tx.closed().await;
+                assert!(tx.is_closed());
+                tx.send(()).is_err()
+            });
+            rx.close();
+            assert!(closed.await.unwrap());
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Closed));
+        });
+    }
+}
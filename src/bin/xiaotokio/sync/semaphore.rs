@@ -0,0 +1,385 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker, ready};
+
+use crate::coop;
+
+/// 公平的计数信号量：等待者按先来后到拿到许可，前面的人要的许可不够时后面的人也得等
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    closed: bool,
+    waiters: VecDeque<Waiter>,
+    next_id: u64,
+}
+
+struct Waiter {
+    id: u64,
+    needed: usize,
+    waker: Waker,
+}
+
+/// 拿到的许可，被丢弃时还给信号量
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+/// 不借用信号量的许可
+pub struct OwnedSemaphorePermit {
+    semaphore: Arc<Semaphore>,
+    permits: usize,
+}
+
+/// 信号量被关闭了
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcquireError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryAcquireError {
+    Closed,
+    NoPermits,
+}
+
+/// `acquire` 返回的 future
+///
+/// 排进等待队列之后被丢弃时会把自己从队列里摘掉；如果已经分到了许可还没来得及拿走，就把许可还回去。
+pub(crate) struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    needed: usize,
+    // 排进等待队列后的编号
+    id: Option<u64>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: Mutex::new(State {
+                permits,
+                closed: false,
+                waiters: VecDeque::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    /// 增加 `n` 个许可，够的话分给排在前面的等待者
+    pub fn add_permits(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.permits += n;
+        let wakers = state.assign();
+        drop(state);
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, AcquireError> {
+        self.acquire_many(1).await
+    }
+
+    pub async fn acquire_many(&self, n: usize) -> Result<SemaphorePermit<'_>, AcquireError> {
+        self.acquire_permits(n).await?;
+        Ok(SemaphorePermit {
+            semaphore: self,
+            permits: n,
+        })
+    }
+
+    pub async fn acquire_owned(self: Arc<Self>) -> Result<OwnedSemaphorePermit, AcquireError> {
+        self.acquire_permits(1).await?;
+        Ok(OwnedSemaphorePermit {
+            semaphore: self,
+            permits: 1,
+        })
+    }
+
+    /// 不等待，马上能拿到才拿。有人在排队时也拿不到，不能插队
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(TryAcquireError::Closed);
+        }
+        if !state.waiters.is_empty() || state.permits == 0 {
+            return Err(TryAcquireError::NoPermits);
+        }
+        state.permits -= 1;
+        Ok(SemaphorePermit {
+            semaphore: self,
+            permits: 1,
+        })
+    }
+
+    /// 关闭信号量：正在等和之后再来拿许可的都得到 `AcquireError`，已经拿到的许可不受影响
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let wakers: Vec<_> = state.waiters.iter().map(|w| w.waker.clone()).collect();
+        drop(state);
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// 拿 `needed` 个许可但不生成 `SemaphorePermit`，拿到的许可由调用者自己负责还
+    pub(crate) fn acquire_permits(&self, needed: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            needed,
+            id: None,
+        }
+    }
+}
+
+impl State {
+    /// 按顺序把许可分给等待者，返回要唤醒的 waker
+    fn assign(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while let Some(waiter) = self.waiters.front() {
+            if waiter.needed > self.permits {
+                break;
+            }
+            self.permits -= waiter.needed;
+            wakers.push(self.waiters.pop_front().unwrap().waker);
+        }
+        wakers
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        self.waiters.iter().position(|w| w.id == id)
+    }
+}
+
+impl Future for Acquire<'_> {
+    type Output = Result<(), AcquireError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut coop = ready!(coop::poll_proceed(cx));
+        let mut state = self.semaphore.state.lock().unwrap();
+
+        let Some(id) = self.id else {
+            if state.closed {
+                return Poll::Ready(Err(AcquireError));
+            }
+            if state.waiters.is_empty() && state.permits >= self.needed {
+                state.permits -= self.needed;
+                coop.made_progress();
+                return Poll::Ready(Ok(()));
+            }
+
+            let id = state.next_id;
+            state.next_id += 1;
+            state.waiters.push_back(Waiter {
+                id,
+                needed: self.needed,
+                waker: cx.waker().clone(),
+            });
+            self.id = Some(id);
+            return Poll::Pending;
+        };
+
+        match state.position(id) {
+            // 不在队列里了，说明 `assign` 已经把许可分给了我们
+            None => {
+                self.id = None;
+                coop.made_progress();
+                Poll::Ready(Ok(()))
+            }
+            Some(i) if state.closed => {
+                state.waiters.remove(i);
+                self.id = None;
+                Poll::Ready(Err(AcquireError))
+            }
+            Some(i) => {
+                let waiter = &mut state.waiters[i];
+                if !waiter.waker.will_wake(cx.waker()) {
+                    waiter.waker = cx.waker().clone();
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+
+        let mut state = self.semaphore.state.lock().unwrap();
+        match state.position(id) {
+            // 还在排队，摘掉之后后面的人可能就够了
+            Some(i) => {
+                state.waiters.remove(i);
+            }
+            // 已经分到了许可却不要了，还回去
+            None => state.permits += self.needed,
+        }
+        let wakers = state.assign();
+        drop(state);
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl SemaphorePermit<'_> {
+    /// 不归还许可，信号量里的许可就少了这么多
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+impl Drop for OwnedSemaphorePermit {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
+
+impl fmt::Display for AcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "semaphore closed".fmt(f)
+    }
+}
+
+impl std::error::Error for AcquireError {}
+
+impl fmt::Display for TryAcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryAcquireError::Closed => "semaphore closed".fmt(f),
+            TryAcquireError::NoPermits => "no permits available".fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TryAcquireError {}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use crate::coop;
+    use crate::model;
+    use crate::runtime::tests::serial;
+    use crate::runtime::{MiniTokio, spawn};
+    use crate::sync::{Semaphore, TryAcquireError};
+    use crate::time;
+
+    /// 同时拿着的许可不能超过总数，被取消的任务不能带走许可
+    #[test]
+    fn interleavings() {
+        struct Held(Rc<Cell<usize>>, usize);
+
+        // 任务被取消时也要减掉它拿着的数，在还许可之前被丢弃
+        impl Drop for Held {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() - self.1);
+            }
+        }
+
+        model::check(|m| {
+            let semaphore = Arc::new(Semaphore::new(2));
+            let in_use = Rc::new(Cell::new(0));
+            for (n, cancellable) in [(2, false), (1, false), (1, true)] {
+                let (semaphore, in_use) = (semaphore.clone(), in_use.clone());
+                let task = async move {
+                    let _permit = semaphore.acquire_many(n).await.unwrap();
+                    in_use.set(in_use.get() + n);
+                    let _held = Held(in_use.clone(), n);
+                    assert!(in_use.get() <= 2, "too many permits handed out");
+                    coop::yield_now().await;
+                };
+                if cancellable {
+                    m.spawn_cancellable(task);
+                } else {
+                    m.spawn(task);
+                }
+            }
+            move || assert_eq!(semaphore.available_permits(), 2)
+        });
+    }
+
+    /// 信号量限制并发数
+    #[test]
+    fn limits_concurrency() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let semaphore = Arc::new(Semaphore::new(5));
+            let (running, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+            let handles: Vec<_> = (0..100)
+                .map(|_| {
+                    let (semaphore, running, peak) =
+                        (semaphore.clone(), running.clone(), peak.clone());
+                    spawn(async move {
+                        let _permit = semaphore.acquire_owned().await.unwrap();
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        time::sleep(Duration::from_millis(1)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            assert!(peak.load(Ordering::SeqCst) <= 5);
+            assert_eq!(semaphore.available_permits(), 5);
+        });
+    }
+
+    #[test]
+    fn forget_and_close() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let semaphore = Arc::new(Semaphore::new(5));
+            let permit = semaphore.acquire().await.unwrap();
+            permit.forget();
+            assert_eq!(semaphore.available_permits(), 4);
+            semaphore.add_permits(1);
+            let waiting = {
+                let semaphore = semaphore.clone();
+                spawn(async move { semaphore.acquire_many(6).await.is_err() })
+            };
+            time::sleep(Duration::from_millis(5)).await;
+            // 有人在排队，`try_acquire` 不能插队
+            assert_eq!(
+                semaphore.try_acquire().err(),
+                Some(TryAcquireError::NoPermits)
+            );
+            semaphore.close();
+            assert!(semaphore.is_closed() && waiting.await.unwrap());
+            assert_eq!(semaphore.try_acquire().err(), Some(TryAcquireError::Closed));
+        });
+    }
+}
@@ -0,0 +1,165 @@
+//! 调度器里的任务
+//!
+//! 任务的 future 不再放在 `Mutex` 里，而是由一个原子的状态机保证同一时间只有一个线程 poll 它：
+//!
+//! - `IDLE`：没在队列里，也没人在 poll，等着被唤醒
+//! - `SCHEDULED`：在某个队列里等着被 poll
+//! - `RUNNING`：某个 worker 正在 poll
+//! - `NOTIFIED`：poll 的过程中被唤醒了，poll 结束后要重新入队
+//! - `COMPLETE`：future 已经结束（或者被取消）并被丢弃，之后的唤醒都被忽略
+//!
+//! 只有把状态从 `SCHEDULED` 改成 `RUNNING` 的那个 worker 能碰 future，
+//! 而任务只有在从 `IDLE` 变成 `SCHEDULED` 时才会入队，所以队列里同一个任务最多只有一份。
+
+use std::cell::UnsafeCell;
+use std::future::Future;
+use std::panic::Location;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::task::Context;
+use std::time::Instant;
+
+use futures::task::{self, ArcWake};
+
+use crate::coop;
+use crate::join::{Abort, Harness, JoinHandle, JoinState};
+use crate::metrics::TaskStats;
+use crate::runtime::Shared;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+const IDLE: u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+const NOTIFIED: u8 = 3;
+const COMPLETE: u8 = 4;
+
+pub struct Task {
+    // 由 `state` 保护：只有处于 `RUNNING` 状态的 worker 会访问它。
+    // 执行完的 future 会被换成 `None`，之后再被唤醒也不会重复 poll 一个已经结束的 future
+    future: UnsafeCell<Option<BoxFuture>>,
+    state: AtomicU8,
+    // 任务被唤醒时要放回哪个调度器
+    scheduler: Arc<Shared>,
+    // `JoinHandle::abort` 设置，下次调度时丢弃 future
+    aborted: AtomicBool,
+    stats: Arc<TaskStats>,
+}
+
+// `future` 只会被持有 `RUNNING` 状态的那一个线程访问，见上面的状态机
+unsafe impl Sync for Task {}
+
+impl Task {
+    // 使用给定的 future 来生成新的任务，并放进调度器的队列中等待执行
+    #[track_caller]
+    pub fn spawn<F>(future: F, scheduler: &Arc<Shared>) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let stats = scheduler.task_spawned(Location::caller());
+
+        let state = Arc::new(JoinState::new());
+        let task = Arc::new(Task {
+            future: UnsafeCell::new(Some(Box::pin(Harness::new(future, state.clone())))),
+            state: AtomicU8::new(SCHEDULED),
+            scheduler: scheduler.clone(),
+            aborted: AtomicBool::new(false),
+            stats,
+        });
+
+        scheduler.schedule(task.clone());
+        JoinHandle::new(state, task)
+    }
+
+    /// 唤醒任务：空闲的任务入队，正在被 poll 的任务记下这次唤醒，其他状态什么都不用做
+    fn wake(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self
+                .state
+                .compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+
+        if state == IDLE {
+            self.scheduler.schedule(self.clone());
+        }
+    }
+
+    pub fn poll(self: Arc<Self>) {
+        // 队列里的任务一定是 `SCHEDULED`，由我们把它改成 `RUNNING`
+        let prev = self.state.swap(RUNNING, Ordering::AcqRel);
+        debug_assert_eq!(prev, SCHEDULED, "polled a task that was not scheduled");
+
+        // 基于 Task 实例创建一个 waker, 它使用了之前的 `ArcWake`
+        let waker = task::waker(self.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        // SAFETY: 状态是 `RUNNING`，其他线程不会访问 `future`
+        let future = unsafe { &mut *self.future.get() };
+        let fut = future.as_mut().expect("scheduled task has no future");
+
+        // 被取消的任务直接丢弃 future，`Harness` 会把结果记成已取消。
+        // `Harness` 已经接住了用户 future 的 panic，这里的 poll 不会 unwind
+        let ready = self.aborted.load(Ordering::SeqCst) || {
+            let start = Instant::now();
+            let (poll, progressed) = coop::budget(|| fut.as_mut().poll(&mut cx));
+            let ready = poll.is_ready();
+            self.scheduler
+                .record_poll(&self.stats, start.elapsed(), ready, progressed);
+            ready
+        };
+        if ready {
+            *future = None;
+            self.state.store(COMPLETE, Ordering::Release);
+            self.scheduler.task_finished(&self.stats);
+            return;
+        }
+
+        // poll 期间没人唤醒就回到 `IDLE`；被唤醒过（`NOTIFIED`）就重新入队，
+        // 而不是在这里接着 poll，免得一个不停唤醒自己的任务霸占 worker
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            self.state.store(SCHEDULED, Ordering::Release);
+            self.scheduler.schedule(self.clone());
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        // 任务还没结束就没人持有了：既不在队列里，也没有 waker 能再唤醒它，
+        // 它永远不会结束，算作结束，免得 `run` 一直等下去
+        if self.future.get_mut().take().is_some() {
+            self.scheduler.task_finished(&self.stats);
+        }
+    }
+}
+
+impl Abort for Task {
+    /// 标记任务被取消，再唤醒一次让 worker 去丢弃它的 future
+    fn abort(self: Arc<Self>) {
+        if !self.aborted.swap(true, Ordering::SeqCst) {
+            self.wake();
+        }
+    }
+}
+
+impl ArcWake for Task {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.wake();
+    }
+}
@@ -0,0 +1,226 @@
+//! 任务本地变量
+//!
+//! 任务会在不同的 worker 线程上被 poll，`thread_local!` 的值跨过 `.await` 就可能换了一个线程，
+//! 不能用来保存“这个任务的”数据。`task_local!` 声明的变量把值存在 `LocalKey::scope` 返回的
+//! future 里，每次 poll 时临时放进线程本地变量，poll 完再拿回来，所以只有在这个 future
+//! 里面（包括它 `.await` 的子 future）才能访问到，其他任务看不到。
+//!
+//! ```ignore
+//! task_local! {
+//!     static REQUEST_ID: u64;
+//! }
+//!
+//! REQUEST_ID.scope(42, async {
+//!     time::sleep(Duration::from_millis(10)).await;
+//!     assert_eq!(REQUEST_ID.get(), 42);
+//! }).await;
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+
+/// 声明任务本地变量，类型是 `LocalKey<T>`
+macro_rules! task_local {
+    () => {};
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task_local::LocalKey<$t> = {
+            std::thread_local! {
+                static __KEY: std::cell::RefCell<Option<$t>> = const { std::cell::RefCell::new(None) };
+            }
+            $crate::task_local::LocalKey { inner: __KEY }
+        };
+
+        $crate::task_local::task_local!($($rest)*);
+    };
+}
+
+pub(crate) use task_local;
+
+/// `task_local!` 声明的变量
+pub struct LocalKey<T: 'static> {
+    // 只给 `task_local!` 用
+    #[doc(hidden)]
+    pub inner: thread::LocalKey<RefCell<Option<T>>>,
+}
+
+/// 在 `scope` 外面访问任务本地变量时返回的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError;
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "task-local value not set".fmt(f)
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+impl<T: 'static> LocalKey<T> {
+    /// 在 `future` 里把变量设为 `value`
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F> {
+        TaskLocalFuture {
+            key: self,
+            slot: Some(value),
+            future: Some(Box::pin(future)),
+        }
+    }
+
+    /// 同步版本的 `scope`，在 `f` 里把变量设为 `value`
+    pub fn sync_scope<R>(&'static self, value: T, f: impl FnOnce() -> R) -> R {
+        let mut slot = Some(value);
+        self.enter(&mut slot, f)
+    }
+
+    /// 把 `slot` 里的值换进线程本地变量，执行完 `f`（包括 panic）后再换回来
+    fn enter<R>(&'static self, slot: &mut Option<T>, f: impl FnOnce() -> R) -> R {
+        struct Guard<'a, T: 'static> {
+            key: &'static LocalKey<T>,
+            slot: &'a mut Option<T>,
+        }
+
+        impl<T> Drop for Guard<'_, T> {
+            fn drop(&mut self) {
+                self.key
+                    .inner
+                    .with(|cell| mem::swap(self.slot, &mut *cell.borrow_mut()));
+            }
+        }
+
+        self.inner
+            .with(|cell| mem::swap(slot, &mut *cell.borrow_mut()));
+        let _guard = Guard { key: self, slot };
+        f()
+    }
+
+    /// 访问变量的值，不在 `scope` 里时 panic
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        self.try_with(f)
+            .expect("cannot access a task-local value outside of its `scope`")
+    }
+
+    /// 访问变量的值，不在 `scope` 里时返回 `AccessError`
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        self.inner.with(|cell| match cell.borrow().as_ref() {
+            Some(value) => Ok(f(value)),
+            None => Err(AccessError),
+        })
+    }
+}
+
+impl<T: Clone + 'static> LocalKey<T> {
+    /// 返回变量的值的拷贝，不在 `scope` 里时 panic
+    pub fn get(&'static self) -> T {
+        self.with(T::clone)
+    }
+}
+
+/// `LocalKey::scope` 返回的 future
+pub struct TaskLocalFuture<T: 'static, F> {
+    key: &'static LocalKey<T>,
+    // 不在 poll 时值放在这里，poll 时被换进线程本地变量
+    slot: Option<T>,
+    // 被丢弃时也要在 scope 里丢弃 future，它的析构函数可能用到这个变量
+    future: Option<Pin<Box<F>>>,
+}
+
+// `slot` 和 `future` 都不会被固定在原地
+impl<T, F> Unpin for TaskLocalFuture<T, F> {}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = &mut *self;
+        let future = this
+            .future
+            .as_mut()
+            .expect("`TaskLocalFuture` polled after completion");
+
+        let poll = this.key.enter(&mut this.slot, || future.as_mut().poll(cx));
+        if poll.is_ready() {
+            this.future = None;
+        }
+        poll
+    }
+}
+
+impl<T: 'static, F> Drop for TaskLocalFuture<T, F> {
+    fn drop(&mut self) {
+        if let Some(future) = self.future.take() {
+            self.key.enter(&mut self.slot, || drop(future));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::AccessError;
+    use crate::runtime::tests::serial;
+    use crate::runtime::{MiniTokio, spawn};
+    use crate::time;
+
+    task_local! {
+        static TASK_ID: usize;
+    }
+
+    /// 任务本地变量跨 `.await`、跨 worker 线程都保持不变，也不会漏到其他任务里
+    #[test]
+    fn scoped_to_task() {
+        const TASKS: usize = 100;
+
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let scoped: Vec<_> = (0..TASKS)
+                .map(|i| {
+                    spawn(TASK_ID.scope(i, async move {
+                        for _ in 0..10 {
+                            time::sleep(Duration::from_millis(1)).await;
+                            assert_eq!(TASK_ID.get(), i);
+                        }
+
+                        // 嵌套的 scope 只在里面生效
+                        TASK_ID
+                            .scope(
+                                i + TASKS,
+                                async move { assert_eq!(TASK_ID.get(), i + TASKS) },
+                            )
+                            .await;
+                        assert_eq!(TASK_ID.get(), i);
+                    }))
+                })
+                .collect();
+
+            // 没有 scope 的任务和上面的任务混在同样的 worker 上，它们什么都看不到
+            let unscoped: Vec<_> = (0..TASKS)
+                .map(|_| {
+                    spawn(async {
+                        for _ in 0..10 {
+                            time::sleep(Duration::from_millis(1)).await;
+                            assert_eq!(TASK_ID.try_with(|_| ()), Err(AccessError));
+                        }
+                    })
+                })
+                .collect();
+
+            for handle in scoped.into_iter().chain(unscoped) {
+                handle.await.unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn sync_scope() {
+        assert_eq!(TASK_ID.try_with(|_| ()), Err(AccessError));
+        assert_eq!(TASK_ID.sync_scope(7, || TASK_ID.get()), 7);
+        assert_eq!(TASK_ID.try_with(|_| ()), Err(AccessError));
+    }
+}
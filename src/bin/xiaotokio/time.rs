@@ -0,0 +1,471 @@
+//! 分层时间轮
+//!
+//! 之前的 `Delay` 每个计时器都要开一个线程睡眠，计时器一多线程就爆了。这里所有计时器都放进
+//! runtime 里的一个时间轮：精度 1ms，共 6 层，每层 64 个槽，第 n 层一个槽覆盖 64^n 毫秒。
+//! 计时器按离到期还有多远放进对应的层，时间推进到某个高层槽时，把里面的计时器重新放进更低的层，
+//! 直到在第 0 层到期。
+//!
+//! 时间轮由 worker 自己推进：没有任务可做时先检查有没有到期的计时器，再睡到下一个到期时间。
+
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker, ready};
+use std::time::{Duration, Instant};
+
+use crate::{coop, runtime};
+
+const LEVELS: usize = 6;
+const SLOTS: usize = 64;
+// 每层用 6 个比特表示槽号
+const SLOT_BITS: usize = 6;
+// 时间轮能表示的最远距离，更远的计时器放在最高层，转一圈后再看
+const MAX_DURATION: u64 = 1 << (SLOT_BITS * LEVELS);
+
+/// runtime 中的计时器驱动
+pub struct Driver {
+    start: Instant,
+    wheel: Mutex<Wheel>,
+}
+
+struct Wheel {
+    // 已经处理到的时刻，单位是从 `start` 开始的毫秒数
+    elapsed: u64,
+    levels: [Level; LEVELS],
+}
+
+struct Level {
+    level: usize,
+    // 第 i 位为 1 表示第 i 个槽里有计时器
+    occupied: u64,
+    slots: [Vec<Arc<Entry>>; SLOTS],
+}
+
+/// 时间轮中的一个计时器
+struct Entry {
+    when: u64,
+    state: Mutex<EntryState>,
+}
+
+struct EntryState {
+    fired: bool,
+    // `Sleep` 被丢弃时清空，到期后就不会再唤醒任何任务
+    waker: Option<Waker>,
+}
+
+/// 下一个需要处理的槽
+struct Expiration {
+    level: usize,
+    slot: usize,
+    deadline: u64,
+}
+
+impl Driver {
+    pub fn new() -> Driver {
+        Driver {
+            start: Instant::now(),
+            wheel: Mutex::new(Wheel {
+                elapsed: 0,
+                levels: std::array::from_fn(|level| Level {
+                    level,
+                    occupied: 0,
+                    slots: std::array::from_fn(|_| Vec::new()),
+                }),
+            }),
+        }
+    }
+
+    /// `instant` 对应的时刻，向上取整，保证计时器不会提前到期
+    fn tick_ceil(&self, instant: Instant) -> u64 {
+        let nanos = instant.saturating_duration_since(self.start).as_nanos();
+        nanos.div_ceil(1_000_000) as u64
+    }
+
+    fn now(&self) -> u64 {
+        Instant::now()
+            .saturating_duration_since(self.start)
+            .as_millis() as u64
+    }
+
+    /// 把计时器放进时间轮。已经到期时返回 `Err`；成功时返回它是不是比原来所有计时器都早，
+    /// 是的话睡眠中的 worker 需要被叫醒，重新计算睡多久
+    fn register(&self, entry: Arc<Entry>) -> Result<bool, ()> {
+        let mut wheel = self.wheel.lock().unwrap();
+        if entry.when <= wheel.elapsed {
+            return Err(());
+        }
+
+        let earliest = wheel
+            .next_expiration()
+            .is_none_or(|next| entry.when < next.deadline);
+        wheel.insert(entry);
+        Ok(earliest)
+    }
+
+    /// 推进时间轮，唤醒所有到期的计时器，返回是否唤醒了任务
+    ///
+    /// 其他 worker 正在推进时直接返回，不用排队等它。
+    pub fn process(&self) -> bool {
+        let Ok(mut wheel) = self.wheel.try_lock() else {
+            return false;
+        };
+        let fired = wheel.advance(self.now());
+        // 唤醒任务会去碰调度器的锁，先放开时间轮
+        drop(wheel);
+
+        let mut woken = false;
+        for entry in fired {
+            let mut state = entry.state.lock().unwrap();
+            state.fired = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+                woken = true;
+            }
+        }
+        woken
+    }
+
+    /// 下一次需要推进时间轮的时间，worker 最多睡到这时
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let wheel = self.wheel.lock().unwrap();
+        let next = wheel.next_expiration()?;
+        Some(self.start + Duration::from_millis(next.deadline))
+    }
+}
+
+impl Wheel {
+    fn insert(&mut self, entry: Arc<Entry>) {
+        let level = level_for(self.elapsed, entry.when);
+        self.levels[level].insert(entry);
+    }
+
+    /// 找到最早有计时器的槽。低层的槽总是比高层的早，所以从低往高找
+    fn next_expiration(&self) -> Option<Expiration> {
+        self.levels
+            .iter()
+            .find_map(|level| level.next_expiration(self.elapsed))
+    }
+
+    /// 推进到 `now`，返回到期的计时器
+    fn advance(&mut self, now: u64) -> Vec<Arc<Entry>> {
+        let mut fired = Vec::new();
+
+        while let Some(next) = self.next_expiration() {
+            if next.deadline > now {
+                break;
+            }
+
+            self.elapsed = self.elapsed.max(next.deadline);
+            let level = &mut self.levels[next.level];
+            level.occupied &= !(1 << next.slot);
+            let entries = mem::take(&mut level.slots[next.slot]);
+
+            for entry in entries {
+                if entry.when <= self.elapsed {
+                    fired.push(entry);
+                } else {
+                    // 高层槽里的计时器还没到期，放进更低的层
+                    self.insert(entry);
+                }
+            }
+        }
+
+        self.elapsed = self.elapsed.max(now);
+        fired
+    }
+}
+
+impl Level {
+    fn insert(&mut self, entry: Arc<Entry>) {
+        let slot = slot_for(entry.when, self.level);
+        self.slots[slot].push(entry);
+        self.occupied |= 1 << slot;
+    }
+
+    fn next_expiration(&self, now: u64) -> Option<Expiration> {
+        if self.occupied == 0 {
+            return None;
+        }
+
+        let slot_range = 1u64 << (SLOT_BITS * self.level);
+        let level_range = slot_range << SLOT_BITS;
+        let now_slot = slot_for(now, self.level) as u32;
+
+        // 从当前槽开始往后找第一个有计时器的槽
+        let slot =
+            (self.occupied.rotate_right(now_slot).trailing_zeros() + now_slot) as usize % SLOTS;
+        let level_start = now & !(level_range - 1);
+        let mut deadline = level_start + slot as u64 * slot_range;
+        if deadline <= now {
+            // 只有最高层会出现：超出范围的计时器在最高层绕了一圈，实际是下一轮的槽
+            deadline += level_range;
+        }
+
+        Some(Expiration {
+            level: self.level,
+            slot,
+            deadline,
+        })
+    }
+}
+
+/// `when` 和 `elapsed` 最高的不同比特决定放在哪一层
+fn level_for(elapsed: u64, when: u64) -> usize {
+    let mut masked = (elapsed ^ when) | (SLOTS as u64 - 1);
+    if masked >= MAX_DURATION {
+        masked = MAX_DURATION - 1;
+    }
+    let significant = 63 - masked.leading_zeros() as usize;
+    significant / SLOT_BITS
+}
+
+fn slot_for(when: u64, level: usize) -> usize {
+    ((when >> (level * SLOT_BITS)) as usize) & (SLOTS - 1)
+}
+
+/// 等到 `deadline` 的 future
+pub struct Sleep {
+    deadline: Instant,
+    entry: Option<Arc<Entry>>,
+}
+
+/// 睡眠 `duration`
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// 睡到 `deadline`
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        entry: None,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // 已经过了的 `sleep` 总是立即就绪，在循环里反复等它也要扣预算
+        let mut coop = ready!(coop::poll_proceed(cx));
+
+        if let Some(entry) = &self.entry {
+            let mut state = entry.state.lock().unwrap();
+            if state.fired {
+                coop.made_progress();
+                return Poll::Ready(());
+            }
+            if !state
+                .waker
+                .as_ref()
+                .is_some_and(|w| w.will_wake(cx.waker()))
+            {
+                state.waker = Some(cx.waker().clone());
+            }
+            return Poll::Pending;
+        }
+
+        // 第一次 poll 时才放进时间轮，waker 先放好，放进去之后随时可能到期
+        let shared = runtime::current();
+        let driver = shared.timer();
+        let entry = Arc::new(Entry {
+            when: driver
+                .tick_ceil(self.deadline)
+                .min(driver.now() + MAX_DURATION - 1),
+            state: Mutex::new(EntryState {
+                fired: false,
+                waker: Some(cx.waker().clone()),
+            }),
+        });
+
+        match driver.register(entry.clone()) {
+            Err(()) => {
+                coop.made_progress();
+                Poll::Ready(())
+            }
+            Ok(earliest) => {
+                self.entry = Some(entry);
+                if earliest {
+                    shared.wake_sleeper();
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // 计时器留在时间轮里，到期时没有 waker 可唤醒，就这样被丢掉；
+        // 这里清掉 waker，免得时间轮一直拿着任务不放
+        if let Some(entry) = &self.entry {
+            entry.state.lock().unwrap().waker = None;
+        }
+    }
+}
+
+/// 每隔 `period` 触发一次，第一次 `tick` 立即返回
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "`period` must be non-zero");
+    Interval {
+        next: Instant::now(),
+        period,
+    }
+}
+
+pub struct Interval {
+    next: Instant,
+    period: Duration,
+}
+
+impl Interval {
+    /// 等到下一次触发，返回这一次预定的触发时间
+    ///
+    /// 错过的触发不会被跳过，会尽快连续补上。
+    pub async fn tick(&mut self) -> Instant {
+        let when = self.next;
+        sleep_until(when).await;
+        self.next = when + self.period;
+        when
+    }
+}
+
+/// 给 `future` 加上超时，`duration` 内没完成就返回 `Err(Elapsed)` 并丢弃它
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        sleep: sleep(duration),
+    }
+}
+
+pub struct Timeout<F> {
+    future: Pin<Box<F>>,
+    sleep: Sleep,
+}
+
+/// `timeout` 到期时返回的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "deadline has elapsed".fmt(f)
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // 先 poll 内部的 future，它和计时器同时就绪时算作完成
+        if let Poll::Ready(output) = self.future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut self.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+    use std::time::{Duration, Instant};
+
+    use super::{Elapsed, interval, sleep, sleep_until, timeout};
+    use crate::runtime::tests::serial;
+    use crate::runtime::{MiniTokio, spawn};
+
+    /// 大量计时器同时等待也不会多开线程
+    #[test]
+    fn many_timers() {
+        const TIMERS: u64 = 10_000;
+
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let threads = thread_count();
+            let start = Instant::now();
+            let handles: Vec<_> = (0..TIMERS)
+                .map(|i| {
+                    // 分散到 0~300ms，覆盖时间轮的前两层
+                    let deadline = start + Duration::from_millis(i * 7919 % 300);
+                    spawn(async move {
+                        sleep_until(deadline).await;
+                        let now = Instant::now();
+                        assert!(now >= deadline, "timer fired early");
+                        (deadline, now - deadline)
+                    })
+                })
+                .collect();
+
+            let mut latest = Duration::ZERO;
+            for handle in handles {
+                // 前 100ms 的计时器主要在等 10000 个任务第一次被 poll，不算在内
+                let (deadline, late) = handle.await.unwrap();
+                if deadline >= start + Duration::from_millis(100) {
+                    latest = latest.max(late);
+                }
+            }
+            assert_eq!(thread_count(), threads);
+            assert!(
+                latest < Duration::from_millis(100),
+                "latest timer {:?} late",
+                latest
+            );
+        });
+    }
+
+    /// 睡得比较久的计时器放在高层，要经过几次下放才到期
+    #[test]
+    fn long_sleep() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let start = Instant::now();
+            sleep(Duration::from_millis(1500)).await;
+            let elapsed = start.elapsed();
+            assert!(
+                elapsed >= Duration::from_millis(1500) && elapsed < Duration::from_millis(1600)
+            );
+        });
+    }
+
+    #[test]
+    fn interval_ticks() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let mut interval = interval(Duration::from_millis(20));
+            let first = interval.tick().await;
+            for i in 1..=5 {
+                let when = interval.tick().await;
+                assert_eq!(when, first + Duration::from_millis(20) * i);
+                assert!(Instant::now() >= when);
+            }
+        });
+    }
+
+    #[test]
+    fn timeout_elapses() {
+        let _serial = serial();
+        MiniTokio::with_workers(4).block_on(async {
+            let fast = timeout(Duration::from_millis(100), sleep(Duration::from_millis(10)));
+            assert_eq!(fast.await, Ok(()));
+            let slow = timeout(Duration::from_millis(10), future::pending::<()>());
+            assert_eq!(slow.await, Err(Elapsed));
+        });
+    }
+
+    /// 当前进程的线程数
+    fn thread_count() -> usize {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("Threads:"))
+            .and_then(|n| n.trim().parse().ok())
+            .unwrap()
+    }
+}
@@ -0,0 +1,917 @@
+//! RESP 编解码器，实现了 `tokio_util::codec` 的 `Decoder` / `Encoder`
+//!
+//! 配合 `Framed` 使用即可在任意 `AsyncRead + AsyncWrite` 上收发 `Frame`。解码分两步：
+//! 1. 只扫描、不分配，确认缓冲区里已经有完整的一帧，顺便检查长度和嵌套深度的限制；
+//! 2. 把这一帧的字节 `split_to` 出来冻结成 `Bytes`，bulk string 直接是它的切片，不再拷贝。
+//!
+//! 数据不够一帧时会记下“至少还需要多少字节”，下次数据没到这个长度就不用再扫描；
+//! 还会记下扫描到了哪个元素、每一层数组还剩几个元素，下次从那里接着扫描，
+//! 这样一个很大的 bulk string 或者有很多元素的数组分成许多次到达也不会被反复扫描。
+//!
+//! 服务端还可以打开 inline 命令，这样用 `nc`、`telnet` 直接敲 `SET foo "hello world"` 也能被识别。
+
+use std::fmt;
+use std::io;
+
+use bytes::{Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::frame::{Frame, Protocol};
+
+/// 和 Redis 的 `proto-max-bulk-len` 默认值一致
+pub const DEFAULT_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// 正常的命令和回复很少超过两三层嵌套
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+/// 数组、集合、map 最多多少个元素
+pub const DEFAULT_MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+
+/// 和 Redis 一样，inline 命令和协议里的每一行（simple string、error、长度头等）最多 64KB
+pub const DEFAULT_MAX_LINE_LEN: usize = 64 * 1024;
+
+// 等待数据时一次最多预留这么多空间，避免一个声称很大的 bulk string 让我们立刻分配几百 MB
+const MAX_RESERVE: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct RespCodec {
+    protocol: Protocol,
+    max_bulk_len: usize,
+    max_multibulk_len: usize,
+    max_line_len: usize,
+    max_depth: usize,
+    inline: bool,
+    // 上次解码发现缓冲区至少要有这么多字节才可能凑够一帧
+    min_needed: usize,
+    // 扫描到一半的帧：下一个要检查的元素从哪里开始
+    resume: usize,
+    // 外面每一层聚合类型还剩几个元素没检查，最内层在最后
+    pending: Vec<usize>,
+    // 当前这一行已经找过、确定没有行尾的位置，下次从这里接着找
+    searched: usize,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// 对端发来的数据不符合协议或者超出了限制，连接应当关闭
+    Protocol(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Protocol(msg) => write!(f, "protocol error; {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// 扫描阶段的结果
+enum Scan {
+    /// 数据不完整，缓冲区至少要有这么多字节才值得再试
+    Incomplete(usize),
+    Invalid(String),
+}
+
+/// 扫描完一个元素的头部之后
+enum Element {
+    /// 标量或者空的聚合类型，已经完整，值是结束的位置
+    Complete(usize),
+    /// 聚合类型的头部，后面还有这么多个元素
+    Aggregate(usize, usize),
+}
+
+impl Default for RespCodec {
+    fn default() -> RespCodec {
+        RespCodec::new()
+    }
+}
+
+impl RespCodec {
+    pub fn new() -> RespCodec {
+        RespCodec {
+            protocol: Protocol::default(),
+            max_bulk_len: DEFAULT_MAX_BULK_LEN,
+            max_multibulk_len: DEFAULT_MAX_MULTIBULK_LEN,
+            max_line_len: DEFAULT_MAX_LINE_LEN,
+            max_depth: DEFAULT_MAX_DEPTH,
+            inline: false,
+            min_needed: 0,
+            resume: 0,
+            pending: Vec::new(),
+            searched: 0,
+        }
+    }
+
+    /// 单个 bulk string 允许的最大长度，超出时直接报错而不是等数据到齐
+    pub fn max_bulk_len(mut self, max: usize) -> RespCodec {
+        self.max_bulk_len = max;
+        self
+    }
+
+    /// 数组、集合、push 允许的最大元素个数，map 按键值对计数
+    pub fn max_multibulk_len(mut self, max: usize) -> RespCodec {
+        self.max_multibulk_len = max;
+        self
+    }
+
+    /// 一行（包括 inline 命令）允许的最大长度，还没读到行尾就超出时直接报错
+    pub fn max_line_len(mut self, max: usize) -> RespCodec {
+        self.max_line_len = max;
+        self
+    }
+
+    /// 数组、map 等聚合类型允许的最大嵌套层数
+    pub fn max_depth(mut self, max: usize) -> RespCodec {
+        self.max_depth = max;
+        self
+    }
+
+    /// 是否接受 inline 命令：不以 RESP 类型字节开头的一行文本，按空格切分成参数，
+    /// 解码成由 bulk string 组成的数组。只有服务端需要打开
+    pub fn inline_commands(mut self, enabled: bool) -> RespCodec {
+        self.inline = enabled;
+        self
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// 切换编码使用的协议版本，解码总是同时接受 RESP2 和 RESP3
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// 检查缓冲区开头是否已经有一帧完整的数据，返回这一帧结束的位置
+    ///
+    /// 数据不够时从上次停下的元素接着检查，已经确认完整的元素不会再扫描一遍
+    fn scan(&mut self, buf: &[u8]) -> Result<usize, Scan> {
+        loop {
+            let end = match self.scan_element(buf)? {
+                Element::Complete(end) => end,
+                Element::Aggregate(end, len) => {
+                    self.advance(end);
+                    self.pending.push(len);
+                    continue;
+                }
+            };
+            self.advance(end);
+
+            // 一个元素完整了，外层的聚合类型也可能因此完整
+            loop {
+                match self.pending.last_mut() {
+                    None => {
+                        self.restart();
+                        return Ok(end);
+                    }
+                    Some(left) if *left > 1 => {
+                        *left -= 1;
+                        break;
+                    }
+                    Some(_) => {
+                        self.pending.pop();
+                    }
+                }
+            }
+        }
+    }
+
+    /// 检查从 `self.resume` 开始的一个元素，聚合类型只检查头部
+    fn scan_element(&mut self, buf: &[u8]) -> Result<Element, Scan> {
+        let pos = self.resume;
+        let Some(&kind) = buf.get(pos) else {
+            return Err(Scan::Incomplete(pos + 1));
+        };
+        let pos = pos + 1;
+
+        let end = match kind {
+            b'+' | b'-' => self.scan_line(buf, pos)?.1,
+            b':' => {
+                let (line, end) = self.scan_line(buf, pos)?;
+                parse_int(line).map_err(Scan::Invalid)?;
+                end
+            }
+            b',' => {
+                let (line, end) = self.scan_line(buf, pos)?;
+                parse_double(line).map_err(Scan::Invalid)?;
+                end
+            }
+            b'(' => {
+                let (line, end) = self.scan_line(buf, pos)?;
+                parse_big_number(line).map_err(Scan::Invalid)?;
+                end
+            }
+            b'#' => match self.scan_line(buf, pos)? {
+                (b"t" | b"f", end) => end,
+                _ => return Err(Scan::Invalid("invalid boolean".to_string())),
+            },
+            b'_' => match self.scan_line(buf, pos)? {
+                (b"", end) => end,
+                _ => return Err(Scan::Invalid("invalid null".to_string())),
+            },
+            b'$' | b'=' | b'!' => {
+                let (line, start) = self.scan_line(buf, pos)?;
+                let len = parse_int(line).map_err(Scan::Invalid)?;
+                if len == -1 && kind == b'$' {
+                    return Ok(Element::Complete(start));
+                }
+                let len = check_len(len, self.max_bulk_len, "bulk length")?;
+                if kind == b'=' && len < 4 {
+                    return Err(Scan::Invalid("invalid verbatim string".to_string()));
+                }
+
+                let end = start + len + 2;
+                if buf.len() < end {
+                    return Err(Scan::Incomplete(end));
+                }
+                if &buf[end - 2..end] != b"\r\n" {
+                    return Err(Scan::Invalid("missing CRLF after bulk data".to_string()));
+                }
+                if kind == b'=' && buf[start + 3] != b':' {
+                    return Err(Scan::Invalid("invalid verbatim string".to_string()));
+                }
+                end
+            }
+            b'*' | b'~' | b'>' | b'%' => {
+                let (line, end) = self.scan_line(buf, pos)?;
+                let len = parse_int(line).map_err(Scan::Invalid)?;
+                if len == -1 && kind == b'*' {
+                    return Ok(Element::Complete(end));
+                }
+                let len = check_len(len, self.max_multibulk_len, "multibulk length")?;
+                if self.pending.len() >= self.max_depth {
+                    return Err(Scan::Invalid(format!(
+                        "nesting deeper than {} levels",
+                        self.max_depth
+                    )));
+                }
+
+                let items = if kind == b'%' { len.saturating_mul(2) } else { len };
+                if items > 0 {
+                    return Ok(Element::Aggregate(end, items));
+                }
+                end
+            }
+            _ => return Err(Scan::Invalid(format!("invalid frame type byte `{}`", kind))),
+        };
+        Ok(Element::Complete(end))
+    }
+
+    /// 找到从 `pos` 开始的一行，返回行内容（不含 `\r\n`）和下一行的起点
+    fn scan_line<'a>(&mut self, buf: &'a [u8], pos: usize) -> Result<(&'a [u8], usize), Scan> {
+        let from = self.searched.max(pos);
+        let rest = buf.get(from..).unwrap_or_default();
+        match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(i) if from + i - pos > self.max_line_len => {
+                Err(Scan::Invalid("line too long".to_string()))
+            }
+            Some(i) => Ok((&buf[pos..from + i], from + i + 2)),
+            None if buf.len().saturating_sub(pos) > self.max_line_len => {
+                Err(Scan::Invalid("line too long".to_string()))
+            }
+            None => {
+                // 最后一个字节可能是 `\r`，下次从它开始找
+                self.searched = buf.len().saturating_sub(1).max(pos);
+                // 至少还要再来一个字节才可能出现 `\r\n`
+                Err(Scan::Incomplete(buf.len() + 1))
+            }
+        }
+    }
+
+    /// 下一个要检查的元素从 `pos` 开始
+    fn advance(&mut self, pos: usize) {
+        self.resume = pos;
+        self.searched = 0;
+    }
+
+    /// 丢掉扫描到一半的状态，下一帧从头开始
+    fn restart(&mut self) {
+        self.advance(0);
+        self.pending.clear();
+    }
+}
+
+fn check_len(len: i64, max: usize, what: &str) -> Result<usize, Scan> {
+    match usize::try_from(len) {
+        Ok(len) if len <= max => Ok(len),
+        Ok(_) => Err(Scan::Invalid(format!("{} exceeds the limit of {}", what, max))),
+        Err(_) => Err(Scan::Invalid(format!("invalid {}", what))),
+    }
+}
+impl Decoder for RespCodec {
+    type Item = Frame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, Error> {
+        if src.len() < self.min_needed {
+            return Ok(None);
+        }
+
+        while self.inline && src.first().is_some_and(|b| !is_type_byte(*b)) {
+            match decode_inline(src, self.max_line_len, &mut self.searched)? {
+                Some(args) if args.is_empty() => continue,
+                Some(args) => {
+                    self.min_needed = 0;
+                    return Ok(Some(Frame::Array(args.into_iter().map(Frame::Bulk).collect())));
+                }
+                None => {
+                    self.min_needed = src.len() + 1;
+                    return Ok(None);
+                }
+            }
+        }
+
+        match self.scan(src) {
+            Ok(end) => {
+                self.min_needed = 0;
+                // 这一帧已经确认完整，之后解析出的 bulk string 都是 `data` 的切片
+                let data = src.split_to(end).freeze();
+                let mut pos = 0;
+                let frame = parse(&data, &mut pos).map_err(Error::Protocol)?;
+                debug_assert_eq!(pos, end);
+                Ok(Some(frame))
+            }
+            Err(Scan::Incomplete(needed)) => {
+                self.min_needed = needed;
+                src.reserve((needed - src.len()).min(MAX_RESERVE));
+                Ok(None)
+            }
+            Err(Scan::Invalid(msg)) => {
+                self.restart();
+                Err(Error::Protocol(msg))
+            }
+        }
+    }
+}
+
+impl Encoder<&Frame> for RespCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: &Frame, dst: &mut BytesMut) -> Result<(), Error> {
+        frame.encode(self.protocol, dst);
+        Ok(())
+    }
+}
+
+impl Encoder<Frame> for RespCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), Error> {
+        self.encode(&frame, dst)
+    }
+}
+
+fn is_type_byte(b: u8) -> bool {
+    b"+-:$*_%~>,#(=!".contains(&b)
+}
+
+/// 从 `src` 中取出一行 inline 命令并切分参数，数据还不够一行时返回 `None`
+///
+/// 行尾的 `\r` 可有可无，空行返回空的参数列表。`searched` 之前已经确认没有换行，
+/// 从它开始找，数据一点一点到达时不会反复扫描整行。
+fn decode_inline(
+    src: &mut BytesMut,
+    max_len: usize,
+    searched: &mut usize,
+) -> Result<Option<Vec<Bytes>>, Error> {
+    let from = (*searched).min(src.len());
+    let Some(len) = src[from..].iter().position(|b| *b == b'\n').map(|i| from + i) else {
+        if src.len() > max_len {
+            return Err(Error::Protocol("too big inline request".to_string()));
+        }
+        *searched = src.len();
+        return Ok(None);
+    };
+    *searched = 0;
+    if len > max_len {
+        return Err(Error::Protocol("too big inline request".to_string()));
+    }
+
+    let line = src.split_to(len + 1);
+    let line = line[..len].strip_suffix(b"\r").unwrap_or(&line[..len]);
+    split_args(line)
+        .map(Some)
+        .ok_or_else(|| Error::Protocol("unbalanced quotes in request".to_string()))
+}
+
+/// 按 Redis 的规则切分 inline 命令的参数，引号不配对时返回 `None`
+///
+/// 双引号内支持 `\n`、`\r`、`\t`、`\b`、`\a`、`\\`、`\"` 和 `\xHH` 转义；单引号内只支持 `\'`。
+/// 结束的引号后面必须是空白或者行尾。
+fn split_args(line: &[u8]) -> Option<Vec<Bytes>> {
+    let mut args = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
+        }
+
+        let mut arg = Vec::new();
+        // 当前所在的引号，0 表示不在引号内
+        let mut quote = 0u8;
+        loop {
+            let Some(&c) = line.get(i) else {
+                if quote != 0 {
+                    return None;
+                }
+                break;
+            };
+
+            if quote == 0 {
+                match c {
+                    c if c.is_ascii_whitespace() => break,
+                    b'"' | b'\'' => quote = c,
+                    c => arg.push(c),
+                }
+            } else if c == quote {
+                // 结束引号后面必须是空白
+                if line.get(i + 1).is_some_and(|b| !b.is_ascii_whitespace()) {
+                    return None;
+                }
+                i += 1;
+                break;
+            } else if c == b'\\' && quote == b'"' {
+                match line.get(i + 1..i + 4) {
+                    Some([b'x', h, l]) if h.is_ascii_hexdigit() && l.is_ascii_hexdigit() => {
+                        arg.push(hex(*h) << 4 | hex(*l));
+                        i += 3;
+                    }
+                    _ => {
+                        i += 1;
+                        arg.push(match line.get(i) {
+                            Some(b'n') => b'\n',
+                            Some(b'r') => b'\r',
+                            Some(b't') => b'\t',
+                            Some(b'b') => 0x08,
+                            Some(b'a') => 0x07,
+                            Some(c) => *c,
+                            None => return None,
+                        });
+                    }
+                }
+            } else if c == b'\\' && line.get(i + 1) == Some(&b'\'') {
+                arg.push(b'\'');
+                i += 1;
+            } else {
+                arg.push(c);
+            }
+            i += 1;
+        }
+
+        args.push(Bytes::from(arg));
+    }
+}
+
+fn hex(b: u8) -> u8 {
+    (b as char).to_digit(16).unwrap() as u8
+}
+
+/// 解析已经通过 `scan` 检查的数据
+fn parse(data: &Bytes, pos: &mut usize) -> Result<Frame, String> {
+    let kind = data[*pos];
+    *pos += 1;
+
+    let frame = match kind {
+        b'+' => Frame::Simple(to_string(line(data, pos))?),
+        b'-' => Frame::Error(to_string(line(data, pos))?),
+        b':' => Frame::Integer(parse_int(line(data, pos))?),
+        b',' => Frame::Double(parse_double(line(data, pos))?),
+        b'(' => Frame::BigNumber(parse_big_number(line(data, pos))?),
+        b'#' => Frame::Boolean(line(data, pos) == b"t"),
+        b'_' => {
+            line(data, pos);
+            Frame::Null
+        }
+        b'$' | b'=' | b'!' => {
+            let len = parse_int(line(data, pos))?;
+            if len == -1 {
+                return Ok(Frame::Null);
+            }
+            let start = *pos;
+            let end = start + len as usize;
+            *pos = end + 2;
+            match kind {
+                b'$' => Frame::Bulk(data.slice(start..end)),
+                b'=' => Frame::Verbatim {
+                    format: [data[start], data[start + 1], data[start + 2]],
+                    text: data.slice(start + 4..end),
+                },
+                _ => Frame::Error(to_string(&data[start..end])?),
+            }
+        }
+        b'*' | b'~' | b'>' | b'%' => {
+            let len = parse_int(line(data, pos))?;
+            if len == -1 {
+                return Ok(Frame::Null);
+            }
+            // 长度来自对端，不能直接拿来预分配
+            let len = len as usize;
+            if kind == b'%' {
+                let mut pairs = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    pairs.push((parse(data, pos)?, parse(data, pos)?));
+                }
+                Frame::Map(pairs)
+            } else {
+                let mut items = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    items.push(parse(data, pos)?);
+                }
+                match kind {
+                    b'*' => Frame::Array(items),
+                    b'~' => Frame::Set(items),
+                    _ => Frame::Push(items),
+                }
+            }
+        }
+        _ => unreachable!("frame was validated by scan"),
+    };
+
+    Ok(frame)
+}
+
+fn line<'a>(data: &'a [u8], pos: &mut usize) -> &'a [u8] {
+    let start = *pos;
+    let len = data[start..]
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .expect("frame was validated by scan");
+    *pos = start + len + 2;
+    &data[start..start + len]
+}
+
+fn to_string(line: &[u8]) -> Result<String, String> {
+    String::from_utf8(line.to_vec()).map_err(|_| "invalid utf-8".to_string())
+}
+
+fn parse_int(line: &[u8]) -> Result<i64, String> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| "invalid integer".to_string())
+}
+
+fn parse_double(line: &[u8]) -> Result<f64, String> {
+    match line {
+        b"inf" => Ok(f64::INFINITY),
+        b"-inf" => Ok(f64::NEG_INFINITY),
+        b"nan" => Ok(f64::NAN),
+        _ => std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| "invalid double".to_string()),
+    }
+}
+
+fn parse_big_number(line: &[u8]) -> Result<String, String> {
+    let digits = line.strip_prefix(b"-").unwrap_or(line);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err("invalid big number".to_string());
+    }
+    to_string(line)
+}
+
+/// 随机往返测试和各种限制的测试
+///
+/// 随机生成各种 `Frame`，编码之后再解码，检查结果和原来的帧一致：一次性喂给解码器、
+/// 切成随机大小的小块逐块喂进去、多帧首尾相连放在同一个缓冲区里；随机改写、截断编码结果时，
+/// 解码器可以报错但不能 panic。种子是固定的，失败时可以稳定复现。
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bytes::{Bytes, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::{DEFAULT_MAX_LINE_LEN, RespCodec};
+    use crate::frame::{Frame, Protocol};
+
+    const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+    const ITERATIONS: usize = 10_000;
+
+    /// xorshift64，够用来生成测试数据，而且同一个种子每次结果都一样，方便复现
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn bytes(&mut self, max_len: usize) -> Bytes {
+            let len = self.below(max_len + 1);
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+
+        /// 不含 `\r`、`\n` 的可打印字符串，simple string 和 error 只能用这样的内容
+        fn line(&mut self, max_len: usize) -> String {
+            let len = self.below(max_len + 1);
+            (0..len)
+                .map(|_| (b' ' + self.below(95) as u8) as char)
+                .collect()
+        }
+    }
+
+    fn random_frame(rng: &mut Rng, depth: usize) -> Frame {
+        // 越深越倾向于生成标量，避免帧无限膨胀
+        let kinds = if depth >= 4 { 10 } else { 14 };
+        match rng.below(kinds) {
+            0 => Frame::Simple(rng.line(20)),
+            1 => Frame::Error(format!("ERR {}", rng.line(20))),
+            2 => Frame::Integer(rng.next() as i64),
+            3 => Frame::Bulk(rng.bytes(64)),
+            4 => Frame::Null,
+            5 => match rng.below(4) {
+                0 => Frame::Double(f64::INFINITY),
+                1 => Frame::Double(f64::NEG_INFINITY),
+                // NaN 不等于自身，没法直接比较，这里不生成
+                _ => Frame::Double((rng.next() as i64) as f64 / (rng.below(1000) + 1) as f64),
+            },
+            6 => Frame::Boolean(rng.below(2) == 0),
+            7 => {
+                let sign = if rng.below(2) == 0 { "-" } else { "" };
+                let digits: String = (0..rng.below(40) + 1)
+                    .map(|_| (b'0' + rng.below(10) as u8) as char)
+                    .collect();
+                Frame::BigNumber(format!("{}{}", sign, digits))
+            }
+            8 => Frame::Verbatim {
+                format: *b"txt",
+                text: rng.bytes(32),
+            },
+            9 => Frame::Bulk(Bytes::from_static(b"")),
+            10 => Frame::Array(random_items(rng, depth)),
+            11 => Frame::Set(random_items(rng, depth)),
+            12 => Frame::Push(random_items(rng, depth)),
+            _ => {
+                let len = rng.below(5);
+                Frame::Map(
+                    (0..len)
+                        .map(|_| (random_frame(rng, depth + 1), random_frame(rng, depth + 1)))
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    fn random_items(rng: &mut Rng, depth: usize) -> Vec<Frame> {
+        let len = rng.below(6);
+        (0..len).map(|_| random_frame(rng, depth + 1)).collect()
+    }
+
+    fn encode(frame: &Frame) -> BytesMut {
+        let mut codec = RespCodec::new();
+        codec.set_protocol(Protocol::Resp3);
+        let mut buf = BytesMut::new();
+        codec.encode(frame, &mut buf).unwrap();
+        buf
+    }
+
+    /// 把 `data` 切成随机大小的块依次喂给解码器，返回解出的所有帧
+    fn decode_chunked(rng: &mut Rng, data: &[u8]) -> Vec<Frame> {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::new();
+        let mut frames = Vec::new();
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let n = (rng.below(16) + 1).min(rest.len());
+            buf.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+            while let Some(frame) = codec.decode(&mut buf).unwrap() {
+                frames.push(frame);
+            }
+        }
+
+        assert!(buf.is_empty(), "{} bytes left undecoded", buf.len());
+        frames
+    }
+
+    fn round_trip_once(rng: &mut Rng) {
+        let frame = random_frame(rng, 0);
+        let encoded = encode(&frame);
+
+        // 一次性解码
+        let mut buf = encoded.clone();
+        let decoded = RespCodec::new().decode(&mut buf).unwrap();
+        assert_eq!(decoded.as_ref(), Some(&frame));
+        assert!(buf.is_empty());
+
+        // 分块解码
+        assert_eq!(decode_chunked(rng, &encoded), vec![frame.clone()]);
+
+        // 多帧相连
+        let frames: Vec<Frame> = (0..rng.below(4) + 2)
+            .map(|_| random_frame(rng, 0))
+            .collect();
+        let mut data = BytesMut::new();
+        for f in &frames {
+            data.extend_from_slice(&encode(f));
+        }
+        assert_eq!(decode_chunked(rng, &data), frames);
+    }
+
+    /// 随机改写或截断一段合法数据，解码器只能返回错误或者等待更多数据，不能 panic
+    fn mutate_once(rng: &mut Rng) {
+        let mut data = encode(&random_frame(rng, 0)).to_vec();
+        for _ in 0..rng.below(4) + 1 {
+            match rng.below(3) {
+                0 if !data.is_empty() => {
+                    let i = rng.below(data.len());
+                    data[i] = rng.next() as u8;
+                }
+                1 => data.truncate(rng.below(data.len() + 1)),
+                _ => {
+                    let i = rng.below(data.len() + 1);
+                    data.insert(i, b"\r\n$*%-:0123456789"[rng.below(17)]);
+                }
+            }
+        }
+
+        let mut codec = RespCodec::new().max_bulk_len(1024).max_depth(8);
+        let mut buf = BytesMut::from(&data[..]);
+        while let Ok(Some(_)) = codec.decode(&mut buf) {}
+    }
+
+    /// 解出来的 bulk string 直接引用读缓冲区里的内存，不会再拷贝一份
+    #[test]
+    fn zero_copy() {
+        let payload = vec![b'x'; 4096];
+        let mut buf = encode(&Frame::Array(vec![Frame::Bulk(Bytes::from(payload))]));
+        let start = buf.as_ptr() as usize;
+        let end = start + buf.len();
+
+        let Some(Frame::Array(items)) = RespCodec::new().decode(&mut buf).unwrap() else {
+            panic!("expected an array");
+        };
+        let Frame::Bulk(data) = &items[0] else {
+            panic!("expected a bulk string");
+        };
+        let ptr = data.as_ptr() as usize;
+        assert!(start <= ptr && ptr < end, "bulk data was copied");
+    }
+
+    /// bulk 长度在读到头部时就检查，不用等数据到齐
+    #[test]
+    fn bulk_len_limit() {
+        let mut codec = RespCodec::new().max_bulk_len(16);
+        let mut buf = BytesMut::from(&b"$17\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        let mut buf = BytesMut::from(&b"$16\r\n0123456789abcdef\r\n"[..]);
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+    }
+
+    /// 没有 `\r\n` 的超长行也要报错，不能无限缓冲下去
+    #[test]
+    fn line_len_limit() {
+        let mut codec = RespCodec::new().max_line_len(16);
+        let mut buf = BytesMut::from(&b"+0123456789abcdefg"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        // 默认一行最多 64KB，和 bulk 长度的限制无关
+        let mut buf = BytesMut::from(&b"+"[..]);
+        buf.extend_from_slice(&vec![b'a'; DEFAULT_MAX_LINE_LEN + 1]);
+        assert!(RespCodec::new().decode(&mut buf).is_err());
+    }
+
+    /// 元素个数也在读到头部时就检查，map 按键值对计数
+    #[test]
+    fn multibulk_len_limit() {
+        let mut codec = RespCodec::new().max_multibulk_len(2);
+        let mut buf = BytesMut::from(&b"*3\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+        let mut buf = BytesMut::from(&b"%3\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+        let mut buf = BytesMut::from(&b"*2\r\n:1\r\n:2\r\n"[..]);
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+    }
+
+    #[test]
+    fn depth_limit() {
+        let nested = |depth: usize| {
+            let mut frame = Frame::Integer(1);
+            for _ in 0..depth {
+                frame = Frame::Array(vec![frame]);
+            }
+            encode(&frame)
+        };
+        let mut codec = RespCodec::new().max_depth(3);
+        assert!(codec.decode(&mut nested(3)).unwrap().is_some());
+        assert!(codec.decode(&mut nested(4)).is_err());
+    }
+
+    #[test]
+    fn partial_frames() {
+        // 声称有很多元素的数组不会导致预先分配大量内存
+        let mut buf = BytesMut::from(&b"*1000000\r\n:1\r\n"[..]);
+        assert!(RespCodec::new().decode(&mut buf).unwrap().is_none());
+
+        // 不完整的数据什么都不消耗
+        let partial = b"*2\r\n$3\r\nfoo\r\n$3\r\nba";
+        let mut buf = BytesMut::from(&partial[..]);
+        assert!(RespCodec::new().decode(&mut buf).unwrap().is_none());
+        assert_eq!(&buf[..], partial);
+    }
+
+    /// 一个字节一个字节地喂一帧很大的数据，扫描过的部分不会被重复扫描，
+    /// 否则这里的耗时会随帧的大小平方增长
+    #[test]
+    fn resume_scan() {
+        let items: Vec<Frame> = (0..20_000).map(Frame::Integer).collect();
+        let mut data = encode(&Frame::Array(items.clone())).to_vec();
+        data.extend_from_slice(b"+");
+        data.extend_from_slice(&vec![b'a'; 60 * 1024]);
+        data.extend_from_slice(b"\r\n");
+
+        let started = Instant::now();
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::new();
+        let mut frames = Vec::new();
+        for b in data {
+            buf.extend_from_slice(&[b]);
+            if let Some(frame) = codec.decode(&mut buf).unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], Frame::Array(items));
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "decoding byte by byte took {:?}",
+            started.elapsed()
+        );
+    }
+
+    #[test]
+    fn inline_commands() {
+        let decode = |line: &[u8]| {
+            let mut buf = BytesMut::from(line);
+            RespCodec::new().inline_commands(true).decode(&mut buf)
+        };
+        let args = |args: &[&str]| {
+            let args = args.iter().map(|a| Frame::Bulk(Bytes::from(a.to_string())));
+            Some(Frame::Array(args.collect()))
+        };
+
+        assert_eq!(decode(b"PING\r\n").unwrap(), args(&["PING"]));
+        assert_eq!(
+            decode(b"  set  foo\tbar\n").unwrap(),
+            args(&["set", "foo", "bar"])
+        );
+        assert_eq!(
+            decode(b"SET k \"a \\x41\\n\\\"\" 'it\\'s'\r\n").unwrap(),
+            args(&["SET", "k", "a A\n\"", "it's"])
+        );
+        assert_eq!(decode(b"SET k \"\"\r\n").unwrap(), args(&["SET", "k", ""]));
+        // 空行被跳过，后面的 RESP 数组照常解析
+        assert_eq!(
+            decode(b"\r\n\r\n*1\r\n$4\r\nPING\r\n").unwrap(),
+            args(&["PING"])
+        );
+        // 还没读到行尾
+        assert_eq!(decode(b"GET fo").unwrap(), None);
+
+        assert!(decode(b"SET k \"abc\r\n").is_err());
+        assert!(decode(b"SET \"a\"b c\r\n").is_err());
+        assert!(decode(&vec![b'a'; DEFAULT_MAX_LINE_LEN + 1]).is_err());
+
+        // 没有打开 inline 时照旧报错
+        let mut buf = BytesMut::from(&b"PING\r\n"[..]);
+        assert!(RespCodec::new().decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng(SEED);
+        for _ in 0..ITERATIONS {
+            round_trip_once(&mut rng);
+        }
+    }
+
+    #[test]
+    fn mutated_input() {
+        let mut rng = Rng(SEED);
+        for _ in 0..ITERATIONS {
+            mutate_once(&mut rng);
+        }
+    }
+}
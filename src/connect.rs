@@ -0,0 +1,98 @@
+//! 同时连接多个地址，用最先连上的那个
+//!
+//! 一个服务端往往有好几个地址（比如 `localhost` 同时解析出 `::1` 和 `127.0.0.1`，或者配置了多个副本），
+//! 挨个尝试的话，前面的地址不通时要等它超时才轮到下一个。这里参考 happy eyeballs（RFC 8305）：
+//! 按顺序发起连接，每隔 [`STAGGER`] 再多发起一个，前一个失败了就立刻发起下一个，
+//! 谁先连上就用谁，其余还在进行的连接直接取消。所有地址都失败时把每个地址的错误一起返回。
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use tokio::net::{TcpStream, ToSocketAddrs, lookup_host};
+use tokio::time::{Instant, sleep};
+
+/// 发起下一个连接之前等前一个多久，RFC 8305 推荐 250ms
+pub const STAGGER: Duration = Duration::from_millis(250);
+
+/// 所有地址都没连上
+#[derive(Debug)]
+pub struct ConnectError {
+    /// 按失败的先后顺序排列
+    pub attempts: Vec<(SocketAddr, io::Error)>,
+}
+
+/// 解析 `addr` 得到的所有地址都去连，返回最先连上的连接和它的地址
+pub async fn connect<A: ToSocketAddrs>(addr: A) -> mini_redis::Result<(TcpStream, SocketAddr)> {
+    let addrs: Vec<SocketAddr> = lookup_host(addr).await?.collect();
+    Ok(race(&addrs, STAGGER).await?)
+}
+
+/// 按 `addrs` 的顺序错开 `stagger` 发起连接，返回最先连上的
+pub async fn race(
+    addrs: &[SocketAddr],
+    stagger: Duration,
+) -> Result<(TcpStream, SocketAddr), ConnectError> {
+    let mut pending = addrs.iter().copied().peekable();
+    let mut attempts = FuturesUnordered::new();
+    let mut errors = Vec::new();
+
+    match pending.next() {
+        Some(addr) => attempts.push(attempt(addr)),
+        None => return Err(ConnectError { attempts: errors }),
+    }
+    let mut timer = pin!(sleep(stagger));
+
+    loop {
+        tokio::select! {
+            Some((addr, res)) = attempts.next() => match res {
+                // 返回时 `attempts` 被丢弃，还在进行中的连接也就取消了
+                Ok(socket) => return Ok((socket, addr)),
+                Err(e) => {
+                    errors.push((addr, e));
+                    // 失败了不用等计时器，马上换下一个地址
+                    match pending.next() {
+                        Some(addr) => {
+                            attempts.push(attempt(addr));
+                            timer.as_mut().reset(Instant::now() + stagger);
+                        }
+                        None if attempts.is_empty() => {
+                            return Err(ConnectError { attempts: errors });
+                        }
+                        None => {}
+                    }
+                }
+            },
+            () = &mut timer, if pending.peek().is_some() => {
+                attempts.push(attempt(pending.next().unwrap()));
+                timer.as_mut().reset(Instant::now() + stagger);
+            }
+        }
+    }
+}
+
+async fn attempt(addr: SocketAddr) -> (SocketAddr, io::Result<TcpStream>) {
+    (addr, TcpStream::connect(addr).await)
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.attempts.is_empty() {
+            return "no addresses to connect to".fmt(f);
+        }
+
+        write!(f, "all connection attempts failed")?;
+        for (i, (addr, e)) in self.attempts.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { "; " };
+            write!(f, "{}{}: {}", sep, addr, e)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConnectError {}
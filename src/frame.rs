@@ -0,0 +1,145 @@
+//! RESP2 / RESP3 数据帧
+//!
+//! `mini_redis::Frame` 只覆盖了 RESP2 的几种类型，这里在它的基础上补上 RESP3 新增的
+//! map、set、double、boolean、big number、verbatim string 和 push。
+//! 同一个 `Frame` 按连接协商出的 [`Protocol`] 编码：RESP2 连接上 RESP3 独有的类型会退化成
+//! RESP2 中最接近的表示，比如 map 展开成 [k1, v1, k2, v2, ...] 的数组。
+//!
+//! 解码在 [`crate::codec::RespCodec`] 中完成。
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// 连接使用的协议版本，由 HELLO 命令协商，默认 RESP2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn version(self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    Array(Vec<Frame>),
+    /// RESP3：键值对，保持插入顺序
+    Map(Vec<(Frame, Frame)>),
+    /// RESP3：无序、不重复的集合
+    Set(Vec<Frame>),
+    /// RESP3：浮点数
+    Double(f64),
+    /// RESP3：布尔值
+    Boolean(bool),
+    /// RESP3：超出 64 位整数范围的大整数，以十进制字符串保存
+    BigNumber(String),
+    /// RESP3：带格式说明的文本，`format` 是三个字符，例如 `txt`、`mkd`
+    Verbatim { format: [u8; 3], text: Bytes },
+    /// RESP3：服务端主动推送的数据，例如 pub/sub 消息
+    Push(Vec<Frame>),
+}
+
+impl Frame {
+    /// 类型的名字，报错时用，不会把对端发来的内容原样带出去
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Frame::Simple(_) => "simple string",
+            Frame::Error(_) => "error",
+            Frame::Integer(_) => "integer",
+            Frame::Bulk(_) => "bulk string",
+            Frame::Null => "null",
+            Frame::Array(_) => "array",
+            Frame::Map(_) => "map",
+            Frame::Set(_) => "set",
+            Frame::Double(_) => "double",
+            Frame::Boolean(_) => "boolean",
+            Frame::BigNumber(_) => "big number",
+            Frame::Verbatim { .. } => "verbatim string",
+            Frame::Push(_) => "push",
+        }
+    }
+
+    /// 按 `protocol` 把这一帧编码到 `dst` 的末尾
+    pub fn encode(&self, protocol: Protocol, dst: &mut BytesMut) {
+        let resp3 = protocol == Protocol::Resp3;
+
+        match self {
+            Frame::Simple(s) => put_line(dst, b'+', s.as_bytes()),
+            Frame::Error(s) => put_line(dst, b'-', s.as_bytes()),
+            Frame::Integer(n) => put_line(dst, b':', n.to_string().as_bytes()),
+            Frame::Bulk(data) => put_blob(dst, b'$', &[data]),
+            Frame::Null if resp3 => dst.put_slice(b"_\r\n"),
+            Frame::Null => dst.put_slice(b"$-1\r\n"),
+            Frame::Array(items) => put_items(dst, b'*', items, protocol),
+            Frame::Map(pairs) => {
+                if resp3 {
+                    put_line(dst, b'%', pairs.len().to_string().as_bytes());
+                } else {
+                    // RESP2 没有 map，展开成 [k1, v1, k2, v2, ...]
+                    put_line(dst, b'*', (pairs.len() * 2).to_string().as_bytes());
+                }
+                for (key, value) in pairs {
+                    key.encode(protocol, dst);
+                    value.encode(protocol, dst);
+                }
+            }
+            Frame::Set(items) if resp3 => put_items(dst, b'~', items, protocol),
+            Frame::Push(items) if resp3 => put_items(dst, b'>', items, protocol),
+            Frame::Set(items) | Frame::Push(items) => put_items(dst, b'*', items, protocol),
+            Frame::Double(n) if resp3 => put_line(dst, b',', format_double(*n).as_bytes()),
+            Frame::Double(n) => put_blob(dst, b'$', &[format_double(*n).as_bytes()]),
+            Frame::Boolean(b) if resp3 => dst.put_slice(if *b { b"#t\r\n" } else { b"#f\r\n" }),
+            Frame::Boolean(b) => dst.put_slice(if *b { b":1\r\n" } else { b":0\r\n" }),
+            Frame::BigNumber(n) if resp3 => put_line(dst, b'(', n.as_bytes()),
+            Frame::BigNumber(n) => put_blob(dst, b'$', &[n.as_bytes()]),
+            Frame::Verbatim { format, text } if resp3 => {
+                put_blob(dst, b'=', &[format, b":", text]);
+            }
+            Frame::Verbatim { text, .. } => put_blob(dst, b'$', &[text]),
+        }
+    }
+}
+
+fn format_double(n: f64) -> String {
+    if n.is_nan() {
+        "nan".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+fn put_line(dst: &mut BytesMut, prefix: u8, line: &[u8]) {
+    dst.put_u8(prefix);
+    dst.put_slice(line);
+    dst.put_slice(b"\r\n");
+}
+
+/// 写入带长度前缀的二进制数据，`parts` 会被依次拼接
+fn put_blob(dst: &mut BytesMut, prefix: u8, parts: &[&[u8]]) {
+    let len: usize = parts.iter().map(|p| p.len()).sum();
+    put_line(dst, prefix, len.to_string().as_bytes());
+    for part in parts {
+        dst.put_slice(part);
+    }
+    dst.put_slice(b"\r\n");
+}
+
+fn put_items(dst: &mut BytesMut, prefix: u8, items: &[Frame], protocol: Protocol) {
+    put_line(dst, prefix, items.len().to_string().as_bytes());
+    for item in items {
+        item.encode(protocol, dst);
+    }
+}
@@ -0,0 +1,12 @@
+//! 多个 bin 之间共享的代码
+//!
+//! `src/bin` 下的每个文件都是一个独立的小程序，需要在服务端和客户端之间复用的类型放在这里，
+//! 通过 `my_redis_project::xxx` 引入。
+
+pub mod codec;
+pub mod connect;
+pub mod frame;
+pub mod mailbox;
+pub mod select;
+pub mod subscription;
+pub mod tls;
@@ -0,0 +1,205 @@
+//! 带慢消费者策略的有界消息队列
+//!
+//! pub/sub 的发布方和订阅方速度往往不一致，`Mailbox` 在两者之间做缓冲，
+//! 缓冲区满了之后怎么办由 [`Policy`] 决定。服务端给每个订阅连接配一个，
+//! 客户端的 `Subscription` 也用它来缓冲从服务端收到的消息。
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::poll_fn;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+/// 消费者跟不上生产者时的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// 缓冲区满了之后让生产者等待，直到消费者腾出空间（背压）
+    Block,
+    /// 缓冲区满了之后丢弃最旧的一条消息，丢弃的数量会以 `Recv::Lagged` 告知消费者
+    DropOldest,
+    /// 缓冲区满了之后直接断开消费者
+    Disconnect,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Policy, String> {
+        match s {
+            "block" => Ok(Policy::Block),
+            "drop-oldest" => Ok(Policy::DropOldest),
+            "disconnect" => Ok(Policy::Disconnect),
+            _ => Err(format!(
+                "unknown slow consumer policy `{}`, expected block, drop-oldest or disconnect",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Policy::Block => "block",
+            Policy::DropOldest => "drop-oldest",
+            Policy::Disconnect => "disconnect",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 消费者从 `Mailbox` 中取出的内容
+#[derive(Debug, PartialEq, Eq)]
+pub enum Recv<T> {
+    /// 一条正常的消息
+    Item(T),
+    /// 自上次取出以来，因为消费太慢而被丢弃的消息数量
+    Lagged(u64),
+}
+
+/// 生产者写入失败：`Mailbox` 已经关闭（消费者退出，或者按 `Disconnect` 策略被断开）
+#[derive(Debug, PartialEq, Eq)]
+pub struct Closed<T>(pub T);
+
+pub struct Mailbox<T> {
+    policy: Policy,
+    capacity: usize,
+    state: Mutex<State<T>>,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    // 还没有告知消费者的丢弃数量
+    lagged: u64,
+    closed: bool,
+    // 等待新消息的消费者
+    rx_waker: Option<Waker>,
+    // `Block` 策略下等待空间的生产者
+    tx_wakers: Vec<Waker>,
+}
+
+impl<T> Mailbox<T> {
+    /// 创建一个最多缓冲 `capacity` 条消息的队列
+    pub fn new(policy: Policy, capacity: usize) -> Mailbox<T> {
+        assert!(capacity > 0, "mailbox capacity must be greater than zero");
+
+        Mailbox {
+            policy,
+            capacity,
+            state: Mutex::new(State {
+                queue: VecDeque::with_capacity(capacity),
+                lagged: 0,
+                closed: false,
+                rx_waker: None,
+                tx_wakers: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// 写入一条消息，缓冲区满了之后按策略处理
+    ///
+    /// 只有 `Block` 策略会真正等待；`DropOldest` 总是立即成功，
+    /// `Disconnect` 在缓冲区满时关闭队列并返回 `Closed`。
+    pub async fn send(&self, item: T) -> Result<(), Closed<T>> {
+        let mut item = Some(item);
+        poll_fn(|cx| self.poll_send(cx, &mut item)).await
+    }
+
+    fn poll_send(&self, cx: &mut Context<'_>, item: &mut Option<T>) -> Poll<Result<(), Closed<T>>> {
+        let mut state = self.state.lock().unwrap();
+        let value = item.take().expect("polled after completion");
+
+        if state.closed {
+            return Poll::Ready(Err(Closed(value)));
+        }
+
+        if state.queue.len() >= self.capacity {
+            match self.policy {
+                Policy::Block => {
+                    // 没有空间，记下 waker 等消费者取走消息后再试
+                    *item = Some(value);
+                    if !state.tx_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                        state.tx_wakers.push(cx.waker().clone());
+                    }
+                    return Poll::Pending;
+                }
+                Policy::DropOldest => {
+                    state.queue.pop_front();
+                    state.lagged += 1;
+                }
+                Policy::Disconnect => {
+                    // 已经缓冲的消息也不再投递，一并算作丢弃
+                    state.lagged += state.queue.len() as u64 + 1;
+                    state.queue.clear();
+                    state.closed = true;
+                    state.wake_all();
+                    return Poll::Ready(Err(Closed(value)));
+                }
+            }
+        }
+
+        state.queue.push_back(value);
+        if let Some(waker) = state.rx_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// 取出下一条消息，没有消息时等待
+    ///
+    /// 返回 `None` 表示队列已关闭且缓冲的内容都已取完。
+    pub async fn recv(&self) -> Option<Recv<T>> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<Recv<T>>> {
+        let mut state = self.state.lock().unwrap();
+
+        // 丢弃的数量排在后续消息之前告知，这样消费者能知道“缺口”在哪里
+        if state.lagged > 0 {
+            let lagged = std::mem::take(&mut state.lagged);
+            return Poll::Ready(Some(Recv::Lagged(lagged)));
+        }
+
+        if let Some(item) = state.queue.pop_front() {
+            for waker in state.tx_wakers.drain(..) {
+                waker.wake();
+            }
+            return Poll::Ready(Some(Recv::Item(item)));
+        }
+
+        if state.closed {
+            return Poll::Ready(None);
+        }
+
+        state.rx_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// 关闭队列，之后的写入都会失败，消费者取完剩余消息后收到 `None`
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.wake_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+}
+
+impl<T> State<T> {
+    fn wake_all(&mut self) {
+        if let Some(waker) = self.rx_waker.take() {
+            waker.wake();
+        }
+        for waker in self.tx_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! 在任意多个 future 之间选择
+//!
+//! `tokio::select!` 的分支写死在代码里，`select_all` 接收一组同类型的 future（类型不同时先装箱成
+//! `BoxFuture`），哪个先完成就返回它的下标和结果，其余的 future 随之被丢弃，效果和 `select!`
+//! 取消没选中的分支一样。
+//!
+//! 默认每次 poll 从随机的位置开始轮询，避免排在前面的 future 总是抢先、后面的饿死；
+//! 调用 [`Select::biased`] 后按下标顺序轮询，对应 `select!` 里的 `biased;`。
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// `select_all` 返回的 future，完成时得到 `(下标, 结果)`
+pub struct Select<F> {
+    futures: Vec<Pin<Box<F>>>,
+    order: Order,
+}
+
+/// `select_some` 返回的 future
+///
+/// 结果为 `None` 的 future 相当于 `select!` 里模式没匹配上的分支：它被禁用，继续等其余的；
+/// 所有 future 都得到 `None` 时返回 `None`，对应 `select!` 的 `else` 分支。
+pub struct SelectSome<F> {
+    // `None` 表示这个分支已经被禁用
+    futures: Vec<Option<Pin<Box<F>>>>,
+    order: Order,
+}
+
+/// 决定每次 poll 从哪个 future 开始
+struct Order {
+    biased: bool,
+    // xorshift 的状态，不需要密码学强度，够打散就行
+    rng: u64,
+}
+
+/// 同时等待 `futures`，返回第一个完成的下标和结果
+///
+/// # Panics
+///
+/// `futures` 为空时 panic，没有 future 的 `Select` 永远不会完成。
+pub fn select_all<I>(futures: I) -> Select<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Future,
+{
+    let futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+    assert!(!futures.is_empty(), "select_all called with no futures");
+    Select {
+        futures,
+        order: Order::new(),
+    }
+}
+
+/// 同时等待 `futures`，返回第一个得到 `Some` 的下标和值
+pub fn select_some<I, T>(futures: I) -> SelectSome<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Future<Output = Option<T>>,
+{
+    SelectSome {
+        futures: futures.into_iter().map(|f| Some(Box::pin(f))).collect(),
+        order: Order::new(),
+    }
+}
+
+impl<F> Select<F> {
+    /// 按下标顺序轮询，前面的 future 优先
+    pub fn biased(mut self) -> Select<F> {
+        self.order.biased = true;
+        self
+    }
+}
+
+impl<F> SelectSome<F> {
+    /// 按下标顺序轮询，前面的 future 优先
+    pub fn biased(mut self) -> SelectSome<F> {
+        self.order.biased = true;
+        self
+    }
+}
+
+impl Order {
+    fn new() -> Order {
+        // `RandomState` 每次创建都带一组新的随机 key，拿它当种子，省得引入 rand
+        let seed = RandomState::new().build_hasher().finish();
+        Order {
+            biased: false,
+            rng: seed | 1,
+        }
+    }
+
+    /// 这一轮从第几个开始
+    fn start(&mut self, len: usize) -> usize {
+        if self.biased {
+            return 0;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng % len as u64) as usize
+    }
+}
+
+impl<F: Future> Future for Select<F> {
+    type Output = (usize, F::Output);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let len = this.futures.len();
+        assert!(len > 0, "`Select` polled after completion");
+
+        let start = this.order.start(len);
+        for i in (start..len).chain(0..start) {
+            if let Poll::Ready(output) = this.futures[i].as_mut().poll(cx) {
+                // 其余的 future 现在就丢掉，不用等 `Select` 本身被丢弃
+                this.futures.clear();
+                return Poll::Ready((i, output));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<F, T> Future for SelectSome<F>
+where
+    F: Future<Output = Option<T>>,
+{
+    type Output = Option<(usize, T)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let len = this.futures.len();
+
+        let start = if len == 0 { 0 } else { this.order.start(len) };
+        for i in (start..len).chain(0..start) {
+            let Some(future) = &mut this.futures[i] else {
+                continue;
+            };
+            match future.as_mut().poll(cx) {
+                Poll::Ready(Some(value)) => {
+                    this.futures.clear();
+                    return Poll::Ready(Some((i, value)));
+                }
+                Poll::Ready(None) => this.futures[i] = None,
+                Poll::Pending => {}
+            }
+        }
+
+        if this.futures.iter().all(Option::is_none) {
+            this.futures.clear();
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::future::{self, Future};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+
+    use futures::FutureExt;
+    use futures::future::BoxFuture;
+    use tokio::sync::mpsc;
+
+    use super::{select_all, select_some};
+
+    /// 对应 select_try2.rs：`Some(v) = rx.recv()` 没匹配上的分支被禁用，都没匹配上时走 `else`
+    #[tokio::test]
+    async fn first_some() {
+        let (tx1, mut rx1) = mpsc::channel(128);
+        let (tx2, mut rx2) = mpsc::channel(128);
+
+        tokio::spawn(async move {
+            tx1.send("I want to learn").await.unwrap();
+            tx2.send("I want to study").await.unwrap();
+        });
+
+        let got = select_some([rx1.recv(), rx2.recv()]).await;
+        assert!(got.is_some());
+
+        // 发送方都已经没了，两边都收到 None，相当于 `else` 分支
+        while rx1.recv().await.is_some() {}
+        while rx2.recv().await.is_some() {}
+        assert_eq!(select_some([rx1.recv(), rx2.recv()]).await, None);
+    }
+
+    /// 对应 select_try3.rs：循环从三个通道里收，直到全部关闭
+    #[tokio::test]
+    async fn until_all_closed() {
+        let (tx1, mut rx1) = mpsc::channel(128);
+        let (tx2, mut rx2) = mpsc::channel(128);
+        let (tx3, mut rx3) = mpsc::channel(128);
+
+        tokio::spawn(async move {
+            tx1.send("value1").await.unwrap();
+            tx2.send("value2").await.unwrap();
+            tx3.send("value3").await.unwrap();
+        });
+
+        let mut got = HashSet::new();
+        while let Some((_, msg)) = select_some([rx1.recv(), rx2.recv(), rx3.recv()]).await {
+            got.insert(msg);
+        }
+
+        assert_eq!(got, HashSet::from(["value1", "value2", "value3"]));
+    }
+
+    async fn action(input: Option<i32>) -> Option<String> {
+        let i = input?;
+        Some(format!("Processed input: {}", i))
+    }
+
+    enum Event {
+        Operation(Option<String>),
+        Received(i32),
+    }
+
+    /// 对应 select_try4.rs：`if !done` 的分支不放进这一轮，`operation` 跨轮次复用，收到偶数后重置
+    #[tokio::test]
+    async fn precondition() {
+        let (tx, mut rx) = mpsc::channel(128);
+
+        let mut done = false;
+        let operation = action(None);
+        tokio::pin!(operation);
+
+        tokio::spawn(async move {
+            let _ = tx.send(1).await;
+            let _ = tx.send(3).await;
+            let _ = tx.send(2).await;
+        });
+
+        let result = loop {
+            // 分支的类型各不相同，先装箱
+            let mut branches: Vec<BoxFuture<'_, Option<Event>>> = Vec::new();
+            if !done {
+                branches.push(async { Some(Event::Operation((&mut operation).await)) }.boxed());
+            }
+            branches.push(rx.recv().map(|v| v.map(Event::Received)).boxed());
+
+            match select_some(branches).await {
+                Some((_, Event::Operation(res))) => {
+                    done = true;
+                    if let Some(v) = res {
+                        break v;
+                    }
+                }
+                Some((_, Event::Received(v))) => {
+                    if v % 2 == 0 {
+                        // `.set` 是 `Pin` 上定义的方法
+                        operation.set(action(Some(v)));
+                        done = false;
+                    }
+                }
+                None => panic!("channel closed before the operation finished"),
+            }
+        };
+
+        assert_eq!(result, "Processed input: 2");
+    }
+
+    /// 同时就绪时 biased 总是选第一个，默认的随机顺序两个都会被选到
+    #[tokio::test]
+    async fn biased_and_random() {
+        for _ in 0..100 {
+            let (i, _) = select_all([future::ready(0), future::ready(1)])
+                .biased()
+                .await;
+            assert_eq!(i, 0);
+        }
+
+        let mut picked = [0; 2];
+        for _ in 0..100 {
+            let (i, v) = select_all([future::ready(0), future::ready(1)]).await;
+            assert_eq!(i, v);
+            picked[i] += 1;
+        }
+        assert!(picked[0] > 0 && picked[1] > 0);
+    }
+
+    /// 记录被丢弃的次数，检查没选中的 future 有没有被丢掉
+    struct Tracked {
+        ready: bool,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    impl Future for Tracked {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+            if self.ready {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// 有一个完成后，其余的 future 马上被丢弃，不用等 `Select` 本身被丢弃
+    #[tokio::test]
+    async fn losers_dropped() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let futures = (0..5).map(|i| Tracked {
+            ready: i == 3,
+            dropped: dropped.clone(),
+        });
+
+        let mut select = select_all(futures);
+        let (i, ()) = (&mut select).await;
+        assert_eq!(i, 3);
+        assert_eq!(dropped.load(Ordering::SeqCst), 5);
+        drop(select);
+        assert_eq!(dropped.load(Ordering::SeqCst), 5);
+    }
+}
@@ -0,0 +1,237 @@
+//! 客户端的订阅类型，实现了 `Stream`
+//!
+//! `mini_redis::client::Subscriber::into_stream` 会消耗掉订阅者，之后就不能再增减频道，
+//! 而且拿到的 `Message` 要自己再过滤一遍。`Subscription` 在后台任务里持有连接，
+//! 一边把服务端推过来的消息放进 `Mailbox`，一边处理 subscribe/unsubscribe 请求，
+//! 因此可以在消费 stream 的同时动态增减频道。
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{ToSocketAddrs, UnixStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
+use tokio_util::codec::Framed;
+
+use crate::codec::RespCodec;
+use crate::connect;
+use crate::frame::Frame;
+use crate::mailbox::{Mailbox, Policy, Recv};
+use crate::tls;
+
+/// 订阅 stream 中的一项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// 某个频道上发布的一条消息
+    Published { channel: String, payload: Bytes },
+    /// 有这么多条消息因为消费太慢被丢弃了，可能发生在本地缓冲，也可能发生在服务端
+    Lagged(u64),
+}
+
+pub struct Subscription {
+    mailbox: Arc<Mailbox<Message>>,
+    requests: mpsc::UnboundedSender<Frame>,
+    subscribed: Arc<Mutex<BTreeSet<String>>>,
+    task: JoinHandle<()>,
+}
+
+impl Subscription {
+    /// 连接到服务端并订阅 `channels`
+    ///
+    /// 本地最多缓冲 `capacity` 条还没被消费的消息，超出后按 `policy` 处理。
+    /// `addr` 解析出多个地址时（也可以直接传 `&[SocketAddr]`）同时去连，用最先连上的那个。
+    pub async fn connect<A: ToSocketAddrs>(
+        addr: A,
+        channels: &[String],
+        policy: Policy,
+        capacity: usize,
+    ) -> mini_redis::Result<Subscription> {
+        let (socket, _) = connect::connect(addr).await?;
+        Subscription::start(socket, channels, policy, capacity).await
+    }
+
+    /// 通过 Unix socket 连接到服务端并订阅 `channels`，其余和 [`Subscription::connect`] 一样
+    pub async fn connect_unix<P: AsRef<Path>>(
+        path: P,
+        channels: &[String],
+        policy: Policy,
+        capacity: usize,
+    ) -> mini_redis::Result<Subscription> {
+        let socket = UnixStream::connect(path).await?;
+        Subscription::start(socket, channels, policy, capacity).await
+    }
+
+    /// 通过 TLS 连接到服务端并订阅 `channels`
+    ///
+    /// 只信任 `ca` 里的 CA 证书，`server_name` 要和服务端证书里的名字一致，其余和
+    /// [`Subscription::connect`] 一样。
+    pub async fn connect_tls<A: ToSocketAddrs, P: AsRef<Path>>(
+        addr: A,
+        ca: P,
+        server_name: &str,
+        channels: &[String],
+        policy: Policy,
+        capacity: usize,
+    ) -> mini_redis::Result<Subscription> {
+        let connector = tls::connector(ca.as_ref())?;
+        let server_name = tls::server_name(server_name)?;
+        let (socket, _) = connect::connect(addr).await?;
+        let socket = connector.connect(server_name, socket).await?;
+        Subscription::start(socket, channels, policy, capacity).await
+    }
+
+    async fn start<S>(
+        socket: S,
+        channels: &[String],
+        policy: Policy,
+        capacity: usize,
+    ) -> mini_redis::Result<Subscription>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut connection = Framed::new(socket, RespCodec::new());
+        connection.send(command("subscribe", channels)).await?;
+
+        let mailbox = Arc::new(Mailbox::new(policy, capacity));
+        let subscribed = Arc::new(Mutex::new(BTreeSet::new()));
+        let (requests, rx) = mpsc::unbounded_channel();
+
+        let task = tokio::spawn(run(connection, rx, mailbox.clone(), subscribed.clone()));
+
+        Ok(Subscription {
+            mailbox,
+            requests,
+            subscribed,
+            task,
+        })
+    }
+
+    /// 追加订阅频道，可以在消费 stream 的过程中调用
+    pub fn subscribe(&self, channels: &[String]) -> mini_redis::Result<()> {
+        self.requests
+            .send(command("subscribe", channels))
+            .map_err(|_| "subscription closed".into())
+    }
+
+    /// 取消订阅频道，`channels` 为空表示取消全部
+    pub fn unsubscribe(&self, channels: &[String]) -> mini_redis::Result<()> {
+        self.requests
+            .send(command("unsubscribe", channels))
+            .map_err(|_| "subscription closed".into())
+    }
+
+    /// 服务端已经确认订阅的频道，按名字排序
+    pub fn subscribed(&self) -> Vec<String> {
+        self.subscribed.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        let item = match ready!(self.mailbox.poll_recv(cx)) {
+            Some(Recv::Item(msg)) => Some(msg),
+            Some(Recv::Lagged(n)) => Some(Message::Lagged(n)),
+            None => None,
+        };
+        Poll::Ready(item)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // 后台任务持有连接，stream 被丢弃后连接也就没有存在的必要了
+        self.task.abort();
+    }
+}
+
+/// 后台任务：读服务端推送的帧放进 `mailbox`，同时把 subscribe/unsubscribe 请求写给服务端
+async fn run<S: AsyncRead + AsyncWrite + Unpin>(
+    mut connection: Framed<S, RespCodec>,
+    mut requests: mpsc::UnboundedReceiver<Frame>,
+    mailbox: Arc<Mailbox<Message>>,
+    subscribed: Arc<Mutex<BTreeSet<String>>>,
+) {
+    loop {
+        // `Framed` 把读到的数据留在内部缓冲区，`next()` 被 `select!` 取消也不会丢数据
+        let frame = tokio::select! {
+            frame = connection.next() => frame,
+            Some(request) = requests.recv() => {
+                if connection.send(request).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let frame = match frame {
+            Some(Ok(frame)) => frame,
+            // 服务端关闭了连接（例如按 `disconnect` 策略把我们踢掉）或者读出错
+            None | Some(Err(_)) => break,
+        };
+
+        let message = match frame {
+            // RESP2 下推送的消息是普通数组，RESP3 下是 push
+            Frame::Array(parts) | Frame::Push(parts) => {
+                let kind = parts.first().and_then(text);
+                let channel = parts.get(1).and_then(text);
+                match (kind.as_deref(), channel, parts.get(2)) {
+                    (Some("message"), Some(channel), Some(Frame::Bulk(payload))) => {
+                        Message::Published {
+                            channel,
+                            payload: payload.clone(),
+                        }
+                    }
+                    (Some("lagged"), _, _) => match parts.get(1) {
+                        Some(Frame::Integer(n)) => Message::Lagged(*n as u64),
+                        _ => break,
+                    },
+                    (Some("subscribe"), Some(channel), _) => {
+                        // 重复订阅同一个频道时服务端也会确认，用集合去重
+                        subscribed.lock().unwrap().insert(channel);
+                        continue;
+                    }
+                    (Some("unsubscribe"), Some(channel), _) => {
+                        subscribed.lock().unwrap().remove(&channel);
+                        continue;
+                    }
+                    // 什么都没订阅时取消订阅，频道名是 nil
+                    (Some("unsubscribe"), None, _) => continue,
+                    _ => break,
+                }
+            }
+            // 服务端返回了错误，订阅没法继续
+            _ => break,
+        };
+
+        // `disconnect` 策略下缓冲区满了会关闭 mailbox，此时主动断开连接
+        if mailbox.send(message).await.is_err() {
+            break;
+        }
+    }
+
+    mailbox.close();
+}
+
+fn command(name: &'static str, channels: &[String]) -> Frame {
+    let mut parts = vec![Frame::Bulk(Bytes::from_static(name.as_bytes()))];
+    parts.extend(channels.iter().map(|c| Frame::Bulk(Bytes::from(c.clone()))));
+    Frame::Array(parts)
+}
+
+/// 取出 bulk string 或 simple string 的文本内容
+fn text(frame: &Frame) -> Option<String> {
+    match frame {
+        Frame::Simple(s) => Some(s.clone()),
+        Frame::Bulk(data) => Some(String::from_utf8_lossy(data).into_owned()),
+        _ => None,
+    }
+}
@@ -0,0 +1,70 @@
+//! TLS 的服务端和客户端配置
+//!
+//! 服务端用 PEM 格式的证书链和私钥终止 TLS，把握手之后的流交给原来处理 `TcpStream` 的代码；
+//! 客户端只信任指定的 CA 证书，适合自己签发的证书，不读取系统的根证书。
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+pub use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// 用 `cert`（证书链，服务端证书在前）和 `key`（私钥）创建服务端的 TLS 配置
+pub fn acceptor(cert: &Path, key: &Path) -> io::Result<TlsAcceptor> {
+    let certs = load_certs(cert)?;
+    let key = rustls_pemfile::private_key(&mut open(key)?)?
+        .ok_or_else(|| invalid(key, "no private key found"))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(io::Error::other)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// 只信任 `ca` 里的 CA 证书的客户端配置
+pub fn connector(ca: &Path) -> io::Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca)? {
+        roots.add(cert).map_err(io::Error::other)?;
+    }
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// 校验服务端证书时用的名字，域名或者 IP 地址
+pub fn server_name(host: &str) -> io::Result<ServerName<'static>> {
+    ServerName::try_from(host.to_string()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid server name `{}`", host),
+        )
+    })
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(invalid(path, "no certificates found"));
+    }
+    Ok(certs)
+}
+
+fn open(path: &Path) -> io::Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+fn invalid(path: &Path, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), msg),
+    )
+}
@@ -0,0 +1,197 @@
+//! TLS 的端到端测试
+//!
+//! 运行时用 rcgen 生成一个 CA 和它签发的 `localhost` 证书，带上 `--tls-cert` / `--tls-key`
+//! 启动 `server` 和 `echo-server`，再用只信任这个 CA 的客户端连上去收发数据。
+
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::SinkExt;
+use my_redis_project::codec::RespCodec;
+use my_redis_project::frame::Frame;
+use my_redis_project::mailbox::Policy;
+use my_redis_project::subscription::{Message, Subscription};
+use my_redis_project::tls;
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+/// 一组证书文件，drop 时删掉所在的临时目录
+struct Certs {
+    dir: PathBuf,
+}
+
+/// 子进程，drop 时杀掉
+struct Process(Child);
+
+impl Certs {
+    fn generate(name: &str) -> Certs {
+        let dir =
+            std::env::temp_dir().join(format!("my-redis-tls-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+
+        fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+        fs::write(dir.join("server.pem"), cert.pem()).unwrap();
+        fs::write(dir.join("server.key"), key.serialize_pem()).unwrap();
+        Certs { dir }
+    }
+
+    fn path(&self, file: &str) -> String {
+        self.dir.join(file).display().to_string()
+    }
+}
+
+impl Drop for Certs {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Process {
+    /// 监听随机端口启动，从第一行输出 `Listening on <地址> ...` 里取出实际的地址
+    fn start(bin: &str, certs: &Certs) -> (Process, String) {
+        let mut child = Command::new(bin)
+            .args(["--addr", "127.0.0.1:0"])
+            .args(["--tls-cert", &certs.path("server.pem")])
+            .args(["--tls-key", &certs.path("server.key")])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        // 后面的输出也要读走，管道关了的话子进程再打印就会出错退出
+        std::thread::spawn(move || io::copy(&mut stdout, &mut io::sink()));
+        let addr = line
+            .strip_prefix("Listening on ")
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap_or_else(|| panic!("unexpected output: {:?}", line))
+            .to_string();
+        (Process(child), addr)
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[tokio::test]
+async fn echo_over_tls() {
+    let certs = Certs::generate("echo");
+    let (_echo, addr) = Process::start(env!("CARGO_BIN_EXE_echo-server"), &certs);
+
+    let connector = tls::connector(certs.path("ca.pem").as_ref()).unwrap();
+    let socket = TcpStream::connect(&addr).await.unwrap();
+    let mut socket = connector
+        .connect(tls::server_name("localhost").unwrap(), socket)
+        .await
+        .unwrap();
+
+    socket.write_all(b"hello over tls\r\n").await.unwrap();
+    socket.shutdown().await.unwrap();
+    let mut echoed = Vec::new();
+    socket.read_to_end(&mut echoed).await.unwrap();
+    assert_eq!(echoed, b"hello over tls\r\n");
+}
+
+#[tokio::test]
+async fn ping_over_tls() {
+    let certs = Certs::generate("server");
+    let (_server, addr) = Process::start(env!("CARGO_BIN_EXE_server"), &certs);
+
+    let connector = tls::connector(certs.path("ca.pem").as_ref()).unwrap();
+    let socket = TcpStream::connect(&addr).await.unwrap();
+    let mut socket = connector
+        .connect(tls::server_name("localhost").unwrap(), socket)
+        .await
+        .unwrap();
+
+    socket.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+    let mut reply = [0; 7];
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+}
+
+#[tokio::test]
+async fn subscribe_and_publish_over_tls() {
+    let certs = Certs::generate("pubsub");
+    let (_server, addr) = Process::start(env!("CARGO_BIN_EXE_server"), &certs);
+
+    let channels = ["news".to_string()];
+    let mut subscription = Subscription::connect_tls(
+        &addr,
+        certs.path("ca.pem"),
+        "localhost",
+        &channels,
+        Policy::DropOldest,
+        16,
+    )
+    .await
+    .unwrap();
+    // 等服务端确认订阅之后再发布，否则消息可能在订阅之前就发出去了
+    while subscription.subscribed().is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let connector = tls::connector(certs.path("ca.pem").as_ref()).unwrap();
+    let socket = TcpStream::connect(&addr).await.unwrap();
+    let socket = connector
+        .connect(tls::server_name("localhost").unwrap(), socket)
+        .await
+        .unwrap();
+    let mut publisher = Framed::new(socket, RespCodec::new());
+    let parts = ["publish", "news", "hello over tls"];
+    let frame = Frame::Array(parts.iter().map(|s| Frame::Bulk(Bytes::from(*s))).collect());
+    publisher.send(frame).await.unwrap();
+    // PUBLISH 返回收到消息的订阅者数量
+    let reply = publisher.next().await.unwrap().unwrap();
+    assert_eq!(reply, Frame::Integer(1));
+
+    let message = tokio::time::timeout(Duration::from_secs(5), subscription.next())
+        .await
+        .unwrap();
+    assert_eq!(
+        message,
+        Some(Message::Published {
+            channel: "news".to_string(),
+            payload: Bytes::from("hello over tls"),
+        })
+    );
+}
+
+#[tokio::test]
+async fn untrusted_certificate_is_rejected() {
+    let certs = Certs::generate("untrusted");
+    let other = Certs::generate("other-ca");
+    let (_echo, addr) = Process::start(env!("CARGO_BIN_EXE_echo-server"), &certs);
+
+    // 服务端的证书不是这个 CA 签发的，握手必须失败
+    let connector = tls::connector(other.path("ca.pem").as_ref()).unwrap();
+    let socket = TcpStream::connect(&addr).await.unwrap();
+    let res = connector
+        .connect(tls::server_name("localhost").unwrap(), socket)
+        .await;
+    assert!(res.is_err());
+}